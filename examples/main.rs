@@ -52,22 +52,20 @@ fn main() {
         repl();
     } else {
         if matches.is_present("compile") {
-            todo!();
-        //let src = PathBuf::from(matches.value_of("src").unwrap());
-        //let out = PathBuf::from(matches.value_of("compile").unwrap());
-        //if let Err(errors) = piccolo::compile(&src, &out) {
-        //    print_errors(errors);
-        //}
+            let src = PathBuf::from(matches.value_of("src").unwrap());
+            let out = PathBuf::from(matches.value_of("compile").unwrap());
+            if let Err(errors) = piccolo::compile(&src, &out) {
+                print_errors(errors, std::fs::read_to_string(&src).ok().as_deref());
+            }
         } else if matches.is_present("bin") {
-            todo!();
-        //let src = PathBuf::from(matches.value_of("bin").unwrap());
-        //if let Err(errors) = piccolo::run_bin(&src) {
-        //    print_errors(errors);
-        //}
+            let src = PathBuf::from(matches.value_of("bin").unwrap());
+            if let Err(errors) = piccolo::run_bin(&src) {
+                print_errors(errors, None);
+            }
         } else if matches.is_present("string") {
             let src = matches.value_of("string").unwrap();
-            if let Err(errors) = piccolo::interpret(&src) {
-                print_errors(errors);
+            if let Err(errors) = piccolo::interpret(src) {
+                print_errors(errors, Some(src));
             }
         } else {
             let src = PathBuf::from(matches.value_of("src").unwrap());
@@ -76,20 +74,26 @@ fn main() {
     }
 }
 
-fn print_errors(errors: Vec<PiccoloError>) {
+// show the source of each error if we have it
+fn print_errors(errors: Vec<PiccoloError>, source: Option<&str>) {
+    let render = |e: &PiccoloError| match source {
+        Some(source) => e.render(source),
+        None => format!("{}\n", e),
+    };
+
     if errors.len() == 1 {
-        println!("Error {}", errors[0])
+        print!("Error {}", render(&errors[0]))
     } else {
         println!("{} Errors:", errors.len());
         for e in errors.iter() {
-            println!("    {}", e);
+            print!("    {}", render(e));
         }
     }
 }
 
 fn file(path: &Path) {
     if let Err(errors) = piccolo::do_file(path) {
-        print_errors(errors);
+        print_errors(errors, std::fs::read_to_string(path).ok().as_deref());
     }
 }
 
@@ -99,19 +103,20 @@ fn repl() {
         .or_else(|_| std::fs::File::create(".piccolo_history").map(|_| ()))
         .unwrap();
 
+    let mut session = Session::new();
     loop {
         match rl.readline("-- ") {
             Ok(line) => {
                 rl.add_history_entry(&line);
                 rl.save_history(".piccolo_history").unwrap();
 
-                match piccolo::interpret(&line) {
+                match session.eval(&line) {
                     Ok(v) => {
                         if v != Constant::Nil {
                             println!("{:?}", v);
                         }
                     }
-                    Err(errors) => print_errors(errors),
+                    Err(errors) => print_errors(errors, Some(&line)),
                 }
             }
 
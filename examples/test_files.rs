@@ -18,17 +18,21 @@ fn main() -> Result<(), PiccoloError> {
     let mut test_errors = Vec::new();
     for item in files.iter() {
         let name = item.display().to_string();
-        if name.ends_with("ignore") {
+        // integers that overflow or are shifted too far become big integers instead
+        // of failing
+        if name.ends_with("ignore")
+            || (cfg!(feature = "bigint") && name.ends_with("_overflow_fail.pc"))
+        {
             ignored += 1;
         } else if !name.ends_with("_fail.pc") {
             println!(" -- '{}'", name);
-            let _ = piccolo::do_file(&item).map_err(|errors| {
+            let _ = piccolo::do_file(item).map_err(|errors| {
                 test_errors.push(errors);
             });
         } else {
             println!(" xx '{}'", name);
-            let _ = piccolo::do_file(&item).map(|v| {
-                test_errors.push(vec![PiccoloError::new(ErrorKind::AssertFailed)
+            let _ = piccolo::do_file(item).map(|v| {
+                test_errors.push(vec![PiccoloError::new(RuntimeError::AssertFailed)
                     .file(name)
                     .msg_string(format!("resulted in {}", v))])
             });
@@ -6,7 +6,7 @@ pub mod parser;
 pub mod scanner;
 
 use crate::runtime::{Line, LocalScopeDepth};
-use crate::{ErrorKind, PiccoloError};
+use crate::{PiccoloError, ScanError};
 
 use core::fmt;
 
@@ -14,11 +14,16 @@ use core::fmt;
 pub(crate) struct Local {
     pub(crate) name: String,
     pub(crate) depth: LocalScopeDepth,
+    pub(crate) captured: bool,
 }
 
 impl Local {
     pub(crate) fn new(name: String, depth: LocalScopeDepth) -> Self {
-        Self { name, depth }
+        Self {
+            name,
+            depth,
+            captured: false,
+        }
     }
 }
 
@@ -45,7 +50,7 @@ pub(crate) fn escape_string(t: &Token) -> Result<String, PiccoloError> {
             let mut line = line_start;
 
             let mut i = 1;
-            while i < s.as_bytes().len() - 1 {
+            while i < s.len() - 1 {
                 let byte = s.as_bytes()[i];
                 if byte == b'\n' {
                     line += 1;
@@ -71,18 +76,18 @@ pub(crate) fn escape_string(t: &Token) -> Result<String, PiccoloError> {
                             value.push(b'\t');
                         }
                         b'\r' | b'\n' => {
-                            while i < s.as_bytes().len() - 1
-                                && scanner::is_whitespace(s.as_bytes()[i])
-                            {
+                            while i < s.len() - 1 && scanner::is_whitespace(s.as_bytes()[i]) {
                                 i += 1;
                             }
                             i -= 1;
                         }
                         c => {
-                            return Err(PiccoloError::new(ErrorKind::UnknownFormatCode {
+                            let start = t.span.start + i - 1;
+                            return Err(PiccoloError::new(ScanError::UnknownFormatCode {
                                 code: c as char,
                             })
-                            .line(line));
+                            .line(line)
+                            .span(Span::new(start, start + 2)));
                         }
                     }
                 } else {
@@ -191,10 +196,38 @@ pub enum TokenKind {
     False,
     Double(f64),
     Integer(i64),
+    // an integer literal too large for an i64, with its digits in the lexeme
+    #[cfg(feature = "bigint")]
+    BigInteger,
+
+    // stands in for something the scanner couldn't make sense of, after recording
+    // an error for it
+    Error,
 
     Eof,
 }
 
+/// A range of bytes in source code, from `start` up to but not including `end`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Make a span covering both `self` and `other`, and everything between them.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
 /// Represents a token in source code.
 ///
 /// Maintains a reference to the original source.
@@ -203,24 +236,37 @@ pub struct Token<'a> {
     pub(crate) kind: TokenKind,
     pub(crate) lexeme: &'a str,
     pub(crate) line: Line,
+    pub(crate) span: Span,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(kind: TokenKind, lexeme: &'a str, line: Line) -> Self {
-        Token { kind, lexeme, line }
+    pub fn new(kind: TokenKind, lexeme: &'a str, line: Line, span: Span) -> Self {
+        Token {
+            kind,
+            lexeme,
+            line,
+            span,
+        }
+    }
+
+    /// Where the token is in the source.
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     /// Whether or not the token is a value literal.
     pub fn is_value(&self) -> bool {
-        matches!(
-            self.kind,
+        match self.kind {
             TokenKind::Nil
-                | TokenKind::String
-                | TokenKind::True
-                | TokenKind::False
-                | TokenKind::Double(_)
-                | TokenKind::Integer(_)
-        )
+            | TokenKind::String
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Double(_)
+            | TokenKind::Integer(_) => true,
+            #[cfg(feature = "bigint")]
+            TokenKind::BigInteger => true,
+            _ => false,
+        }
     }
 
     pub fn is_assign(&self) -> bool {
@@ -265,6 +311,8 @@ impl<'a> fmt::Display for Token<'a> {
             TokenKind::String => write!(f, "{}", self.lexeme),
             TokenKind::Double(v) => write!(f, "{}", v),
             TokenKind::Integer(v) => write!(f, "{}", v),
+            #[cfg(feature = "bigint")]
+            TokenKind::BigInteger => write!(f, "{}", self.lexeme),
             v => write!(f, "{:?}", v),
         }
     }
@@ -371,6 +419,9 @@ impl fmt::Display for TokenKind {
             TokenKind::False => write!(f, "false"),
             TokenKind::Double(v) => write!(f, "{}", v),
             TokenKind::Integer(v) => write!(f, "{}", v),
+            #[cfg(feature = "bigint")]
+            TokenKind::BigInteger => write!(f, "bigint"),
+            TokenKind::Error => write!(f, "<error>"),
             TokenKind::Eof => write!(f, ""),
         }
     }
@@ -10,7 +10,7 @@
 
 // https://github.com/Darksecond/lox/blob/master/lox-compiler/src/bettercompiler/statements.rs
 
-use crate::{Token, TokenKind};
+use crate::{Span, Token, TokenKind};
 
 /// Simple type alias for the abstract syntax tree.
 pub type Ast<'a> = [Stmt<'a>];
@@ -24,6 +24,7 @@ pub enum Expr<'a> {
         literal: Token<'a>,
     },
     Paren {
+        left_paren: Token<'a>,
         right_paren: Token<'a>,
         expr: Box<Expr<'a>>,
     },
@@ -56,6 +57,7 @@ pub enum Expr<'a> {
     New {
         name: Token<'a>,
         args: Vec<(Token<'a>, Box<Expr<'a>>)>,
+        right_paren: Token<'a>,
     },
     Get {
         object: Box<Expr<'a>>,
@@ -71,6 +73,24 @@ pub enum Expr<'a> {
         object: Box<Expr<'a>>,
         idx: Box<Expr<'a>>,
     },
+    SetIndex {
+        right_bracket: Token<'a>,
+        object: Box<Expr<'a>>,
+        idx: Box<Expr<'a>>,
+        value: Box<Expr<'a>>,
+    },
+    List {
+        left_bracket: Token<'a>,
+        values: Vec<Expr<'a>>,
+        right_bracket: Token<'a>,
+    },
+    // a key written as a bare identifier is a string, and is kept as a literal of
+    // the identifier token
+    Map {
+        left_brace: Token<'a>,
+        entries: Vec<(Expr<'a>, Expr<'a>)>,
+        right_brace: Token<'a>,
+    },
     Fn {
         name: Token<'a>,
         args: Vec<Token<'a>>,
@@ -126,12 +146,20 @@ pub enum Stmt<'a> {
         body: Vec<Stmt<'a>>,
         end: Token<'a>,
     },
+    ForIn {
+        for_: Token<'a>,
+        name: Token<'a>,
+        iterable: Expr<'a>,
+        body: Vec<Stmt<'a>>,
+        end: Token<'a>,
+    },
     Fn {
         name: Token<'a>,
         args: Vec<Token<'a>>,
         arity: usize,
         body: Vec<Stmt<'a>>,
         method: bool,
+        end: Token<'a>,
     },
     Break {
         break_: Token<'a>,
@@ -156,6 +184,96 @@ pub enum Stmt<'a> {
     },
 }
 
+impl<'a> Expr<'a> {
+    /// The part of the source the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { literal } => literal.span,
+            Expr::Paren {
+                left_paren,
+                right_paren,
+                ..
+            } => left_paren.span.to(right_paren.span),
+            Expr::Path { names } => names[0].span.to(names[names.len() - 1].span),
+            Expr::Variable { variable } => variable.span,
+            Expr::Unary { op, rhs } => op.span.to(rhs.span()),
+            Expr::Binary { lhs, rhs, .. } | Expr::Logical { lhs, rhs, .. } => {
+                lhs.span().to(rhs.span())
+            }
+            Expr::Call { callee, paren, .. } => callee.span().to(paren.span),
+            Expr::New {
+                name, right_paren, ..
+            } => name.span.to(right_paren.span),
+            Expr::Get { object, name } => object.span().to(name.span),
+            Expr::Set { object, value, .. } => object.span().to(value.span()),
+            Expr::Index {
+                object,
+                right_bracket,
+                ..
+            } => object.span().to(right_bracket.span),
+            Expr::SetIndex { object, value, .. } => object.span().to(value.span()),
+            Expr::List {
+                left_bracket,
+                right_bracket,
+                ..
+            } => left_bracket.span.to(right_bracket.span),
+            Expr::Map {
+                left_brace,
+                right_brace,
+                ..
+            } => left_brace.span.to(right_brace.span),
+            Expr::Fn { name, body, .. } => match body.last() {
+                Some(stmt) => name.span.to(stmt.span()),
+                None => name.span,
+            },
+        }
+    }
+}
+
+impl<'a> Stmt<'a> {
+    /// The part of the source the statement was parsed from.
+    ///
+    /// Statements don't keep every token they were parsed from, so this may leave
+    /// out leading keywords like `fn` and `data`.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr { expr, .. } => expr.span(),
+            Stmt::Block { end, body } => match body.first() {
+                Some(stmt) => stmt.span().to(end.span),
+                None => end.span,
+            },
+            Stmt::Assignment { name, value, .. } | Stmt::Declaration { name, value, .. } => {
+                name.span.to(value.span())
+            }
+            Stmt::If { if_, end, .. } => if_.span.to(end.span),
+            Stmt::While { while_, end, .. } => while_.span.to(end.span),
+            Stmt::For { for_, end, .. } | Stmt::ForIn { for_, end, .. } => for_.span.to(end.span),
+            Stmt::Fn { name, end, .. } => name.span.to(end.span),
+            Stmt::Break { break_ } => break_.span,
+            Stmt::Continue { continue_ } => continue_.span,
+            Stmt::Retn { retn, value } => match value {
+                Some(value) => retn.span.to(value.span()),
+                None => retn.span,
+            },
+            Stmt::Assert { assert, value } => assert.span.to(value.span()),
+            Stmt::Data {
+                name,
+                methods,
+                fields,
+            } => {
+                let mut span = name.span;
+                if let Some((_, value)) = fields.last() {
+                    span = span.to(value.span());
+                }
+                if let Some(method) = methods.last() {
+                    span = span.to(method.span());
+                }
+                span
+            }
+        }
+    }
+}
+
 /// Print an abstract syntax tree.
 pub fn print_ast(ast: &Ast) -> String {
     let mut s = String::new();
@@ -187,9 +305,9 @@ fn parenthesize_list(indent: usize, name: &str, expr: Option<&Expr>, stmts: &[St
 
 fn parenthesize_lists(indent: usize, name: &str, expr: Option<&Expr>, stmts: &[&[Stmt]]) -> String {
     let mut s = format!("({}", name);
-    if expr.is_some() {
+    if let Some(expr) = expr {
         s.push(' ');
-        s.push_str(&print_expr(indent + 1, &expr.as_ref().unwrap()));
+        s.push_str(&print_expr(indent + 1, expr));
     }
     for stmt_list in stmts.iter() {
         for stmt in stmt_list.iter() {
@@ -221,6 +339,8 @@ fn print_stmt(indent: usize, stmt: &Stmt) -> String {
             => print_while(indent, cond, body),
         Stmt::For { init, cond, inc, body, .. }
             => print_for(indent, init.as_ref(), cond, inc.as_ref(), body),
+        Stmt::ForIn { name, iterable, body, .. }
+            => print_for_in(indent, name, iterable, body),
         Stmt::Fn { name, args, body, .. }
             => print_fn(indent, name, args, body),
         Stmt::Break { .. }
@@ -255,7 +375,7 @@ fn print_expr(indent: usize, expr: &Expr) -> String {
             => print_logical(indent, lhs, op, rhs),
         Expr::Call { callee, args, .. }
             => print_call(indent, callee, args),
-        Expr::New { name, args }
+        Expr::New { name, args, .. }
             => print_new(indent, name, args),
         Expr::Get { object, name }
             => print_get(indent, object, name),
@@ -263,6 +383,12 @@ fn print_expr(indent: usize, expr: &Expr) -> String {
             => print_set(indent, object, name, value),
         Expr::Index { object, idx, .. }
             => print_index(indent, object, idx),
+        Expr::SetIndex { object, idx, value, .. }
+            => print_set_index(indent, object, idx, value),
+        Expr::List { values, .. }
+            => print_list(indent, values),
+        Expr::Map { entries, .. }
+            => print_map(indent, entries),
         Expr::Fn { name, args, body, .. }
             => print_fn(indent, name, args, body),
     }
@@ -311,6 +437,11 @@ fn print_for(indent: usize, init: &Stmt, cond: &Expr, inc: &Stmt, body: &[Stmt])
     parenthesize_list(indent, &s, None, body)
 }
 
+fn print_for_in(indent: usize, name: &Token, iterable: &Expr, body: &[Stmt]) -> String {
+    let s = format!("for {} in {}", name.lexeme, print_expr(indent, iterable));
+    parenthesize_list(indent, &s, None, body)
+}
+
 fn print_fn(indent: usize, name: &Token, args: &[Token], body: &[Stmt]) -> String {
     let mut s = format!("fn {} (", name.lexeme);
     for (n, arg) in args.iter().enumerate() {
@@ -337,7 +468,7 @@ fn print_retn(indent: usize, retn: &Token, expr: Option<&Expr>) -> String {
         indent,
         "retn",
         &[expr.unwrap_or(&Expr::Literal {
-            literal: Token::new(TokenKind::Nil, "nil", retn.line),
+            literal: Token::new(TokenKind::Nil, "nil", retn.line, retn.span),
         })],
     )
 }
@@ -346,14 +477,24 @@ fn print_assert(indent: usize, value: &Expr) -> String {
     parenthesize(indent, "assert", &[value])
 }
 
-fn print_data(
-    indent: usize,
-    _name: &Token,
-    _methods: &[Stmt],
-    _fields: &[(Token, Expr)],
-) -> String {
-    // TODO
-    parenthesize(indent, "data", &[])
+fn print_data(indent: usize, name: &Token, methods: &[Stmt], fields: &[(Token, Expr)]) -> String {
+    let mut s = format!("(data {}", name.lexeme);
+    for (field, value) in fields.iter() {
+        s.push('\n');
+        for _ in 0..indent + 1 {
+            s.push_str("  ");
+        }
+        s.push_str(&print_declaration(indent + 1, field, value));
+    }
+    for method in methods.iter() {
+        s.push('\n');
+        for _ in 0..indent + 1 {
+            s.push_str("  ");
+        }
+        s.push_str(&print_stmt(indent + 1, method));
+    }
+    s.push(')');
+    s
 }
 
 fn print_literal(literal: &Token) -> String {
@@ -415,3 +556,25 @@ fn print_index(indent: usize, object: &Expr, idx: &Expr) -> String {
     let s = format!("index {}", print_expr(indent, idx));
     parenthesize(indent, &s, &[object])
 }
+
+fn print_set_index(indent: usize, object: &Expr, idx: &Expr, value: &Expr) -> String {
+    let s = format!(
+        "set index {} = {}",
+        print_expr(indent, idx),
+        print_expr(indent, value)
+    );
+    parenthesize(indent, &s, &[object])
+}
+
+fn print_list(indent: usize, values: &[Expr]) -> String {
+    let values: Vec<&Expr> = values.iter().collect();
+    parenthesize(indent, "list", &values)
+}
+
+fn print_map(indent: usize, entries: &[(Expr, Expr)]) -> String {
+    let values: Vec<&Expr> = entries
+        .iter()
+        .flat_map(|(key, value)| vec![key, value])
+        .collect();
+    parenthesize(indent, "map", &values)
+}
@@ -3,13 +3,17 @@
 use super::ast::{Ast, Expr, Stmt};
 use super::Local;
 use crate::compiler::{Token, TokenKind};
-use crate::error::{ErrorKind, PiccoloError};
+use crate::error::{CompileError, PiccoloError};
 use crate::runtime::{
-    chunk::Chunk, object::Function, op::Opcode, value::Constant, ChunkOffset, ConstantIdx, Line,
-    LocalScopeDepth, LocalSlotIdx,
+    builtin::BUILTINS,
+    chunk::Chunk,
+    object::{Capture, Function},
+    op::Opcode,
+    value::Constant,
+    ChunkOffset, ConstantIdx, Line, LocalScopeDepth, LocalSlotIdx,
 };
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 /// Compile an abstract syntax tree into bytecode.
 pub fn compile_ast(emitter: &mut Emitter, ast: &Ast) -> Result<(), Vec<PiccoloError>> {
@@ -43,8 +47,10 @@ fn compile_stmt(emitter: &mut Emitter, stmt: &Stmt) -> Result<(), PiccoloError>
             => compile_while(emitter, while_, cond, body, end),
         Stmt::For { for_, init, cond, inc, body, end }
             => compile_for(emitter, for_, init.as_ref(), cond, inc.as_ref(), body, end),
-        // Stmt::Fn { name, args, arity, body, method }
-        //     => compile_func(emitter, name, args, *arity, body, *method),
+        Stmt::ForIn { for_, name, iterable, body, end }
+            => compile_for_in(emitter, for_, name, iterable, body, end),
+        Stmt::Fn { name, args, arity, body, end, .. }
+            => compile_fn(emitter, name, args, *arity, body, end),
         Stmt::Break { break_ }
             => compile_break(emitter, break_),
         Stmt::Continue { continue_ }
@@ -53,9 +59,8 @@ fn compile_stmt(emitter: &mut Emitter, stmt: &Stmt) -> Result<(), PiccoloError>
             => compile_retn(emitter, retn, value.as_ref()),
         Stmt::Assert { assert, value }
             => compile_assert(emitter, assert, value),
-        // Stmt::Data { name, methods, fields }
-        //     => compile_data(emitter, name, methods, fields),
-        _ => todo!("{:?}", stmt),
+        Stmt::Data { name, methods, fields }
+            => compile_data(emitter, name, methods, fields),
     }
 }
 
@@ -64,8 +69,8 @@ fn compile_expr(emitter: &mut Emitter, expr: &Expr) -> Result<(), PiccoloError>
     match expr {
         Expr::Literal { literal }
             => compile_literal(emitter, literal),
-        Expr::Paren { right_paren, expr }
-            => compile_paren(emitter, right_paren, expr),
+        Expr::Paren { left_paren, expr, .. }
+            => compile_paren(emitter, left_paren, expr),
         Expr::Variable { variable }
             => compile_variable(emitter, variable),
         Expr::Unary { op, rhs }
@@ -74,16 +79,22 @@ fn compile_expr(emitter: &mut Emitter, expr: &Expr) -> Result<(), PiccoloError>
             => compile_binary(emitter, lhs, op, rhs),
         Expr::Logical { lhs, op, rhs }
             => compile_logical(emitter, lhs, op, rhs),
-        // Expr::Call { callee, paren, arity, args }
-        //     => compile_call(emitter, callee, paren, *arity, args),
-        // Expr::New { name, args }
-        //     => compile_new(emitter, name, args),
-        // Expr::Get { object, name }
-        //     => compile_get(emitter, object, name),
-        // Expr::Set { object, name, value }
-        //     => compile_set(emitter, object, name, value),
-        // Expr::Index { right_bracket, object, idx }
-        //     => compile_index(emitter, right_bracket, object, idx),
+        Expr::Call { callee, paren, arity, args }
+            => compile_call(emitter, callee, paren, *arity, args),
+        Expr::New { name, args, .. }
+            => compile_new(emitter, name, args),
+        Expr::Get { object, name }
+            => compile_get(emitter, object, name),
+        Expr::Set { object, name, value }
+            => compile_set(emitter, object, name, value),
+        Expr::Index { right_bracket, object, idx }
+            => compile_index(emitter, right_bracket, object, idx),
+        Expr::SetIndex { right_bracket, object, idx, value }
+            => compile_set_index(emitter, right_bracket, object, idx, value),
+        Expr::List { left_bracket, values, .. }
+            => compile_list(emitter, left_bracket, values),
+        Expr::Map { left_brace, entries, .. }
+            => compile_map(emitter, left_brace, entries),
         // Expr::Fn { name, args, arity, body, method }
         //     => compile_func(emitter, name, args, *arity, body, *method),
         _ => todo!("{:?}", expr),
@@ -130,14 +141,11 @@ fn compile_assignment(
 ) -> Result<(), PiccoloError> {
     trace!("{} assign {}", name.line, name.lexeme);
 
+    let variable = emitter.resolve_variable(name)?;
+
     if let Some(opcode) = op.assign_by_mutate_op() {
         // if this is an assignment-by-mutation operator, first get the value of the variable
-        if let Some(idx) = emitter.get_local_slot(name) {
-            emitter.add_instruction_arg(Opcode::GetLocal, idx, op.line);
-        } else {
-            let idx = emitter.get_global_ident(name)?;
-            emitter.add_instruction_arg(Opcode::GetGlobal, idx, op.line);
-        }
+        emitter.add_instruction_arg(variable.get_op(), variable.arg(), op.line);
 
         // calculate the value to mutate with
         compile_expr(emitter, value)?;
@@ -150,17 +158,7 @@ fn compile_assignment(
     }
 
     // then assign
-    if emitter.is_local() {
-        if let Some(idx) = emitter.get_local_slot(name) {
-            emitter.add_instruction_arg(Opcode::SetLocal, idx, op.line);
-        } else {
-            let idx = emitter.get_global_ident(name)?;
-            emitter.add_instruction_arg(Opcode::SetGlobal, idx, op.line);
-        }
-    } else {
-        let idx = emitter.get_global_ident(name)?;
-        emitter.add_instruction_arg(Opcode::SetGlobal, idx, op.line);
-    }
+    emitter.add_instruction_arg(variable.set_op(), variable.arg(), op.line);
 
     Ok(())
 }
@@ -300,9 +298,183 @@ fn compile_for(
     Ok(())
 }
 
+fn compile_for_in(
+    emitter: &mut Emitter,
+    for_: &Token,
+    name: &Token,
+    iterable: &Expr,
+    body: &[Stmt],
+    end: &Token,
+) -> Result<(), PiccoloError> {
+    trace!("{} for {} in", for_.line, name.lexeme);
+
+    // the iterable and the state of the iteration live in hidden locals, the state
+    // starting out as nil
+    emitter.begin_scope();
+    compile_expr(emitter, iterable)?;
+    let iterable = emitter.make_hidden_local();
+    emitter.add_instruction(Opcode::Nil, for_.line);
+    let state = emitter.make_hidden_local();
+
+    // advance the state, and jump to the end once it's false-y
+    let loop_start = emitter.start_loop_jumps();
+    emitter.add_instruction_arg(Opcode::GetLocal, iterable, for_.line);
+    emitter.add_instruction_arg(Opcode::GetLocal, state, for_.line);
+    emitter.add_instruction(Opcode::Iterate, for_.line);
+    emitter.add_instruction_arg(Opcode::SetLocal, state, for_.line);
+    emitter.add_instruction_arg(Opcode::GetLocal, state, for_.line);
+    let exit_jump = emitter.start_jump(Opcode::JumpFalse, for_.line);
+    emitter.add_instruction(Opcode::Pop, for_.line);
+
+    // the loop variable gets a new scope every time around, so closures capture
+    // the item from their own iteration
+    emitter.begin_scope();
+    emitter.add_instruction_arg(Opcode::GetLocal, iterable, for_.line);
+    emitter.add_instruction_arg(Opcode::GetLocal, state, for_.line);
+    emitter.add_instruction(Opcode::IterItem, for_.line);
+    emitter.make_variable(name)?;
+    compile_block(emitter, end, body)?;
+    emitter.end_scope(end.line);
+
+    // here after the loop variable is popped if we encounter a continue
+    emitter.patch_continue_jumps();
+    emitter.add_jump_back(loop_start, end.line);
+
+    // pop the state after false
+    emitter.patch_jump(exit_jump);
+    emitter.add_instruction(Opcode::Pop, end.line);
+
+    // here if we encounter a break
+    emitter.patch_break_jumps();
+
+    // pop the hidden locals
+    emitter.end_scope(end.line);
+
+    Ok(())
+}
+
+fn compile_fn(
+    emitter: &mut Emitter,
+    name: &Token,
+    args: &[Token],
+    arity: usize,
+    body: &[Stmt],
+    end: &Token,
+) -> Result<(), PiccoloError> {
+    trace!("{} fn {}", name.line, name.lexeme);
+
+    if emitter.is_local() {
+        // the function value will end up in the slot of the new local
+        emitter.make_variable(name)?;
+        compile_function(emitter, name, args, arity, body, end, false)?;
+    } else {
+        // declare the global before compiling the body so the function can call itself
+        let idx = emitter.make_global_ident(name);
+        compile_function(emitter, name, args, arity, body, end, false)?;
+        emitter.add_instruction_arg(Opcode::DeclareGlobal, idx, name.line);
+    }
+
+    Ok(())
+}
+
+// compile the body of a function into its own chunk, and add it as a constant
+// to the enclosing chunk
+fn compile_function(
+    emitter: &mut Emitter,
+    name: &Token,
+    args: &[Token],
+    arity: usize,
+    body: &[Stmt],
+    end: &Token,
+    method: bool,
+) -> Result<(), PiccoloError> {
+    emitter.begin_function(name.lexeme, arity, method);
+
+    let result = compile_function_body(emitter, args, body);
+
+    // implicitly return nil at the end of the function
+    emitter.add_instruction(Opcode::Nil, end.line);
+    emitter.add_instruction(Opcode::Return, end.line);
+
+    // always leave the function, even if we had an error
+    let function = emitter.end_function();
+    result?;
+
+    let idx = emitter
+        .current_chunk_mut()
+        .make_constant(Constant::Function(function));
+    emitter.add_instruction_arg(Opcode::Closure, idx, name.line);
+    Ok(())
+}
+
+fn compile_function_body(
+    emitter: &mut Emitter,
+    args: &[Token],
+    body: &[Stmt],
+) -> Result<(), PiccoloError> {
+    for arg in args {
+        emitter.make_variable(arg)?;
+    }
+
+    for stmt in body {
+        compile_stmt(emitter, stmt)?;
+    }
+
+    Ok(())
+}
+
+fn compile_data(
+    emitter: &mut Emitter,
+    name: &Token,
+    methods: &[Stmt],
+    fields: &[(Token, Expr)],
+) -> Result<(), PiccoloError> {
+    trace!("{} data {}", name.line, name.lexeme);
+
+    // like functions, declare the name first so that methods can refer to the type
+    let global = if emitter.is_local() {
+        emitter.make_variable(name)?;
+        None
+    } else {
+        Some(emitter.make_global_ident(name))
+    };
+
+    let idx = emitter.identifier_constant(name.lexeme);
+    emitter.add_instruction_arg(Opcode::Data, idx, name.line);
+
+    for (field, value) in fields {
+        compile_expr(emitter, value)?;
+        let idx = emitter.identifier_constant(field.lexeme);
+        emitter.add_instruction_arg(Opcode::Field, idx, field.line);
+    }
+
+    for method in methods {
+        if let Stmt::Fn {
+            name,
+            args,
+            arity,
+            body,
+            end,
+            ..
+        } = method
+        {
+            compile_function(emitter, name, args, *arity, body, end, true)?;
+            let idx = emitter.identifier_constant(name.lexeme);
+            emitter.add_instruction_arg(Opcode::Method, idx, name.line);
+        }
+    }
+
+    if let Some(global) = global {
+        emitter.add_instruction_arg(Opcode::DeclareGlobal, global, name.line);
+    }
+
+    Ok(())
+}
+
 fn compile_break(emitter: &mut Emitter, break_: &Token) -> Result<(), PiccoloError> {
     trace!("{} break", break_.line);
 
+    emitter.pop_loop_locals(break_.line);
     let offset = emitter.start_jump(Opcode::JumpForward, break_.line);
     emitter.add_break(offset, break_)?;
 
@@ -312,6 +484,7 @@ fn compile_break(emitter: &mut Emitter, break_: &Token) -> Result<(), PiccoloErr
 fn compile_continue(emitter: &mut Emitter, continue_: &Token) -> Result<(), PiccoloError> {
     trace!("{} continue", continue_.line);
 
+    emitter.pop_loop_locals(continue_.line);
     let offset = emitter.start_jump(Opcode::JumpForward, continue_.line);
     emitter.add_continue(offset, continue_)?;
 
@@ -352,28 +525,19 @@ fn compile_literal(emitter: &mut Emitter, literal: &Token) -> Result<(), Piccolo
 
 fn compile_paren(
     emitter: &mut Emitter,
-    right_paren: &Token,
+    left_paren: &Token,
     expr: &Expr,
 ) -> Result<(), PiccoloError> {
-    trace!("{} paren", right_paren.line);
+    trace!("{} paren", left_paren.line);
 
-    compile_expr(emitter, expr).map_err(|e| {
-        e.msg_string(format!(
-            "in expression starting on line {}",
-            right_paren.line
-        ))
-    })
+    compile_expr(emitter, expr).map_err(|e| e.label(left_paren.span, "in expression starting here"))
 }
 
 fn compile_variable(emitter: &mut Emitter, variable: &Token) -> Result<(), PiccoloError> {
     trace!("{} variable {}", variable.line, variable.lexeme);
 
-    if let Some(local) = emitter.get_local_slot(variable) {
-        emitter.add_instruction_arg(Opcode::GetLocal, local, variable.line);
-    } else {
-        let global = emitter.get_global_ident(&variable)?;
-        emitter.add_instruction_arg(Opcode::GetGlobal, global, variable.line);
-    }
+    let variable_ = emitter.resolve_variable(variable)?;
+    emitter.add_instruction_arg(variable_.get_op(), variable_.arg(), variable.line);
     Ok(())
 }
 
@@ -424,12 +588,34 @@ fn compile_binary(
         TokenKind::BitwiseOr => emitter.add_instruction(Opcode::BitOr, op.line),
         TokenKind::BitwiseXor => emitter.add_instruction(Opcode::BitXor, op.line),
 
+        TokenKind::ExclusiveRange => emitter.add_instruction(Opcode::ExclusiveRange, op.line),
+        TokenKind::InclusiveRange => emitter.add_instruction(Opcode::InclusiveRange, op.line),
+
         _ => unreachable!("binary {:?}", op),
     }
 
     Ok(())
 }
 
+fn compile_call(
+    emitter: &mut Emitter,
+    callee: &Expr,
+    paren: &Token,
+    arity: usize,
+    args: &[Expr],
+) -> Result<(), PiccoloError> {
+    trace!("{} call", paren.line);
+
+    compile_expr(emitter, callee)?;
+    for arg in args {
+        compile_expr(emitter, arg)?;
+    }
+
+    emitter.add_instruction_arg(Opcode::Call, arity as u16, paren.line);
+
+    Ok(())
+}
+
 fn compile_logical(
     emitter: &mut Emitter,
     lhs: &Expr,
@@ -456,18 +642,203 @@ fn compile_logical(
     Ok(())
 }
 
+fn compile_new(
+    emitter: &mut Emitter,
+    name: &Token,
+    args: &[(Token, Box<Expr>)],
+) -> Result<(), PiccoloError> {
+    trace!("{} new {}", name.line, name.lexeme);
+
+    compile_variable(emitter, name)?;
+    for (field, value) in args {
+        emitter.add_constant(Constant::String(field.lexeme.to_owned()), field.line);
+        compile_expr(emitter, value)?;
+    }
+    emitter.add_instruction_arg(Opcode::New, args.len() as u16, name.line);
+
+    Ok(())
+}
+
+fn compile_get(emitter: &mut Emitter, object: &Expr, name: &Token) -> Result<(), PiccoloError> {
+    trace!("{} get {}", name.line, name.lexeme);
+
+    compile_expr(emitter, object)?;
+    let idx = emitter.identifier_constant(name.lexeme);
+    emitter.add_instruction_arg(Opcode::GetProperty, idx, name.line);
+
+    Ok(())
+}
+
+fn compile_set(
+    emitter: &mut Emitter,
+    object: &Expr,
+    name: &Token,
+    value: &Expr,
+) -> Result<(), PiccoloError> {
+    trace!("{} set {}", name.line, name.lexeme);
+
+    compile_expr(emitter, object)?;
+    compile_expr(emitter, value)?;
+    let idx = emitter.identifier_constant(name.lexeme);
+    emitter.add_instruction_arg(Opcode::SetProperty, idx, name.line);
+
+    Ok(())
+}
+
+fn compile_index(
+    emitter: &mut Emitter,
+    right_bracket: &Token,
+    object: &Expr,
+    idx: &Expr,
+) -> Result<(), PiccoloError> {
+    trace!("{} index", right_bracket.line);
+
+    compile_expr(emitter, object)?;
+    compile_expr(emitter, idx)?;
+    emitter.add_instruction(Opcode::GetIndex, right_bracket.line);
+
+    Ok(())
+}
+
+fn compile_set_index(
+    emitter: &mut Emitter,
+    right_bracket: &Token,
+    object: &Expr,
+    idx: &Expr,
+    value: &Expr,
+) -> Result<(), PiccoloError> {
+    trace!("{} set index", right_bracket.line);
+
+    compile_expr(emitter, object)?;
+    compile_expr(emitter, idx)?;
+    compile_expr(emitter, value)?;
+    emitter.add_instruction(Opcode::SetIndex, right_bracket.line);
+
+    Ok(())
+}
+
+fn compile_list(
+    emitter: &mut Emitter,
+    left_bracket: &Token,
+    values: &[Expr],
+) -> Result<(), PiccoloError> {
+    trace!("{} list", left_bracket.line);
+
+    if values.len() > u16::MAX as usize {
+        return Err(PiccoloError::new(CompileError::SyntaxError)
+            .line(left_bracket.line)
+            .span(left_bracket.span)
+            .msg("too many items in list"));
+    }
+
+    for value in values {
+        compile_expr(emitter, value)?;
+    }
+    emitter.add_instruction_arg(Opcode::List, values.len() as u16, left_bracket.line);
+
+    Ok(())
+}
+
+fn compile_map(
+    emitter: &mut Emitter,
+    left_brace: &Token,
+    entries: &[(Expr, Expr)],
+) -> Result<(), PiccoloError> {
+    trace!("{} map", left_brace.line);
+
+    if entries.len() > u16::MAX as usize {
+        return Err(PiccoloError::new(CompileError::SyntaxError)
+            .line(left_brace.line)
+            .span(left_brace.span)
+            .msg("too many entries in map"));
+    }
+
+    for (key, value) in entries {
+        match key {
+            Expr::Literal { literal } if literal.kind == TokenKind::Identifier => {
+                emitter.add_constant(Constant::String(literal.lexeme.to_owned()), literal.line);
+            }
+            key => compile_expr(emitter, key)?,
+        }
+        compile_expr(emitter, value)?;
+    }
+    emitter.add_instruction_arg(Opcode::Map, entries.len() as u16, left_brace.line);
+
+    Ok(())
+}
+
+// How a variable is accessed at runtime.
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    Local(LocalSlotIdx),
+    Upvalue(u16),
+    Global(ConstantIdx),
+}
+
+impl Variable {
+    fn get_op(self) -> Opcode {
+        match self {
+            Variable::Local(_) => Opcode::GetLocal,
+            Variable::Upvalue(_) => Opcode::GetUpvalue,
+            Variable::Global(_) => Opcode::GetGlobal,
+        }
+    }
+
+    fn set_op(self) -> Opcode {
+        match self {
+            Variable::Local(_) => Opcode::SetLocal,
+            Variable::Upvalue(_) => Opcode::SetUpvalue,
+            Variable::Global(_) => Opcode::SetGlobal,
+        }
+    }
+
+    fn arg(self) -> u16 {
+        match self {
+            Variable::Local(idx) | Variable::Upvalue(idx) | Variable::Global(idx) => idx,
+        }
+    }
+}
+
+// The compilation state of a single function. The top level of a script is
+// compiled as if it were a function with no arguments.
+struct FunctionState {
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    identifiers: FnvHashMap<String, ConstantIdx>,
+    scope_depth: LocalScopeDepth,
+    continue_offsets: Vec<Vec<ChunkOffset>>,
+    break_offsets: Vec<Vec<ChunkOffset>>,
+    loop_depths: Vec<LocalScopeDepth>,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize) -> Self {
+        FunctionState {
+            name: name.to_owned(),
+            arity,
+            chunk: Chunk::default(),
+            locals: Vec::new(),
+            captures: Vec::new(),
+            identifiers: FnvHashMap::default(),
+            scope_depth: 0,
+            continue_offsets: Vec::with_capacity(0),
+            break_offsets: Vec::with_capacity(0),
+            loop_depths: Vec::with_capacity(0),
+        }
+    }
+}
+
 /// Bytecode compiler object
 ///
 /// Construct an Emitter, and pass a `&mut` reference to `compile_ast` along with
 /// the abstract syntax tree. Extract the `Chunk` with `current_chunk{_mut}()` or
 /// `into_chunk()`.
 pub struct Emitter {
-    function: Function,
-    locals: Vec<Local>,
-    global_identifiers: FnvHashMap<String, ConstantIdx>,
-    scope_depth: LocalScopeDepth,
-    continue_offsets: Vec<Vec<ChunkOffset>>,
-    break_offsets: Vec<Vec<ChunkOffset>>,
+    functions: Vec<FunctionState>,
+    global_identifiers: FnvHashSet<String>,
 }
 
 impl Default for Emitter {
@@ -480,29 +851,59 @@ impl Emitter {
     /// Make a new emitter, equivalent to `Default::default()`
     pub fn new() -> Self {
         Self {
-            function: Function::default(),
-            locals: Vec::new(),
-            global_identifiers: FnvHashMap::default(),
-            scope_depth: 0,
-            continue_offsets: Vec::with_capacity(0),
-            break_offsets: Vec::with_capacity(0),
+            functions: vec![FunctionState::new("", 0)],
+            global_identifiers: BUILTINS
+                .iter()
+                .map(|(name, _, _)| String::from(*name))
+                .collect(),
         }
     }
 
+    /// Declare a global variable that will be defined outside of the compiled
+    /// program, like a function registered with [`Machine::register_fn`].
+    ///
+    /// [`Machine::register_fn`]: ../../runtime/vm/struct.Machine.html#method.register_fn
+    pub fn declare_global(&mut self, name: &str) {
+        self.global_identifiers.insert(String::from(name));
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.functions.last().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
     fn is_local(&self) -> bool {
-        self.scope_depth > 0
+        self.state().scope_depth > 0
     }
 
-    pub fn into_chunk(self) -> Chunk {
-        self.function.into_chunk()
+    pub fn into_chunk(mut self) -> Chunk {
+        self.functions.swap_remove(0).chunk
     }
 
     pub fn current_chunk(&self) -> &Chunk {
-        self.function.chunk()
+        &self.state().chunk
     }
 
     pub fn current_chunk_mut(&mut self) -> &mut Chunk {
-        self.function.chunk_mut()
+        &mut self.state_mut().chunk
+    }
+
+    fn begin_function(&mut self, name: &str, arity: usize, method: bool) {
+        let mut state = FunctionState::new(name, arity);
+        // the first slot of a call frame holds the function being called, or the
+        // instance a method was called on
+        let slot = if method { "me" } else { "" };
+        state.locals.push(Local::new(String::from(slot), 0));
+        state.scope_depth = 1;
+        self.functions.push(state);
+    }
+
+    fn end_function(&mut self) -> Function {
+        let state = self.functions.pop().unwrap();
+        Function::new(state.name, state.arity, state.chunk, state.captures)
     }
 
     fn add_instruction(&mut self, op: Opcode, line: Line) {
@@ -523,38 +924,55 @@ impl Emitter {
             .write_arg_u16(Opcode::Constant, idx, line);
     }
 
-    fn make_global_ident(&mut self, name: &Token) -> ConstantIdx {
-        trace!("{} make global {}", name.line, name.lexeme);
-
-        if self.global_identifiers.contains_key(name.lexeme) {
-            self.global_identifiers[name.lexeme]
+    // get the index of the identifier in the current chunk's constant table
+    fn identifier_constant(&mut self, name: &str) -> ConstantIdx {
+        if let Some(idx) = self.state().identifiers.get(name) {
+            *idx
         } else {
             let idx = self
                 .current_chunk_mut()
-                .make_constant(Constant::String(name.lexeme.to_owned()));
-            self.global_identifiers.insert(name.lexeme.to_owned(), idx);
+                .make_constant(Constant::String(name.to_owned()));
+            self.state_mut().identifiers.insert(name.to_owned(), idx);
             idx
         }
     }
 
-    fn get_global_ident(&self, name: &Token) -> Result<ConstantIdx, PiccoloError> {
+    fn make_global_ident(&mut self, name: &Token) -> ConstantIdx {
+        trace!("{} make global {}", name.line, name.lexeme);
+
+        self.global_identifiers.insert(name.lexeme.to_owned());
+        self.identifier_constant(name.lexeme)
+    }
+
+    fn get_global_ident(&mut self, name: &Token) -> Result<ConstantIdx, PiccoloError> {
         trace!("{} get global {}", name.line, name.lexeme);
 
-        self.global_identifiers
-            .get(name.lexeme)
-            .copied()
-            .ok_or_else(|| {
-                PiccoloError::new(ErrorKind::UndefinedVariable {
-                    name: name.lexeme.to_owned(),
-                })
-                .line(name.line)
+        if self.global_identifiers.contains(name.lexeme) {
+            Ok(self.identifier_constant(name.lexeme))
+        } else {
+            Err(PiccoloError::new(CompileError::UndefinedVariable {
+                name: name.lexeme.to_owned(),
             })
+            .line(name.line)
+            .span(name.span))
+        }
+    }
+
+    fn resolve_variable(&mut self, name: &Token) -> Result<Variable, PiccoloError> {
+        let current = self.functions.len() - 1;
+        if let Some(slot) = self.get_local_slot(current, name) {
+            Ok(Variable::Local(slot))
+        } else if let Some(idx) = self.get_upvalue(current, name) {
+            Ok(Variable::Upvalue(idx))
+        } else {
+            Ok(Variable::Global(self.get_global_ident(name)?))
+        }
     }
 
-    fn get_local_slot(&self, name: &Token) -> Option<LocalSlotIdx> {
+    fn get_local_slot(&self, function: usize, name: &Token) -> Option<LocalSlotIdx> {
         trace!("{} get local slot {}", name.line, name.lexeme);
 
-        for (i, local) in self.locals.iter().enumerate().rev() {
+        for (i, local) in self.functions[function].locals.iter().enumerate().rev() {
             if local.name == name.lexeme {
                 return Some(i as u16);
             }
@@ -563,10 +981,41 @@ impl Emitter {
         None
     }
 
+    // find a variable in the functions enclosing `function`, capturing it in each
+    // function in between
+    fn get_upvalue(&mut self, function: usize, name: &Token) -> Option<u16> {
+        trace!("{} get upvalue {}", name.line, name.lexeme);
+
+        if function == 0 {
+            return None;
+        }
+
+        let enclosing = function - 1;
+        if let Some(slot) = self.get_local_slot(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].captured = true;
+            Some(self.add_capture(function, true, slot))
+        } else {
+            self.get_upvalue(enclosing, name)
+                .map(|idx| self.add_capture(function, false, idx))
+        }
+    }
+
+    fn add_capture(&mut self, function: usize, is_local: bool, index: u16) -> u16 {
+        let captures = &mut self.functions[function].captures;
+        let capture = Capture { is_local, index };
+
+        if let Some(idx) = captures.iter().position(|c| *c == capture) {
+            idx as u16
+        } else {
+            captures.push(capture);
+            captures.len() as u16 - 1
+        }
+    }
+
     fn get_local_depth(&self, name: &Token) -> Option<LocalScopeDepth> {
         trace!("{} get local depth {}", name.line, name.lexeme);
 
-        for local in self.locals.iter().rev() {
+        for local in self.state().locals.iter().rev() {
             if local.name == name.lexeme {
                 return Some(local.depth);
             }
@@ -579,27 +1028,31 @@ impl Emitter {
         trace!("{} make variable {}", name.line, name.lexeme);
 
         // are we in global scope?
-        if self.scope_depth > 0 {
+        let scope_depth = self.state().scope_depth;
+        if scope_depth > 0 {
             // check if we have a local with this name
             if let Some(idx) = self.get_local_depth(name) {
                 // if we do,
-                if idx != self.scope_depth {
+                if idx != scope_depth {
                     // create a new local if we're in a different scope
-                    self.locals
-                        .push(Local::new(name.lexeme.to_owned(), self.scope_depth));
+                    self.state_mut()
+                        .locals
+                        .push(Local::new(name.lexeme.to_owned(), scope_depth));
                 } else {
                     // error if we're in the same scope
-                    return Err(PiccoloError::new(ErrorKind::SyntaxError)
+                    return Err(PiccoloError::new(CompileError::SyntaxError)
                         .line(name.line)
+                        .span(name.span)
                         .msg_string(format!(
                             "variable with name '{}' already exists",
-                            self.locals[idx as usize - 1].name
+                            name.lexeme
                         )));
                 }
             } else {
                 // if we don't, create a new local with this name
-                self.locals
-                    .push(Local::new(name.lexeme.to_owned(), self.scope_depth));
+                self.state_mut()
+                    .locals
+                    .push(Local::new(name.lexeme.to_owned(), scope_depth));
             }
         } else {
             // yes, make a global
@@ -610,16 +1063,29 @@ impl Emitter {
         Ok(())
     }
 
+    // make a local that can't be named, for values the compiler keeps on the stack
+    fn make_hidden_local(&mut self) -> LocalSlotIdx {
+        let depth = self.state().scope_depth;
+        self.state_mut()
+            .locals
+            .push(Local::new(String::new(), depth));
+        self.state().locals.len() as LocalSlotIdx - 1
+    }
+
     fn begin_scope(&mut self) {
-        self.scope_depth += 1;
+        self.state_mut().scope_depth += 1;
     }
 
     fn end_scope(&mut self, line: Line) {
-        self.scope_depth -= 1;
-        while !self.locals.is_empty() && self.locals[self.locals.len() - 1].depth > self.scope_depth
+        self.state_mut().scope_depth -= 1;
+        while !self.state().locals.is_empty()
+            && self.state().locals[self.state().locals.len() - 1].depth > self.state().scope_depth
         {
-            self.add_instruction(Opcode::Pop, line);
-            self.locals.pop().unwrap();
+            if self.state_mut().locals.pop().unwrap().captured {
+                self.add_instruction(Opcode::CloseUpvalue, line);
+            } else {
+                self.add_instruction(Opcode::Pop, line);
+            }
         }
     }
 
@@ -632,30 +1098,60 @@ impl Emitter {
     }
 
     fn start_loop_jumps(&mut self) -> ChunkOffset {
-        self.continue_offsets.push(Vec::new());
-        self.break_offsets.push(Vec::new());
+        self.state_mut().continue_offsets.push(Vec::new());
+        self.state_mut().break_offsets.push(Vec::new());
+        let depth = self.state().scope_depth;
+        self.state_mut().loop_depths.push(depth);
         self.current_chunk().data.len()
     }
 
     fn patch_continue_jumps(&mut self) {
-        for offset in self.continue_offsets.pop().unwrap() {
+        for offset in self.state_mut().continue_offsets.pop().unwrap() {
             self.patch_jump(offset);
         }
     }
 
     fn patch_break_jumps(&mut self) {
-        for offset in self.break_offsets.pop().unwrap() {
+        for offset in self.state_mut().break_offsets.pop().unwrap() {
             self.patch_jump(offset);
         }
+        self.state_mut().loop_depths.pop();
+    }
+
+    // break and continue jump out of the scopes inside the loop, so the locals
+    // declared in them need to be removed without forgetting about them
+    fn pop_loop_locals(&mut self, line: Line) {
+        let depth = match self.state().loop_depths.last() {
+            Some(depth) => *depth,
+            None => return,
+        };
+
+        let captured: Vec<bool> = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| local.captured)
+            .collect();
+        for captured in captured {
+            if captured {
+                self.add_instruction(Opcode::CloseUpvalue, line);
+            } else {
+                self.add_instruction(Opcode::Pop, line);
+            }
+        }
     }
 
     fn add_break(&mut self, offset: ChunkOffset, break_: &Token) -> Result<(), PiccoloError> {
-        self.break_offsets
+        self.state_mut()
+            .break_offsets
             .last_mut()
             .ok_or_else(|| {
-                PiccoloError::new(ErrorKind::SyntaxError)
+                PiccoloError::new(CompileError::SyntaxError)
                     .msg("cannot break outside of a loop")
                     .line(break_.line)
+                    .span(break_.span)
             })?
             .push(offset);
 
@@ -663,12 +1159,14 @@ impl Emitter {
     }
 
     fn add_continue(&mut self, offset: ChunkOffset, continue_: &Token) -> Result<(), PiccoloError> {
-        self.continue_offsets
+        self.state_mut()
+            .continue_offsets
             .last_mut()
             .ok_or_else(|| {
-                PiccoloError::new(ErrorKind::SyntaxError)
+                PiccoloError::new(CompileError::SyntaxError)
                     .msg("cannot continue outside of a loop")
                     .line(continue_.line)
+                    .span(continue_.span)
             })?
             .push(offset);
 
@@ -1,33 +1,34 @@
 //! Contains `Parser`, which converts a source of tokens into a Piccolo AST.
 
-use crate::{ErrorKind, PiccoloError, Scanner, Token, TokenKind};
+use crate::{ParseError, PiccoloError, Scanner, Span, Token, TokenKind};
 
 use super::ast::Expr;
 use super::ast::Stmt;
 
-/// Parse a stream of tokens into an AST. This method collects errors on statement
-/// boundaries, continuing until the end of the file.
+/// Parse a stream of tokens into an AST.
+///
+/// When a statement has an error, the parser skips ahead to where the next one
+/// probably starts and continues until the end of the file, so that a mistake is
+/// reported once rather than once for every token after it. Errors from the
+/// scanner are collected as well. All of the errors are returned in the order
+/// they appear in the source.
 pub fn parse<'a>(scanner: &mut Scanner<'a>) -> Result<Vec<Stmt<'a>>, Vec<PiccoloError>> {
-    let mut ast = Vec::new();
     let mut errors = Vec::new();
+    let ast = parse_block(scanner, &[], &mut errors)?;
 
-    while scanner.peek_token(0)?.kind != TokenKind::Eof {
-        trace!("statement");
-
-        match parse_statement(scanner) {
-            Ok(stmt) => ast.push(stmt),
-            Err(e) => errors.push(e),
-        }
-    }
-
+    errors.extend(scanner.take_errors());
     if errors.is_empty() {
         Ok(ast)
     } else {
+        errors.sort_by_key(|e| e.get_span().map(|span| span.start));
         Err(errors)
     }
 }
 
-fn parse_statement<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_statement<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     if scanner.peek_token(1)?.is_assign() {
         parse_assignment(scanner)
     } else if scanner.peek_token(1)?.kind == TokenKind::Declare {
@@ -41,22 +42,53 @@ fn parse_statement<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloErr
     } else if scanner.peek_token(0)?.kind == TokenKind::Assert {
         parse_assert(scanner)
     } else if scanner.peek_token(0)?.kind == TokenKind::Do {
-        parse_do(scanner)
+        parse_do(scanner, errors)
     } else if scanner.peek_token(0)?.kind == TokenKind::If {
-        parse_if(scanner)
+        parse_if(scanner, errors)
     } else if scanner.peek_token(0)?.kind == TokenKind::While {
-        parse_while(scanner)
+        parse_while(scanner, errors)
     } else if scanner.peek_token(0)?.kind == TokenKind::For {
-        parse_for(scanner)
+        parse_for(scanner, errors)
     } else if scanner.peek_token(0)?.kind == TokenKind::Fn {
-        parse_fn(scanner)
+        parse_fn(scanner, errors)
+    } else if scanner.peek_token(0)?.kind == TokenKind::Data {
+        parse_data(scanner, errors)
     } else {
         trace!("expr");
 
         let token = *scanner.peek_token(0)?;
         let expr = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
 
-        Ok(Stmt::Expr { token, expr })
+        match expr {
+            Expr::Get { object, name } if scanner.peek_token(0)?.kind == TokenKind::Assign => {
+                trace!("set");
+                scanner.next_token()?;
+                let value = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+                let expr = Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                };
+                Ok(Stmt::Expr { token, expr })
+            }
+            Expr::Index {
+                right_bracket,
+                object,
+                idx,
+            } if scanner.peek_token(0)?.kind == TokenKind::Assign => {
+                trace!("set index");
+                scanner.next_token()?;
+                let value = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+                let expr = Expr::SetIndex {
+                    right_bracket,
+                    object,
+                    idx,
+                    value: Box::new(value),
+                };
+                Ok(Stmt::Expr { token, expr })
+            }
+            expr => Ok(Stmt::Expr { token, expr }),
+        }
     }
 }
 
@@ -114,28 +146,34 @@ fn parse_assert<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError>
     Ok(Stmt::Assert { assert, value })
 }
 
-fn parse_do<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_do<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     trace!("do");
 
     scanner.next_token()?;
-    let body = parse_block(scanner)?;
+    let body = parse_block(scanner, &[TokenKind::End], errors)?;
     let end = consume(scanner, TokenKind::End)?;
 
     Ok(Stmt::Block { end, body })
 }
 
-fn parse_if<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_if<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     trace!("if");
 
     let if_ = scanner.next_token()?;
     let cond = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
     consume(scanner, TokenKind::Do)?;
-    let then_block = block_until_else_or_end(scanner)?;
+    let then_block = parse_block(scanner, &[TokenKind::End, TokenKind::Else], errors)?;
 
     let (else_, else_block) = if scanner.peek_token(0)?.kind == TokenKind::Else {
         (
             Some(consume(scanner, TokenKind::Else)?),
-            Some(parse_block(scanner)?),
+            Some(parse_block(scanner, &[TokenKind::End], errors)?),
         )
     } else {
         (None, None)
@@ -153,12 +191,15 @@ fn parse_if<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
     })
 }
 
-fn parse_while<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_while<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     trace!("while");
     let while_ = scanner.next_token()?;
     let cond = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
     consume(scanner, TokenKind::Do)?;
-    let body = parse_block(scanner)?;
+    let body = parse_block(scanner, &[TokenKind::End], errors)?;
     let end = consume(scanner, TokenKind::End)?;
 
     Ok(Stmt::While {
@@ -169,10 +210,19 @@ fn parse_while<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError>
     })
 }
 
-fn parse_for<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_for<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     trace!("for");
     let for_ = scanner.next_token()?;
 
+    if scanner.peek_token(0)?.kind == TokenKind::Identifier
+        && scanner.peek_token(1)?.kind == TokenKind::In
+    {
+        return parse_for_in(scanner, for_, errors);
+    }
+
     let init = Box::new(parse_declaration(scanner)?);
 
     consume(scanner, TokenKind::Comma)?;
@@ -182,7 +232,7 @@ fn parse_for<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
     let inc = Box::new(parse_assignment(scanner)?);
 
     consume(scanner, TokenKind::Do)?;
-    let body = parse_block(scanner)?;
+    let body = parse_block(scanner, &[TokenKind::End], errors)?;
     let end = consume(scanner, TokenKind::End)?;
 
     Ok(Stmt::For {
@@ -195,7 +245,34 @@ fn parse_for<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
     })
 }
 
-fn parse_fn<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
+fn parse_for_in<'a>(
+    scanner: &mut Scanner<'a>,
+    for_: Token<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
+    trace!("for in");
+
+    let name = consume(scanner, TokenKind::Identifier)?;
+    consume(scanner, TokenKind::In)?;
+    let iterable = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+
+    consume(scanner, TokenKind::Do)?;
+    let body = parse_block(scanner, &[TokenKind::End], errors)?;
+    let end = consume(scanner, TokenKind::End)?;
+
+    Ok(Stmt::ForIn {
+        for_,
+        name,
+        iterable,
+        body,
+        end,
+    })
+}
+
+fn parse_fn<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
     trace!("fn");
     scanner.next_token()?;
     let name = consume(scanner, TokenKind::Identifier)?;
@@ -215,8 +292,8 @@ fn parse_fn<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
     consume(scanner, TokenKind::RightParen)?;
 
     consume(scanner, TokenKind::Do)?;
-    let body = parse_block(scanner)?;
-    consume(scanner, TokenKind::End)?;
+    let body = parse_block(scanner, &[TokenKind::End], errors)?;
+    let end = consume(scanner, TokenKind::End)?;
 
     Ok(Stmt::Fn {
         name,
@@ -224,42 +301,189 @@ fn parse_fn<'a>(scanner: &mut Scanner<'a>) -> Result<Stmt<'a>, PiccoloError> {
         arity,
         body,
         method: false,
+        end,
     })
 }
 
-fn block_until_else_or_end<'a>(scanner: &mut Scanner<'a>) -> Result<Vec<Stmt<'a>>, PiccoloError> {
-    let mut stmts = Vec::new();
+fn parse_data<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Stmt<'a>, PiccoloError> {
+    trace!("data");
+    scanner.next_token()?;
+    let name = consume(scanner, TokenKind::Identifier)?;
+    consume(scanner, TokenKind::Do)?;
 
+    let mut methods = Vec::new();
+    let mut fields = Vec::new();
     while scanner.peek_token(0)?.kind != TokenKind::End
-        && scanner.peek_token(0)?.kind != TokenKind::Else
+        && scanner.peek_token(0)?.kind != TokenKind::Eof
     {
-        trace!("declaration in block until else");
-        stmts.push(parse_statement(scanner)?);
+        let start = scanner.peek_token(0)?.span;
+        let member = if scanner.peek_token(0)?.kind == TokenKind::Fn {
+            parse_fn(scanner, errors).map(|method| {
+                if let Stmt::Fn {
+                    name,
+                    args,
+                    arity,
+                    body,
+                    end,
+                    ..
+                } = method
+                {
+                    methods.push(Stmt::Fn {
+                        name,
+                        args,
+                        arity,
+                        body,
+                        method: true,
+                        end,
+                    });
+                }
+            })
+        } else {
+            parse_field(scanner).map(|field| fields.push(field))
+        };
+
+        if let Err(e) = member {
+            errors.push(e);
+            synchronize(scanner, start, &[TokenKind::End], errors)?;
+        }
     }
+    consume(scanner, TokenKind::End)?;
 
-    Ok(stmts)
+    Ok(Stmt::Data {
+        name,
+        methods,
+        fields,
+    })
 }
 
-fn parse_block<'a>(scanner: &mut Scanner<'a>) -> Result<Vec<Stmt<'a>>, PiccoloError> {
+fn parse_field<'a>(scanner: &mut Scanner<'a>) -> Result<(Token<'a>, Expr<'a>), PiccoloError> {
+    let field = consume(scanner, TokenKind::Identifier)?;
+    consume(scanner, TokenKind::Declare)?;
+    let value = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+    Ok((field, value))
+}
+
+// parse statements until one of `until` or the end of the file, recovering from
+// errors in each statement
+fn parse_block<'a>(
+    scanner: &mut Scanner<'a>,
+    until: &[TokenKind],
+    errors: &mut Vec<PiccoloError>,
+) -> Result<Vec<Stmt<'a>>, PiccoloError> {
     let mut stmts = Vec::new();
 
-    while scanner.peek_token(0)?.kind != TokenKind::End {
+    while scanner.peek_token(0)?.kind != TokenKind::Eof
+        && !until.contains(&scanner.peek_token(0)?.kind)
+    {
         trace!("declaration in block");
-        stmts.push(parse_statement(scanner)?);
+        let start = scanner.peek_token(0)?.span;
+        match parse_statement(scanner, errors) {
+            Ok(stmt) => stmts.push(stmt),
+            Err(e) => {
+                errors.push(e);
+                synchronize(scanner, start, until, errors)?;
+            }
+        }
     }
 
     Ok(stmts)
 }
 
+// after an error in the statement starting at `start`, skip to where the next
+// statement probably starts: the first token on a new line, a statement keyword,
+// or one of `until`. a `do` on the same line as the error begins the body of the
+// broken statement, which is parsed for errors and thrown away.
+fn synchronize<'a>(
+    scanner: &mut Scanner<'a>,
+    start: Span,
+    until: &[TokenKind],
+    errors: &mut Vec<PiccoloError>,
+) -> Result<(), PiccoloError> {
+    // the statement has to make progress, or it would fail again in the same place
+    if scanner.peek_token(0)?.span == start {
+        scanner.next_token()?;
+    }
+
+    loop {
+        let token = *scanner.peek_token(0)?;
+        if token.kind == TokenKind::Eof
+            || until.contains(&token.kind)
+            || token.line != scanner.previous_line()
+        {
+            break;
+        }
+
+        match token.kind {
+            TokenKind::Do => {
+                scanner.next_token()?;
+                parse_block(scanner, &[TokenKind::End, TokenKind::Else], errors)?;
+                while scanner.peek_token(0)?.kind == TokenKind::Else {
+                    scanner.next_token()?;
+                    parse_block(scanner, &[TokenKind::End, TokenKind::Else], errors)?;
+                }
+                if scanner.peek_token(0)?.kind == TokenKind::End {
+                    scanner.next_token()?;
+                }
+                break;
+            }
+
+            TokenKind::Fn
+            | TokenKind::Data
+            | TokenKind::If
+            | TokenKind::While
+            | TokenKind::For
+            | TokenKind::Retn
+            | TokenKind::Assert
+            | TokenKind::Break
+            | TokenKind::Continue => break,
+
+            _ => {
+                scanner.next_token()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn parse_expression<'a>(
     scanner: &mut Scanner<'a>,
     min_bp: BindingPower,
 ) -> Result<Expr<'a>, PiccoloError> {
     trace!("expr_bp {:?}", min_bp);
-    let lhs_token = scanner.next_token()?;
-    let mut lhs = if lhs_token.is_value() {
+    let lhs_token = *scanner.peek_token(0)?;
+    if !lhs_token.is_value()
+        && !matches!(
+            lhs_token.kind,
+            TokenKind::Me
+                | TokenKind::Identifier
+                | TokenKind::LeftParen
+                | TokenKind::LeftBracket
+                | TokenKind::LeftBrace
+                | TokenKind::Error
+        )
+        && prefix_binding_power(lhs_token.kind) == BindingPower::None
+    {
+        // left for the caller to recover from, it could be the start of a statement
+        return Err(PiccoloError::new(ParseError::ExpectedExpression {
+            got: lhs_token.to_string(),
+        })
+        .line(lhs_token.line)
+        .span(lhs_token.span));
+    }
+
+    scanner.next_token()?;
+    let mut lhs = if lhs_token.is_value() || lhs_token.kind == TokenKind::Error {
         trace!("atom");
         Expr::Literal { literal: lhs_token }
+    } else if lhs_token.kind == TokenKind::Me {
+        trace!("me");
+        Expr::Variable {
+            variable: lhs_token,
+        }
     } else if lhs_token.kind == TokenKind::Identifier {
         if scanner.peek_token(0)?.kind == TokenKind::Colon {
             trace!("path");
@@ -280,30 +504,23 @@ fn parse_expression<'a>(
     } else if lhs_token.kind == TokenKind::LeftParen {
         trace!("grouping");
         let expr = Box::new(parse_expression(scanner, BindingPower::ExpressionBoundary)?);
-        let right_paren = consume(scanner, TokenKind::RightParen).map_err(|e| {
-            e.msg_string(format!("in expression starting on line {}", lhs_token.line))
-        })?;
-        Expr::Paren { right_paren, expr }
-    } else if lhs_token.kind == TokenKind::Eof {
-        trace!("eof");
-        return Err(PiccoloError::new(ErrorKind::ExpectedExpression {
-            got: lhs_token.to_string(),
-        })
-        .line(lhs_token.line));
+        let right_paren = consume(scanner, TokenKind::RightParen)
+            .map_err(|e| e.label(lhs_token.span, "in expression starting here"))?;
+        Expr::Paren {
+            left_paren: lhs_token,
+            right_paren,
+            expr,
+        }
+    } else if lhs_token.kind == TokenKind::LeftBracket {
+        parse_list(scanner, lhs_token)?
+    } else if lhs_token.kind == TokenKind::LeftBrace {
+        parse_map(scanner, lhs_token)?
     } else {
         trace!("prefix");
-        let pbp = prefix_binding_power(lhs_token.kind);
-        if pbp != BindingPower::None {
-            let rhs = parse_expression(scanner, pbp)?;
-            Expr::Unary {
-                op: lhs_token,
-                rhs: Box::new(rhs),
-            }
-        } else {
-            return Err(PiccoloError::new(ErrorKind::ExpectedExpression {
-                got: lhs_token.to_string(),
-            })
-            .line(lhs_token.line));
+        let rhs = parse_expression(scanner, prefix_binding_power(lhs_token.kind))?;
+        Expr::Unary {
+            op: lhs_token,
+            rhs: Box::new(rhs),
         }
     };
 
@@ -320,6 +537,40 @@ fn parse_expression<'a>(
             break;
         }
 
+        if op_token.kind == TokenKind::LeftParen {
+            lhs = parse_call(scanner, lhs)?;
+            continue;
+        }
+
+        if op_token.kind == TokenKind::LeftBracket {
+            // a bracket on the next line starts a list, not an index
+            if op_token.line != scanner.previous_line() {
+                break;
+            }
+
+            trace!("index");
+            scanner.next_token()?;
+            let idx = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+            let right_bracket = consume(scanner, TokenKind::RightBracket)?;
+            lhs = Expr::Index {
+                right_bracket,
+                object: Box::new(lhs),
+                idx: Box::new(idx),
+            };
+            continue;
+        }
+
+        if op_token.kind == TokenKind::Period {
+            trace!("get");
+            scanner.next_token()?;
+            let name = consume(scanner, TokenKind::Identifier)?;
+            lhs = Expr::Get {
+                object: Box::new(lhs),
+                name,
+            };
+            continue;
+        }
+
         let op = scanner.next_token()?;
         let rhs = parse_expression(scanner, op_bp + 1)?;
         lhs = if matches!(op_bp, BindingPower::LogicalAnd | BindingPower::LogicalOr) {
@@ -342,16 +593,130 @@ fn parse_expression<'a>(
     Ok(lhs)
 }
 
+fn parse_list<'a>(
+    scanner: &mut Scanner<'a>,
+    left_bracket: Token<'a>,
+) -> Result<Expr<'a>, PiccoloError> {
+    trace!("list");
+
+    let label = |e: PiccoloError| e.label(left_bracket.span, "in list starting here");
+    let mut values = Vec::new();
+    while scanner.peek_token(0)?.kind != TokenKind::RightBracket {
+        values.push(parse_expression(scanner, BindingPower::ExpressionBoundary)?);
+        if scanner.peek_token(0)?.kind != TokenKind::RightBracket {
+            consume(scanner, TokenKind::Comma).map_err(label)?;
+        }
+    }
+    let right_bracket = consume(scanner, TokenKind::RightBracket).map_err(label)?;
+
+    Ok(Expr::List {
+        left_bracket,
+        values,
+        right_bracket,
+    })
+}
+
+fn parse_map<'a>(
+    scanner: &mut Scanner<'a>,
+    left_brace: Token<'a>,
+) -> Result<Expr<'a>, PiccoloError> {
+    trace!("map");
+
+    let label = |e: PiccoloError| e.label(left_brace.span, "in map starting here");
+    let mut entries = Vec::new();
+    while scanner.peek_token(0)?.kind != TokenKind::RightBrace {
+        // bare identifiers are string keys, not variables
+        let key = if scanner.peek_token(0)?.kind == TokenKind::Identifier
+            && scanner.peek_token(1)?.kind == TokenKind::Colon
+        {
+            Expr::Literal {
+                literal: scanner.next_token()?,
+            }
+        } else {
+            parse_expression(scanner, BindingPower::ExpressionBoundary)?
+        };
+        consume(scanner, TokenKind::Colon).map_err(label)?;
+        let value = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+        entries.push((key, value));
+        if scanner.peek_token(0)?.kind != TokenKind::RightBrace {
+            consume(scanner, TokenKind::Comma).map_err(label)?;
+        }
+    }
+    let right_brace = consume(scanner, TokenKind::RightBrace).map_err(label)?;
+
+    Ok(Expr::Map {
+        left_brace,
+        entries,
+        right_brace,
+    })
+}
+
+fn parse_call<'a>(scanner: &mut Scanner<'a>, callee: Expr<'a>) -> Result<Expr<'a>, PiccoloError> {
+    trace!("call");
+
+    consume(scanner, TokenKind::LeftParen)?;
+
+    // named arguments construct an instance of a data type
+    if scanner.peek_token(0)?.kind == TokenKind::Identifier
+        && scanner.peek_token(1)?.kind == TokenKind::Assign
+    {
+        if let Expr::Variable { variable: name } = callee {
+            return parse_new(scanner, name);
+        }
+    }
+
+    let mut args = Vec::new();
+    while scanner.peek_token(0)?.kind != TokenKind::RightParen {
+        args.push(parse_expression(scanner, BindingPower::ExpressionBoundary)?);
+        if scanner.peek_token(0)?.kind != TokenKind::RightParen {
+            consume(scanner, TokenKind::Comma)?;
+        }
+    }
+    let paren = consume(scanner, TokenKind::RightParen)?;
+
+    Ok(Expr::Call {
+        callee: Box::new(callee),
+        paren,
+        arity: args.len(),
+        args,
+    })
+}
+
+fn parse_new<'a>(scanner: &mut Scanner<'a>, name: Token<'a>) -> Result<Expr<'a>, PiccoloError> {
+    trace!("new");
+
+    let mut args = Vec::new();
+    while scanner.peek_token(0)?.kind != TokenKind::RightParen {
+        let field = consume(scanner, TokenKind::Identifier)?;
+        consume(scanner, TokenKind::Assign)?;
+        let value = parse_expression(scanner, BindingPower::ExpressionBoundary)?;
+        args.push((field, Box::new(value)));
+        if scanner.peek_token(0)?.kind != TokenKind::RightParen {
+            consume(scanner, TokenKind::Comma)?;
+        }
+    }
+    let right_paren = consume(scanner, TokenKind::RightParen)?;
+
+    Ok(Expr::New {
+        name,
+        args,
+        right_paren,
+    })
+}
+
 fn consume<'a>(scanner: &mut Scanner<'a>, kind: TokenKind) -> Result<Token<'a>, PiccoloError> {
-    let tok = scanner.next_token()?;
+    // the token is left alone if it's the wrong kind, so that the parser can
+    // recover from it
+    let tok = *scanner.peek_token(0)?;
     if tok.kind == kind {
-        Ok(tok)
+        scanner.next_token()
     } else {
-        Err(PiccoloError::new(ErrorKind::UnexpectedToken {
+        Err(PiccoloError::new(ParseError::UnexpectedToken {
             exp: format!("{:?}", kind),
             got: format!("{:?}", tok.kind),
         })
-        .line(tok.line))
+        .line(tok.line)
+        .span(tok.span))
     }
 }
 
@@ -387,6 +752,10 @@ pub(crate) fn infix_binding_power(kind: TokenKind) -> BindingPower {
         | TokenKind::GreaterEqual
             => BindingPower::Comparison,
 
+          TokenKind::ExclusiveRange
+        | TokenKind::InclusiveRange
+            => BindingPower::Range,
+
           TokenKind::Equal
         | TokenKind::NotEqual => BindingPower::Equality,
 
@@ -397,6 +766,11 @@ pub(crate) fn infix_binding_power(kind: TokenKind) -> BindingPower {
         TokenKind::LogicalAnd => BindingPower::LogicalAnd,
         TokenKind::LogicalOr => BindingPower::LogicalOr,
 
+          TokenKind::LeftParen
+        | TokenKind::LeftBracket
+        | TokenKind::Period
+            => BindingPower::Call,
+
         TokenKind::Retn => BindingPower::None,
         TokenKind::Identifier => BindingPower::None,
         _ => BindingPower::None,
@@ -410,9 +784,9 @@ macro_rules! prec {
             $($item = $num,)*
         }
 
-        impl Into<u8> for $name {
-            fn into(self) -> u8 {
-                match self {
+        impl From<$name> for u8 {
+            fn from(bp: $name) -> u8 {
+                match bp {
                     $($name::$item => $num,)*
                 }
             }
@@ -447,17 +821,19 @@ prec!(BindingPower =>
     BitAnd = 6,
     Equality = 7,
     Comparison = 8,
-    BitShift = 9,
-    Term = 10,
-    Factor = 11,
-    Unary = 12,
-    Call = 13,
-    Primary = 14,
+    Range = 9,
+    BitShift = 10,
+    Term = 11,
+    Factor = 12,
+    Unary = 13,
+    Call = 14,
+    Primary = 15,
 );
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Span;
 
     #[test]
     fn precedence_ord() {
@@ -473,10 +849,10 @@ mod test {
         assert_eq!(
             ast,
             &[Stmt::Assignment {
-                name: Token::new(TokenKind::Identifier, "a", 1),
-                op: Token::new(TokenKind::PlusAssign, "+=", 1),
+                name: Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1)),
+                op: Token::new(TokenKind::PlusAssign, "+=", 1, Span::new(2, 4)),
                 value: Expr::Literal {
-                    literal: Token::new(TokenKind::Integer(3), "3", 1),
+                    literal: Token::new(TokenKind::Integer(3), "3", 1, Span::new(5, 6)),
                 }
             }]
         );
@@ -489,13 +865,13 @@ mod test {
         assert_eq!(
             ast,
             &[Stmt::Expr {
-                token: Token::new(TokenKind::Identifier, "a", 1),
+                token: Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1)),
                 expr: Expr::Path {
                     names: vec![
-                        Token::new(TokenKind::Identifier, "a", 1),
-                        Token::new(TokenKind::Identifier, "b", 1),
-                        Token::new(TokenKind::Identifier, "c", 1),
-                        Token::new(TokenKind::Identifier, "d", 1),
+                        Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1)),
+                        Token::new(TokenKind::Identifier, "b", 1, Span::new(2, 3)),
+                        Token::new(TokenKind::Identifier, "c", 1, Span::new(4, 5)),
+                        Token::new(TokenKind::Identifier, "d", 1, Span::new(6, 7)),
                     ],
                 }
             }]
@@ -510,4 +886,221 @@ mod test {
         let src = ":a";
         assert!(parse(&mut Scanner::new(src)).is_err());
     }
+
+    #[test]
+    fn call() {
+        let src = "f(1, 2)(3)";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        assert_eq!(
+            ast,
+            &[Stmt::Expr {
+                token: Token::new(TokenKind::Identifier, "f", 1, Span::new(0, 1)),
+                expr: Expr::Call {
+                    callee: Box::new(Expr::Call {
+                        callee: Box::new(Expr::Variable {
+                            variable: Token::new(TokenKind::Identifier, "f", 1, Span::new(0, 1)),
+                        }),
+                        paren: Token::new(TokenKind::RightParen, ")", 1, Span::new(6, 7)),
+                        arity: 2,
+                        args: vec![
+                            Expr::Literal {
+                                literal: Token::new(TokenKind::Integer(1), "1", 1, Span::new(2, 3)),
+                            },
+                            Expr::Literal {
+                                literal: Token::new(TokenKind::Integer(2), "2", 1, Span::new(5, 6)),
+                            },
+                        ],
+                    }),
+                    paren: Token::new(TokenKind::RightParen, ")", 1, Span::new(9, 10)),
+                    arity: 1,
+                    args: vec![Expr::Literal {
+                        literal: Token::new(TokenKind::Integer(3), "3", 1, Span::new(8, 9)),
+                    }],
+                }
+            }]
+        );
+
+        let src = "f(1 2)";
+        assert!(parse(&mut Scanner::new(src)).is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let src = "x =: a + f(b, (c))\nretn Foo(y=1).y";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        assert_eq!(ast[0].span(), Span::new(0, 18));
+        if let Stmt::Declaration { value, .. } = &ast[0] {
+            assert_eq!(value.span(), Span::new(5, 18));
+        } else {
+            panic!("not a declaration");
+        }
+        assert_eq!(ast[1].span(), Span::new(19, 34));
+    }
+
+    #[test]
+    fn recovery() {
+        let lines = |src| {
+            parse(&mut Scanner::new(src))
+                .unwrap_err()
+                .iter()
+                .map(|e| e.get_line().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // one error per mistake, in the order they appear
+        assert_eq!(
+            lines("x =: (1 + 2\ny =: 3 +\nz =: 4\n)\nretn )"),
+            &[2, 3, 4, 5]
+        );
+        assert_eq!(lines("x =: \"abc\ny =: ("), &[1]);
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(lines("a =: 99999999999999999999\nb =: )"), &[1, 2]);
+
+        // blocks recover on their own, and errors in the header of a statement still
+        // find errors in its body
+        assert_eq!(
+            lines("fn f(a,) do\n  x =: )\nend\nwhile do\n  y =: 1 +\nend\nz =: )"),
+            &[1, 2, 4, 6, 7]
+        );
+        assert_eq!(
+            lines("if x = y do\n  a =: 1\nelse\n  b =: )\nend\nend"),
+            &[1, 4, 6]
+        );
+        assert_eq!(
+            lines("data Foo do\n  x 1\n  fn f() do\n    retn +\n  end\nend"),
+            &[2, 4]
+        );
+
+        // a missing end is reported once
+        assert_eq!(lines("do\n  x =: 1\n"), &[3]);
+    }
+
+    #[test]
+    fn list_and_index() {
+        let src = "xs[0] = [1, a][-1]\n[2]";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        assert_eq!(ast.len(), 2);
+        match &ast[0] {
+            Stmt::Expr {
+                expr: Expr::SetIndex { object, value, .. },
+                ..
+            } => {
+                assert!(matches!(**object, Expr::Variable { .. }));
+                match &**value {
+                    Expr::Index { object, .. } => {
+                        assert!(matches!(&**object, Expr::List { values, .. } if values.len() == 2))
+                    }
+                    _ => panic!("not an index"),
+                }
+            }
+            _ => panic!("not a set index"),
+        }
+        // a bracket on a new line starts a list
+        assert!(matches!(
+            ast[1],
+            Stmt::Expr {
+                expr: Expr::List { .. },
+                ..
+            }
+        ));
+        assert_eq!(ast[1].span(), Span::new(19, 22));
+    }
+
+    #[test]
+    fn map_literal() {
+        let src = "m =: {a: 1, \"b\": c, d + 1: [],}";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let entries = match &ast[0] {
+            Stmt::Declaration {
+                value: Expr::Map { entries, .. },
+                ..
+            } => entries,
+            _ => panic!("not a map"),
+        };
+        assert_eq!(entries.len(), 3);
+        // bare identifier keys are literals, not variables
+        assert!(matches!(
+            &entries[0].0,
+            Expr::Literal { literal } if literal.kind == TokenKind::Identifier
+        ));
+        assert!(matches!(&entries[1].1, Expr::Variable { .. }));
+        assert!(matches!(&entries[2].0, Expr::Binary { .. }));
+        assert_eq!(ast[0].span(), Span::new(0, src.len()));
+
+        assert!(parse(&mut Scanner::new("m =: {a 1}")).is_err());
+    }
+
+    #[test]
+    fn range() {
+        let ast = parse(&mut Scanner::new("x =: a - 1...b * 2 < c")).unwrap();
+        match &ast[0] {
+            Stmt::Declaration {
+                value: Expr::Binary { lhs, op, .. },
+                ..
+            } => {
+                assert_eq!(op.kind, TokenKind::Less);
+                match &**lhs {
+                    Expr::Binary { lhs, op, rhs } => {
+                        assert_eq!(op.kind, TokenKind::InclusiveRange);
+                        assert!(matches!(**lhs, Expr::Binary { .. }));
+                        assert!(matches!(**rhs, Expr::Binary { .. }));
+                    }
+                    _ => panic!("not a range"),
+                }
+            }
+            _ => panic!("not a comparison"),
+        }
+    }
+
+    #[test]
+    fn for_in() {
+        let src = "for x in 0..n do\n  print(x)\nend";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        match &ast[0] {
+            Stmt::ForIn {
+                name,
+                iterable: Expr::Binary { op, .. },
+                body,
+                ..
+            } => {
+                assert_eq!(name.lexeme, "x");
+                assert_eq!(op.kind, TokenKind::ExclusiveRange);
+                assert_eq!(body.len(), 1);
+            }
+            _ => panic!("not a for in"),
+        }
+        assert_eq!(ast[0].span(), Span::new(0, src.len()));
+
+        // the C-style loop still works
+        let ast = parse(&mut Scanner::new("for i =: 0, i < 3, i += 1 do\nend")).unwrap();
+        assert!(matches!(ast[0], Stmt::For { .. }));
+    }
+
+    #[test]
+    fn new_and_set() {
+        let src = "Foo(x=3).y = 4";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        assert_eq!(
+            ast,
+            &[Stmt::Expr {
+                token: Token::new(TokenKind::Identifier, "Foo", 1, Span::new(0, 3)),
+                expr: Expr::Set {
+                    object: Box::new(Expr::New {
+                        name: Token::new(TokenKind::Identifier, "Foo", 1, Span::new(0, 3)),
+                        args: vec![(
+                            Token::new(TokenKind::Identifier, "x", 1, Span::new(4, 5)),
+                            Box::new(Expr::Literal {
+                                literal: Token::new(TokenKind::Integer(3), "3", 1, Span::new(6, 7)),
+                            }),
+                        )],
+                        right_paren: Token::new(TokenKind::RightParen, ")", 1, Span::new(7, 8)),
+                    }),
+                    name: Token::new(TokenKind::Identifier, "y", 1, Span::new(9, 10)),
+                    value: Box::new(Expr::Literal {
+                        literal: Token::new(TokenKind::Integer(4), "4", 1, Span::new(13, 14)),
+                    }),
+                }
+            }]
+        );
+    }
 }
@@ -1,7 +1,7 @@
 //! Contains `Scanner`, an on-demand producer of tokens.
 
 use crate::runtime::Line;
-use crate::{ErrorKind, PiccoloError, Token, TokenKind};
+use crate::{PiccoloError, ScanError, Span, Token, TokenKind};
 
 use std::collections::VecDeque;
 
@@ -17,17 +17,25 @@ use std::collections::VecDeque;
 /// parser calls `peek_token` with a maximum of 2, so the size of the token buffer
 /// is never larger than 2 tokens.
 ///
+/// Lexical errors, like unterminated strings, don't stop the scanner. It records
+/// the error, produces a [`TokenKind::Error`] in place of the bad token, and keeps
+/// going. The errors can be retrieved with [`take_errors`].
+///
 /// [`VecDeque`]: https://doc.rust-lang.org/stable/std/collections/struct.VecDeque.html
 /// [`Token`]: ../struct.Token.html
 /// [`next_token`]: ./struct.Scanner.html#method.next_token
 /// [`peek_token`]: ./struct.Scanner.html#method.peek_token
+/// [`TokenKind::Error`]: ../enum.TokenKind.html#variant.Error
+/// [`take_errors`]: ./struct.Scanner.html#method.take_errors
 #[derive(Debug)]
 pub struct Scanner<'a> {
     source: &'a [u8],
     tokens: VecDeque<Token<'a>>,
+    errors: Vec<PiccoloError>,
     start: usize,
     current: usize,
     line: Line,
+    previous_line: Line,
 }
 
 impl<'a> Scanner<'a> {
@@ -36,16 +44,31 @@ impl<'a> Scanner<'a> {
         Scanner {
             source: source.as_bytes(),
             tokens: VecDeque::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            previous_line: 1,
         }
     }
 
     #[cfg(feature = "pc-debug")]
     pub(super) fn scan_all(mut self) -> Result<Vec<Token<'a>>, PiccoloError> {
         while self.next()?.kind != TokenKind::Eof {}
-        Ok(self.tokens.drain(0..).collect())
+        match self.errors.drain(0..).next() {
+            Some(error) => Err(error),
+            None => Ok(self.tokens.drain(0..).collect()),
+        }
+    }
+
+    /// Remove and return the errors found in the tokens produced so far.
+    pub fn take_errors(&mut self) -> Vec<PiccoloError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // the line of the last token moved out of the scanner
+    pub(super) fn previous_line(&self) -> Line {
+        self.previous_line
     }
 
     /// Produce the next token, moving it out of the scanner. Returns [`TokenKind::Eof`]
@@ -57,7 +80,9 @@ impl<'a> Scanner<'a> {
             self.next()?;
         }
 
-        Ok(self.tokens.pop_front().unwrap())
+        let token = self.tokens.pop_front().unwrap();
+        self.previous_line = token.line;
+        Ok(token)
     }
 
     /// Looks ahead in the token stream, generating tokens if they do not exist.
@@ -91,6 +116,7 @@ impl<'a> Scanner<'a> {
     fn next<'b>(&'b mut self) -> Result<&'b Token<'a>, PiccoloError> {
         self.slurp_whitespace();
         if self.is_at_end() {
+            self.start = self.current;
             self.add_token(TokenKind::Eof)?;
             return Ok(&self.tokens[self.tokens.len() - 1]);
         }
@@ -257,11 +283,11 @@ impl<'a> Scanner<'a> {
                 }
             }
 
-            b'"' => self.scan_string()?,
+            b'"' => self.scan_string().unwrap_or_else(|e| self.error(e)),
 
             c => {
                 if is_digit(c) {
-                    self.scan_number()?
+                    self.scan_number().unwrap_or_else(|e| self.error(e))
                 } else if is_whitespace(c) {
                     panic!("found whitespace where there shouldn't be any");
                 } else {
@@ -292,16 +318,23 @@ impl<'a> Scanner<'a> {
                 self.line += 1;
             }
 
+            // skip the escaped character, it could be a quote
             if self.peek_char() == b'\\' {
                 self.advance_char();
-                self.line += 1;
+                if self.is_at_end() {
+                    break;
+                } else if self.peek_char() == b'\n' {
+                    self.line += 1;
+                }
             }
 
             self.advance_char();
         }
 
         if self.is_at_end() {
-            Err(PiccoloError::new(ErrorKind::UnterminatedString).line(line_start))
+            Err(PiccoloError::new(ScanError::UnterminatedString)
+                .line(line_start)
+                .span(self.span()))
         } else {
             self.advance_char();
             Ok(TokenKind::String)
@@ -326,13 +359,16 @@ impl<'a> Scanner<'a> {
         }
 
         let value = self.lexeme()?;
-        if let Ok(i) = value.parse::<i64>() {
-            Ok(TokenKind::Integer(i))
-        } else {
-            Err(PiccoloError::new(ErrorKind::InvalidNumberLiteral {
+        match value.parse::<i64>() {
+            Ok(i) => Ok(TokenKind::Integer(i)),
+            #[cfg(feature = "bigint")]
+            Err(_) => Ok(TokenKind::BigInteger),
+            #[cfg(not(feature = "bigint"))]
+            Err(_) => Err(PiccoloError::new(ScanError::InvalidNumberLiteral {
                 literal: value.to_owned(),
             })
-            .line(self.line))
+            .line(self.line)
+            .span(self.span())),
         }
     }
 
@@ -351,20 +387,31 @@ impl<'a> Scanner<'a> {
         if let Ok(f) = value.parse::<f64>() {
             Ok(TokenKind::Double(f))
         } else {
-            Err(PiccoloError::new(ErrorKind::InvalidNumberLiteral {
+            Err(PiccoloError::new(ScanError::InvalidNumberLiteral {
                 literal: value.to_owned(),
             })
-            .line(self.line))
+            .line(self.line)
+            .span(self.span()))
         }
     }
 
+    // record an error, so the bad token can be skipped over
+    fn error(&mut self, error: PiccoloError) -> TokenKind {
+        self.errors.push(error);
+        TokenKind::Error
+    }
+
     fn add_token(&mut self, kind: TokenKind) -> Result<(), PiccoloError> {
         self.tokens
-            .push_back(Token::new(kind, self.lexeme()?, self.line));
+            .push_back(Token::new(kind, self.lexeme()?, self.line, self.span()));
 
         Ok(())
     }
 
+    fn span(&self) -> Span {
+        Span::new(self.start, self.current)
+    }
+
     fn lexeme(&self) -> Result<&'a str, PiccoloError> {
         Ok(core::str::from_utf8(
             &self.source[self.start..self.current],
@@ -430,7 +477,7 @@ fn into_keyword(s: &str) -> Option<TokenKind> {
 }
 
 fn is_digit(c: u8) -> bool {
-    (b'0'..=b'9').contains(&c)
+    c.is_ascii_digit()
 }
 
 pub(super) fn is_whitespace(c: u8) -> bool {
@@ -483,7 +530,7 @@ fn is_non_identifier(c: u8) -> bool {
 
 #[cfg(test)]
 mod test {
-    use super::{Scanner, Token, TokenKind};
+    use super::{Scanner, Span, Token, TokenKind};
 
     #[test]
     fn multi_char_ops() {
@@ -548,46 +595,73 @@ mod test {
         );
     }
 
+    // the number is a valid literal with the bigint feature
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn errors() {
+        let src = "a 99999999999999999999 b \"c";
+        let mut scanner = Scanner::new(src);
+        while scanner.next().unwrap().kind != TokenKind::Eof {}
+        let tokens: Vec<TokenKind> = scanner.tokens.drain(0..).map(|t| t.kind).collect();
+
+        assert_eq!(
+            tokens,
+            &[
+                TokenKind::Identifier,
+                TokenKind::Error,
+                TokenKind::Identifier,
+                TokenKind::Error,
+                TokenKind::Eof,
+            ]
+        );
+
+        let errors = scanner.take_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].get_span(), Some(Span::new(2, 22)));
+        assert_eq!(errors[1].get_span(), Some(Span::new(25, 27)));
+        assert!(scanner.take_errors().is_empty());
+    }
+
     #[test]
     fn scanner() {
         let src = "a = 3\nio.prln(a)\n";
         let mut scanner = Scanner::new(src);
         assert_eq!(
             scanner.peek_token(0).unwrap(),
-            &Token::new(TokenKind::Identifier, "a", 1)
+            &Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1))
         );
         assert_eq!(
             scanner.peek_token(1).unwrap(),
-            &Token::new(TokenKind::Assign, "=", 1)
+            &Token::new(TokenKind::Assign, "=", 1, Span::new(2, 3))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Identifier, "a", 1)
+            Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1))
         );
 
         assert_eq!(
             scanner.peek_token(0).unwrap(),
-            &Token::new(TokenKind::Assign, "=", 1)
+            &Token::new(TokenKind::Assign, "=", 1, Span::new(2, 3))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Assign, "=", 1)
+            Token::new(TokenKind::Assign, "=", 1, Span::new(2, 3))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Integer(3), "3", 1)
+            Token::new(TokenKind::Integer(3), "3", 1, Span::new(4, 5))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Identifier, "io", 2)
+            Token::new(TokenKind::Identifier, "io", 2, Span::new(6, 8))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Period, ".", 2)
+            Token::new(TokenKind::Period, ".", 2, Span::new(8, 9))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Identifier, "prln", 2)
+            Token::new(TokenKind::Identifier, "prln", 2, Span::new(9, 13))
         );
     }
 
@@ -599,40 +673,40 @@ mod test {
 
         assert_eq!(
             scanner.peek_token(0).unwrap(),
-            &Token::new(TokenKind::Identifier, "a", 1)
+            &Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1))
         );
         assert_eq!(
             scanner.peek_token(1).unwrap(),
-            &Token::new(TokenKind::Assign, "=", 1)
+            &Token::new(TokenKind::Assign, "=", 1, Span::new(2, 3))
         );
         assert_eq!(
             scanner.peek_token(0).unwrap(),
-            &Token::new(TokenKind::Identifier, "a", 1)
+            &Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1))
         );
         assert_eq!(
             scanner.peek_token(6).unwrap(),
-            &Token::new(TokenKind::LeftParen, "(", 2)
+            &Token::new(TokenKind::LeftParen, "(", 2, Span::new(13, 14))
         );
         assert_eq!(
             scanner.peek_token(8).unwrap(),
-            &Token::new(TokenKind::RightParen, ")", 2)
+            &Token::new(TokenKind::RightParen, ")", 2, Span::new(15, 16))
         );
 
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Identifier, "a", 1)
+            Token::new(TokenKind::Identifier, "a", 1, Span::new(0, 1))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Assign, "=", 1)
+            Token::new(TokenKind::Assign, "=", 1, Span::new(2, 3))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Integer(3), "3", 1)
+            Token::new(TokenKind::Integer(3), "3", 1, Span::new(4, 5))
         );
         assert_eq!(
             scanner.next_token().unwrap(),
-            Token::new(TokenKind::Identifier, "io", 2)
+            Token::new(TokenKind::Identifier, "io", 2, Span::new(6, 8))
         );
     }
 }
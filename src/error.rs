@@ -1,108 +1,356 @@
 //! Types for dealing with errors in scanning, parsing, compiling, or executing Piccolo.
 
+use crate::compiler::Span;
 use crate::runtime::{op::Opcode, Line};
 
 use core::fmt;
+use std::sync::Arc;
 
-// TODO: impl Error for PiccoloError
 /// The main error-reporting struct.
 #[derive(Debug, Clone)]
 pub struct PiccoloError {
+    // boxed to keep results small, since almost every function in the compiler and
+    // the VM returns one
+    inner: Box<ErrorInner>,
+}
+
+#[derive(Debug, Clone)]
+struct ErrorInner {
     kind: ErrorKind,
     line: Option<Line>,
+    span: Option<Span>,
+    labels: Vec<(Span, String)>,
     file: Option<String>,
     msg: Option<String>,
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+    backtrace: Vec<StackFrame>,
 }
 
-impl PiccoloError {
-    pub fn new(kind: ErrorKind) -> Self {
-        PiccoloError {
-            kind,
-            line: None,
-            file: None,
-            msg: None,
+/// A call frame that was active when a runtime error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// Name of the function the frame was running, or `<script>` for the top level.
+    pub function: String,
+    pub file: Option<String>,
+    /// Line of the instruction the frame was running, or for frames other than the
+    /// innermost, the line of the call to the next frame.
+    pub line: Line,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "in {} at {}:{}", self.function, file, self.line),
+            None => write!(f, "in {} at line {}", self.function, self.line),
         }
     }
+}
 
-    pub fn line(self, line: Line) -> Self {
+impl PiccoloError {
+    pub fn new<K: Into<ErrorKind>>(kind: K) -> Self {
         PiccoloError {
-            line: Some(line),
-            ..self
+            inner: Box::new(ErrorInner {
+                kind: kind.into(),
+                line: None,
+                span: None,
+                labels: Vec::new(),
+                file: None,
+                msg: None,
+                source: None,
+                backtrace: Vec::new(),
+            }),
         }
     }
 
-    pub fn file(self, file: String) -> Self {
-        PiccoloError {
-            file: Some(file),
-            ..self
-        }
+    /// The kind of the error, and the phase it happened in.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner.kind
     }
 
-    pub fn msg(self, msg: &str) -> Self {
-        PiccoloError {
-            msg: Some(String::from(msg)),
-            ..self
-        }
+    /// The line the error happened on, if it's known.
+    pub fn get_line(&self) -> Option<Line> {
+        self.inner.line
     }
 
-    pub fn msg_string(self, msg: String) -> Self {
-        PiccoloError {
-            msg: Some(msg),
-            ..self
+    /// The part of the source that caused the error, if it's known.
+    pub fn get_span(&self) -> Option<Span> {
+        self.inner.span
+    }
+
+    /// The call frames that were active when the error happened, innermost first.
+    /// Empty unless the error happened at runtime.
+    pub fn backtrace(&self) -> &[StackFrame] {
+        &self.inner.backtrace
+    }
+
+    /// Set the underlying error that caused this one, returned by
+    /// [`Error::source`].
+    ///
+    /// [`Error::source`]: https://doc.rust-lang.org/std/error/trait.Error.html#method.source
+    pub fn caused_by<E: std::error::Error + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.inner.source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn line(mut self, line: Line) -> Self {
+        self.inner.line = Some(line);
+        self
+    }
+
+    /// Set the part of the source that caused the error.
+    pub fn span(mut self, span: Span) -> Self {
+        self.inner.span = Some(span);
+        self
+    }
+
+    /// Point out another part of the source that's related to the error.
+    pub fn label(mut self, span: Span, label: &str) -> Self {
+        self.inner.labels.push((span, String::from(label)));
+        self
+    }
+
+    /// Set the file the error happened in, along with any frames of the backtrace
+    /// that don't have a file yet.
+    pub fn file(mut self, file: String) -> Self {
+        for frame in self.inner.backtrace.iter_mut() {
+            if frame.file.is_none() {
+                frame.file = Some(file.clone());
+            }
         }
+        self.inner.file = Some(file);
+        self
+    }
+
+    pub fn with_backtrace(mut self, backtrace: Vec<StackFrame>) -> Self {
+        self.inner.backtrace = backtrace;
+        self
+    }
+
+    pub fn msg(mut self, msg: &str) -> Self {
+        self.inner.msg = Some(String::from(msg));
+        self
+    }
+
+    pub fn msg_string(mut self, msg: String) -> Self {
+        self.inner.msg = Some(msg);
+        self
     }
 }
 
-impl fmt::Display for PiccoloError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{line}{file}{separator}{kind}{msg}",
-            line = if self.line.is_some() {
-                format!("at line {} ", self.line.unwrap())
-            } else {
-                "".into()
+impl PiccoloError {
+    /// Format the error along with the lines of `source` it refers to, underlining
+    /// the cause of the error with carets and any labels with dashes.
+    ///
+    /// `source` must be the source the error came from. Falls back to the line
+    /// of the error, or just the error, if it doesn't have a span. The backtrace,
+    /// if any, follows the source.
+    pub fn render(&self, source: &str) -> String {
+        let mut s = format!("{}\n", self.header());
+        s.push_str(&self.excerpt(source));
+        for frame in self.inner.backtrace.iter() {
+            s.push_str(&format!("    {}\n", frame));
+        }
+        s
+    }
+
+    // the first line of the error, without the backtrace
+    fn header(&self) -> String {
+        format!(
+            "{line}{file}{separator}[{code}] {kind}{msg}",
+            line = match self.inner.line {
+                Some(line) => format!("at line {} ", line),
+                None => "".into(),
             },
-            file = if self.file.is_some() {
-                format!("in file {} ", self.file.as_ref().unwrap())
-            } else {
-                "".into()
+            file = match &self.inner.file {
+                Some(file) => format!("in file {} ", file),
+                None => "".into(),
             },
-            separator = if self.line.is_some() || self.file.is_some() {
+            separator = if self.inner.line.is_some() || self.inner.file.is_some() {
                 "- "
             } else {
                 ""
             },
-            kind = self.kind,
-            msg = if self.msg.is_some() {
-                format!(" ({})", self.msg.as_ref().unwrap())
-            } else {
-                "".into()
+            code = self.inner.kind.code(),
+            kind = self.inner.kind,
+            msg = match &self.inner.msg {
+                Some(msg) => format!(" ({})", msg),
+                None => "".into(),
             }
         )
     }
+
+    // the lines of source marked up with the span and labels of the error
+    fn excerpt(&self, source: &str) -> String {
+        let mut s = String::new();
+
+        let mut marks = Vec::new();
+        if let Some(span) = self.inner.span {
+            marks.push((span, '^', None));
+        } else if let Some(line) = self.inner.line {
+            let start = source
+                .split('\n')
+                .take(line - 1)
+                .map(|line| line.len() + 1)
+                .sum::<usize>();
+            if start <= source.len() {
+                marks.push((Span::new(start, start), ' ', None));
+            }
+        }
+        for (span, label) in self.inner.labels.iter() {
+            marks.push((*span, '-', Some(label.as_str())));
+        }
+
+        // ignore spans that don't fit the source we were given
+        let mut marks: Vec<_> = marks
+            .into_iter()
+            .filter(|(span, _, _)| {
+                span.start <= span.end
+                    && span.end <= source.len()
+                    && source.is_char_boundary(span.start)
+                    && source.is_char_boundary(span.end)
+            })
+            .map(|(span, c, label)| (Location::find(source, span), c, label))
+            .collect();
+        if marks.is_empty() {
+            return s;
+        }
+
+        // errors without a span only know their line, not a column
+        let (primary, c, _) = &marks[0];
+        s.push_str(&format!(
+            " --> {}{}{}\n",
+            match &self.inner.file {
+                Some(file) => format!("{}:", file),
+                None => String::new(),
+            },
+            primary.line,
+            match c {
+                ' ' => String::new(),
+                _ => format!(":{}", primary.column + 1),
+            },
+        ));
+
+        marks.sort_by_key(|(location, _, _)| location.line);
+        let width = marks.last().unwrap().0.line.to_string().len();
+        let gutter = " ".repeat(width);
+        s.push_str(&format!("{} |\n", gutter));
+
+        let mut previous = None;
+        for (location, c, label) in marks.iter() {
+            if previous != Some(location.line) {
+                s.push_str(&format!(
+                    "{:>width$} | {}\n",
+                    location.line,
+                    location.text,
+                    width = width
+                ));
+                previous = Some(location.line);
+            }
+
+            if *c != ' ' {
+                s.push_str(&format!(
+                    "{} | {}{}{}\n",
+                    gutter,
+                    " ".repeat(location.column),
+                    c.to_string().repeat(location.width.max(1)),
+                    match label {
+                        Some(label) => format!(" {}", label),
+                        None => String::new(),
+                    }
+                ));
+            }
+        }
+
+        s
+    }
+}
+
+// the line and column of a span, along with the text of the line it starts on
+struct Location<'a> {
+    line: Line,
+    column: usize,
+    width: usize,
+    text: &'a str,
+}
+
+impl<'a> Location<'a> {
+    fn find(source: &'a str, span: Span) -> Self {
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // spans covering several lines are only marked on the first
+        let end = span.end.min(line_start + text.len()).max(span.start);
+        Location {
+            line: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count(),
+            width: source[span.start..end].chars().count(),
+            text,
+        }
+    }
+}
+
+impl fmt::Display for PiccoloError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.header())?;
+        for frame in self.inner.backtrace.iter() {
+            write!(f, "\n    {}", frame)?;
+        }
+        Ok(())
+    }
 }
 
-// TODO: split into scan, parse, compile, runtime errors
-/// Types of errors possible in Piccolo.
+/// Types of errors possible in Piccolo, grouped by the phase they happen in.
+///
+/// Every kind of error has a stable code, which doesn't change between versions
+/// of Piccolo even if the error message does.
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
-    StackUnderflow {
-        op: Opcode,
-    },
+    Io(IoError),
+    Scan(ScanError),
+    Parse(ParseError),
+    Compile(CompileError),
+    Runtime(RuntimeError),
+    Bytecode(BytecodeError),
+}
+
+/// Errors from reading files and other input.
+#[derive(Debug, Clone)]
+pub enum IoError {
     InvalidUTF8,
     FileNotFound,
     IOError,
+}
+
+/// Errors from turning source code into tokens.
+#[derive(Debug, Clone)]
+pub enum ScanError {
     UnterminatedString,
-    UnknownFormatCode {
-        code: char,
-    },
-    InvalidNumberLiteral {
-        literal: String,
-    },
-    UnexpectedToken {
-        exp: String,
-        got: String,
+    UnknownFormatCode { code: char },
+    InvalidNumberLiteral { literal: String },
+}
+
+/// Errors from parsing tokens into an AST.
+#[derive(Debug, Clone)]
+pub enum ParseError {
+    UnexpectedToken { exp: String, got: String },
+    ExpectedExpression { got: String },
+}
+
+/// Errors from compiling an AST into bytecode.
+#[derive(Debug, Clone)]
+pub enum CompileError {
+    SyntaxError,
+    UndefinedVariable { name: String },
+}
+
+/// Errors from running bytecode.
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    StackUnderflow {
+        op: Opcode,
     },
     IncorrectType {
         exp: String,
@@ -112,62 +360,258 @@ pub enum ErrorKind {
     UndefinedVariable {
         name: String,
     },
+    IncorrectArity {
+        name: String,
+        exp: usize,
+        got: usize,
+    },
     UnknownField {
         obj: String,
         name: String,
     },
-    ExpectedExpression {
-        got: String,
-    },
     CannotClone {
         ty: String,
     },
     AssertFailed,
-    SyntaxError,
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    KeyNotFound {
+        key: String,
+    },
+    InvalidStep {
+        step: i64,
+    },
+    NotCharBoundary {
+        index: usize,
+    },
+    IntegerOverflow {
+        op: Opcode,
+    },
+    DivisionByZero {
+        op: Opcode,
+    },
+    InvalidShift {
+        amount: i64,
+    },
+    OutOfFuel,
+    DeadlineExceeded,
+    Cancelled,
+    OutOfMemory {
+        limit: String,
+    },
+    StackOverflow {
+        depth: usize,
+    },
+    ReadOnlyGlobal {
+        name: String,
+    },
 }
 
-#[rustfmt::skip]
+/// Errors from loading compiled bytecode.
+#[derive(Debug, Clone)]
+pub enum BytecodeError {
+    InvalidBytecode,
+}
+
+impl ErrorKind {
+    /// The stable code of the error, like `E0402`. The first two digits are the phase
+    /// of the error, and the last two are the kind of error within that phase.
+    #[rustfmt::skip]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Io(IoError::FileNotFound)                      => "E0001",
+            ErrorKind::Io(IoError::IOError)                           => "E0002",
+            ErrorKind::Io(IoError::InvalidUTF8)                       => "E0003",
+            ErrorKind::Scan(ScanError::UnterminatedString)            => "E0101",
+            ErrorKind::Scan(ScanError::UnknownFormatCode { .. })      => "E0102",
+            ErrorKind::Scan(ScanError::InvalidNumberLiteral { .. })   => "E0103",
+            ErrorKind::Parse(ParseError::UnexpectedToken { .. })      => "E0201",
+            ErrorKind::Parse(ParseError::ExpectedExpression { .. })   => "E0202",
+            ErrorKind::Compile(CompileError::SyntaxError)             => "E0301",
+            ErrorKind::Compile(CompileError::UndefinedVariable { .. }) => "E0302",
+            ErrorKind::Runtime(RuntimeError::StackUnderflow { .. })   => "E0401",
+            ErrorKind::Runtime(RuntimeError::IncorrectType { .. })    => "E0402",
+            ErrorKind::Runtime(RuntimeError::UndefinedVariable { .. }) => "E0403",
+            ErrorKind::Runtime(RuntimeError::IncorrectArity { .. })   => "E0404",
+            ErrorKind::Runtime(RuntimeError::UnknownField { .. })     => "E0405",
+            ErrorKind::Runtime(RuntimeError::CannotClone { .. })      => "E0406",
+            ErrorKind::Runtime(RuntimeError::AssertFailed)            => "E0407",
+            ErrorKind::Runtime(RuntimeError::IndexOutOfBounds { .. }) => "E0408",
+            ErrorKind::Runtime(RuntimeError::KeyNotFound { .. })      => "E0409",
+            ErrorKind::Runtime(RuntimeError::InvalidStep { .. })      => "E0410",
+            ErrorKind::Runtime(RuntimeError::NotCharBoundary { .. })  => "E0411",
+            ErrorKind::Runtime(RuntimeError::IntegerOverflow { .. })  => "E0412",
+            ErrorKind::Runtime(RuntimeError::DivisionByZero { .. })   => "E0413",
+            ErrorKind::Runtime(RuntimeError::InvalidShift { .. })     => "E0414",
+            ErrorKind::Runtime(RuntimeError::OutOfFuel)               => "E0415",
+            ErrorKind::Runtime(RuntimeError::DeadlineExceeded)        => "E0416",
+            ErrorKind::Runtime(RuntimeError::Cancelled)               => "E0417",
+            ErrorKind::Runtime(RuntimeError::OutOfMemory { .. })      => "E0418",
+            ErrorKind::Runtime(RuntimeError::StackOverflow { .. })    => "E0419",
+            ErrorKind::Runtime(RuntimeError::ReadOnlyGlobal { .. })   => "E0420",
+            ErrorKind::Bytecode(BytecodeError::InvalidBytecode)       => "E0501",
+        }
+    }
+}
+
+macro_rules! phase_from {
+    ($($phase:ident => $kind:ident,)*) => {
+        $(impl From<$kind> for ErrorKind {
+            fn from(kind: $kind) -> ErrorKind {
+                ErrorKind::$phase(kind)
+            }
+        })*
+    };
+}
+
+phase_from!(
+    Io => IoError,
+    Scan => ScanError,
+    Parse => ParseError,
+    Compile => CompileError,
+    Runtime => RuntimeError,
+    Bytecode => BytecodeError,
+);
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ErrorKind::StackUnderflow { op }
-                => write!(f, "Stack underflow due to {:?}", op),
-            ErrorKind::InvalidUTF8
+            ErrorKind::Io(kind) => write!(f, "{}", kind),
+            ErrorKind::Scan(kind) => write!(f, "{}", kind),
+            ErrorKind::Parse(kind) => write!(f, "{}", kind),
+            ErrorKind::Compile(kind) => write!(f, "{}", kind),
+            ErrorKind::Runtime(kind) => write!(f, "{}", kind),
+            ErrorKind::Bytecode(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoError::InvalidUTF8
                 => write!(f, "Invalid UTF-8 sequence"),
-            ErrorKind::FileNotFound
+            IoError::FileNotFound
                 => write!(f, "File not found"),
-            ErrorKind::IOError
+            IoError::IOError
                 => write!(f, "Unknown IO error occurred"),
-            ErrorKind::UnterminatedString
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::UnterminatedString
                 => write!(f, "Unterminated string"),
-            ErrorKind::UnknownFormatCode { code }
+            ScanError::UnknownFormatCode { code }
                 => write!(f, "Unknown format code '\\{}'", code),
-            ErrorKind::InvalidNumberLiteral { literal }
-                => write!(f, "Invalid number literal '{}'", literal) ,
-            ErrorKind::UnexpectedToken { exp, got }
-                => write!(f, "Unexpected token: expected {}, got {}", exp, got) ,
-            ErrorKind::IncorrectType { exp, got, op }
+            ScanError::InvalidNumberLiteral { literal }
+                => write!(f, "Invalid number literal '{}'", literal),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { exp, got }
+                => write!(f, "Unexpected token: expected {}, got {}", exp, got),
+            ParseError::ExpectedExpression { got }
+                => write!(f, "Expected expression, got {}", got),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::SyntaxError
+                => write!(f, "Syntax error"),
+            CompileError::UndefinedVariable { name }
+                => write!(f, "Undefined variable '{}'", name),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::StackUnderflow { op }
+                => write!(f, "Stack underflow due to {:?}", op),
+            RuntimeError::IncorrectType { exp, got, op }
                 => write!(f, "Incorrect type: expected {}, got {} for op {:?}", exp, got, op),
-            ErrorKind::UndefinedVariable { name }
+            RuntimeError::UndefinedVariable { name }
                 => write!(f, "Undefined variable '{}'", name),
-            ErrorKind::UnknownField { obj, name }
-                => write!(f, "Unknown field '{}' on {}", name, obj) ,
-            ErrorKind::ExpectedExpression { got }
-                => write!(f, "Expected expression, got {}", got),
-            ErrorKind::CannotClone { ty }
+            RuntimeError::IncorrectArity { name, exp, got }
+                => write!(f, "Incorrect arity: function {} expected {} arguments, got {}", name, exp, got),
+            RuntimeError::UnknownField { obj, name }
+                => write!(f, "Unknown field '{}' on {}", name, obj),
+            RuntimeError::CannotClone { ty }
                 => write!(f, "Cannot clone type {}", ty),
-            ErrorKind::AssertFailed
+            RuntimeError::AssertFailed
                 => write!(f, "Assertion failed"),
-            ErrorKind::SyntaxError
-                => write!(f, "Syntax error"),
+            RuntimeError::IndexOutOfBounds { index, len }
+                => write!(f, "Index {} out of bounds for length {}", index, len),
+            RuntimeError::KeyNotFound { key }
+                => write!(f, "Key {} not found", key),
+            RuntimeError::InvalidStep { step }
+                => write!(f, "Invalid range step {}", step),
+            RuntimeError::NotCharBoundary { index }
+                => write!(f, "Byte index {} is not a character boundary", index),
+            RuntimeError::IntegerOverflow { op }
+                => write!(f, "Integer overflow for op {:?}", op),
+            RuntimeError::DivisionByZero { op }
+                => write!(f, "Integer division by zero for op {:?}", op),
+            RuntimeError::InvalidShift { amount }
+                => write!(f, "Invalid shift amount {}", amount),
+            RuntimeError::OutOfFuel
+                => write!(f, "Ran out of fuel"),
+            RuntimeError::DeadlineExceeded
+                => write!(f, "Deadline exceeded"),
+            RuntimeError::Cancelled
+                => write!(f, "Cancelled"),
+            RuntimeError::OutOfMemory { limit }
+                => write!(f, "Out of memory, the limit is {}", limit),
+            RuntimeError::StackOverflow { depth }
+                => write!(f, "Stack overflow, the limit is {}", depth),
+            RuntimeError::ReadOnlyGlobal { name }
+                => write!(f, "Cannot assign to read-only global '{}'", name),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::InvalidBytecode
+                => write!(f, "Invalid bytecode"),
+        }
+    }
+}
+
+impl std::error::Error for PiccoloError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.inner.source {
+            Some(source) => Some(source.as_ref()),
+            None => None,
         }
     }
 }
 
 impl From<std::str::Utf8Error> for PiccoloError {
     fn from(e: std::str::Utf8Error) -> PiccoloError {
-        PiccoloError::new(ErrorKind::InvalidUTF8)
+        PiccoloError::new(IoError::InvalidUTF8)
             .msg_string(format!("valid up to {}", e.valid_up_to()))
+            .caused_by(e)
     }
 }
 
@@ -179,11 +623,12 @@ impl From<std::string::FromUtf8Error> for PiccoloError {
 
 impl From<std::io::Error> for PiccoloError {
     fn from(e: std::io::Error) -> PiccoloError {
-        match e.kind() {
-            std::io::ErrorKind::InvalidData => PiccoloError::new(ErrorKind::InvalidUTF8),
-            std::io::ErrorKind::NotFound => PiccoloError::new(ErrorKind::FileNotFound),
-            _ => PiccoloError::new(ErrorKind::IOError),
-        }
+        let kind = match e.kind() {
+            std::io::ErrorKind::InvalidData => IoError::InvalidUTF8,
+            std::io::ErrorKind::NotFound => IoError::FileNotFound,
+            _ => IoError::IOError,
+        };
+        PiccoloError::new(kind).caused_by(e)
     }
 }
 
@@ -11,15 +11,24 @@ pub extern crate log;
 pub mod compiler;
 pub mod error;
 pub mod runtime;
+pub mod session;
 
 /// Commonly used items that you might want access to.
 pub mod prelude {
     pub use super::compiler::{emitter::Emitter, parser::parse, scanner::Scanner};
-    pub use super::compiler::{Token, TokenKind};
-    pub use super::error::{ErrorKind, PiccoloError};
+    pub use super::compiler::{Span, Token, TokenKind};
+    pub use super::error::{
+        BytecodeError, CompileError, ErrorKind, IoError, ParseError, PiccoloError, RuntimeError,
+        ScanError, StackFrame,
+    };
     pub use super::runtime::{
-        chunk::Chunk, object::Object, value::Constant, value::Value, vm::Machine,
+        chunk::Chunk, object::Object, value::Constant, value::Value, vm::Arithmetic,
+        vm::CancelHandle, vm::Machine, vm::MachineConfig, vm::Policy,
     };
+    pub use super::session::Session;
+
+    #[cfg(feature = "bigint")]
+    pub use num_bigint::BigInt;
 }
 
 use prelude::*;
@@ -42,19 +51,7 @@ pub use compiler::print_tokens;
 /// # }
 /// ```
 pub fn interpret(src: &str) -> Result<Constant, Vec<PiccoloError>> {
-    let mut scanner = Scanner::new(src);
-    debug!("parse");
-    let ast = parse(&mut scanner)?;
-    debug!("ast\n{}", compiler::ast::print_ast(&ast));
-    debug!("compile");
-
-    let mut emitter = compiler::emitter::Emitter::new();
-    compiler::emitter::compile_ast(&mut emitter, &ast)?;
-    let chunk = emitter.current_chunk();
-
-    debug!("chunk\n{}", chunk.disassemble(""));
-    debug!("interpret");
-    Ok(Machine::new().interpret(&chunk)?)
+    Session::new().eval(src)
 }
 
 /// Reads a file and interprets its contents.
@@ -67,6 +64,36 @@ pub fn do_file(file: &std::path::Path) -> Result<Constant, Vec<PiccoloError>> {
     })
 }
 
+/// Compiles a source file into a bytecode file that can be run with [`run_bin`].
+///
+/// [`run_bin`]: ./fn.run_bin.html
+pub fn compile(src: &std::path::Path, out: &std::path::Path) -> Result<(), Vec<PiccoloError>> {
+    let file_name = |e: PiccoloError| e.file(src.to_str().unwrap().to_owned());
+    let contents = std::fs::read_to_string(src).map_err(|e| vec![file_name(e.into())])?;
+
+    let ast = parse(&mut Scanner::new(&contents))
+        .map_err(|v| v.into_iter().map(file_name).collect::<Vec<_>>())?;
+    let mut emitter = Emitter::new();
+    compiler::emitter::compile_ast(&mut emitter, &ast)
+        .map_err(|v| v.into_iter().map(file_name).collect::<Vec<_>>())?;
+
+    let bytes = runtime::bytecode::serialize(emitter.current_chunk());
+    std::fs::write(out, bytes).map_err(|e| vec![PiccoloError::from(e)])?;
+    Ok(())
+}
+
+/// Reads a bytecode file created by [`compile`] and runs it.
+///
+/// [`compile`]: ./fn.compile.html
+pub fn run_bin(file: &std::path::Path) -> Result<Constant, Vec<PiccoloError>> {
+    let file_name = |e: PiccoloError| e.file(file.to_str().unwrap().to_owned());
+    let bytes = std::fs::read(file).map_err(|e| vec![file_name(e.into())])?;
+    let chunk = runtime::bytecode::deserialize(&bytes).map_err(|e| vec![file_name(e)])?;
+    Machine::new()
+        .interpret(&chunk)
+        .map_err(|e| vec![file_name(e)])
+}
+
 pub(crate) fn encode_bytes(low: u8, high: u8) -> u16 {
     ((high as u16) << 8) | (low as u16)
 }
@@ -195,7 +222,7 @@ pub mod fuzzer {
 
 #[cfg(test)]
 mod integration {
-    use super::{parse, Emitter, Machine, Scanner, Token, TokenKind};
+    use super::{parse, Emitter, Machine, PiccoloError, Scanner, Span, Token, TokenKind, Value};
     use crate::compiler::ast::{self, Expr, Stmt};
     use crate::Constant;
 
@@ -206,6 +233,284 @@ mod integration {
         crate::do_file(path).unwrap();
     }
 
+    #[test]
+    fn retn() {
+        assert_eq!(
+            crate::interpret("retn 1 + 2\nassert false").unwrap(),
+            Constant::Integer(3)
+        );
+        assert_eq!(
+            crate::interpret("fn f(x) do\n retn x * 2\nend\nretn f(4)").unwrap(),
+            Constant::Integer(8)
+        );
+    }
+
+    #[test]
+    fn gc() {
+        let src = "data Node do\n next =: nil\nend\n\
+                   keep =: Node()\n\
+                   for i =: 0, i < 5000, i += 1 do\n\
+                     keep = Node(next=keep)\n\
+                     keep = keep.next\n\
+                   end\n\
+                   assert keep.next == nil";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let mut emitter = Emitter::new();
+        crate::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+
+        let mut vm = Machine::new();
+        vm.interpret(emitter.current_chunk()).unwrap();
+        assert!(vm.heap().allocated() < 2048);
+
+        // the data type and the instance in `keep` are still reachable, along with
+        // the builtin functions and methods
+        use crate::runtime::builtin::{BUILTINS, METHODS};
+        vm.gc();
+        assert_eq!(
+            vm.heap().allocated(),
+            2 + BUILTINS.len() + METHODS.iter().map(|(_, m)| m.len()).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn gc_strings() {
+        let src = "s =: \"\"\n\
+                   for i =: 0, i < 5000, i += 1 do\n\
+                     s = \"\" + i\n\
+                   end\n\
+                   assert s == \"4999\"";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let mut emitter = Emitter::new();
+        crate::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+
+        let mut vm = Machine::new();
+        vm.interpret(emitter.current_chunk()).unwrap();
+        assert!(vm.heap().interner().len() < 2048);
+    }
+
+    #[test]
+    fn register_fn() {
+        use crate::RuntimeError;
+        use std::{cell::Cell, rc::Rc};
+
+        let calls = Rc::new(Cell::new(0));
+        let mut vm = Machine::new();
+        let counter = Rc::clone(&calls);
+        vm.register_fn("add", 2, move |_, args| {
+            counter.set(counter.get() + 1);
+            match (args[0], args[1]) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Integer(l + r)),
+                _ => Err(PiccoloError::new(RuntimeError::IncorrectType {
+                    exp: "integer".into(),
+                    got: "something else".into(),
+                    op: crate::runtime::op::Opcode::Call,
+                })),
+            }
+        });
+
+        let mut run = |src: &str| {
+            let ast = parse(&mut Scanner::new(src)).unwrap();
+            let mut emitter = Emitter::new();
+            emitter.declare_global("add");
+            crate::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+            vm.interpret(emitter.current_chunk())
+        };
+
+        assert_eq!(
+            run("fn f(x) do\n retn add(x, 1)\nend\nretn add(f(1), 3)").unwrap(),
+            Constant::Integer(5)
+        );
+        assert_eq!(calls.get(), 2);
+        assert!(run("add(1)").is_err());
+        assert_eq!(calls.get(), 2);
+        assert!(run("\nadd(1, nil)")
+            .unwrap_err()
+            .to_string()
+            .contains("line 2"));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn globals() {
+        let mut vm = Machine::new();
+        let run = |vm: &mut Machine, src: &str| {
+            let ast = parse(&mut Scanner::new(src)).unwrap();
+            let mut emitter = Emitter::new();
+            emitter.declare_global("limit");
+            emitter.declare_global("total");
+            crate::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+            vm.interpret(emitter.current_chunk())
+        };
+
+        vm.set_global("limit", Constant::Integer(3));
+        run(
+            &mut vm,
+            "total =: 0\nfor i =: 0, i < limit, i += 1 do\n total += i\nend",
+        )
+        .unwrap();
+        assert_eq!(vm.get_global("total"), Some(Constant::Integer(3)));
+
+        // globals persist between chunks
+        vm.set_global("limit", Constant::String("changed".into()));
+        run(&mut vm, "total += 1\nfn f() do\nend\ndata D do\nend").unwrap();
+        assert_eq!(vm.get_global("total"), Some(Constant::Integer(4)));
+        assert_eq!(
+            vm.get_global("limit"),
+            Some(Constant::String("changed".into()))
+        );
+        assert!(matches!(vm.get_global("f"), Some(Constant::Function(_))));
+        assert_eq!(vm.get_global("D"), None);
+        assert_eq!(vm.get_global("print"), None);
+        assert_eq!(vm.get_global("nonexistent"), None);
+
+        let mut names: Vec<_> = vm.globals().map(|(name, _)| name.to_owned()).collect();
+        names.sort();
+        assert_eq!(names, vec!["f", "limit", "total"]);
+
+        assert_eq!(vm.remove_global("total"), Some(Constant::Integer(4)));
+        assert_eq!(vm.get_global("total"), None);
+        assert!(run(&mut vm, "total += 1").is_err());
+    }
+
+    #[test]
+    fn captured_closures() {
+        let mut vm = Machine::new();
+        let run = |vm: &mut Machine, src: &str| {
+            let ast = parse(&mut Scanner::new(src)).unwrap();
+            let mut emitter = Emitter::new();
+            emitter.declare_global("mk");
+            emitter.declare_global("g");
+            crate::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+            vm.interpret(emitter.current_chunk())
+        };
+        run(
+            &mut vm,
+            "fn mk() do\n  x =: 1\n  fn f() do\n    retn x\n  end\n  retn f\nend",
+        )
+        .unwrap();
+
+        // a closure that has captured variables can't be a constant
+        let f = run(&mut vm, "retn mk()").unwrap();
+        assert_eq!(f, Constant::Nil);
+        run(&mut vm, "g =: mk()").unwrap();
+        assert_eq!(vm.get_global("g"), None);
+        assert_eq!(
+            run(&mut vm, "retn [mk()]").unwrap(),
+            Constant::List(vec![Constant::Nil])
+        );
+
+        vm.set_global("g", f);
+        assert!(run(&mut vm, "retn g()").is_err());
+    }
+
+    #[test]
+    fn render_errors() {
+        let src = "x =: 1\ny =: (x + 2\nprint(y)\n";
+        let errors = crate::interpret(src).unwrap_err();
+        assert_eq!(
+            errors[0].render(src),
+            "at line 3 - [E0201] Unexpected token: expected RightParen, got Identifier\n \
+             --> 3:1\n  \
+               |\n\
+             2 | y =: (x + 2\n  \
+               |      - in expression starting here\n\
+             3 | print(y)\n  \
+               | ^^^^^\n"
+        );
+
+        let src = "longer_name =: 1\nlonger_name + (1 + long_name)\n";
+        let errors = crate::interpret(src).unwrap_err();
+        let rendered = errors[0].render(src);
+        assert!(rendered.contains(" --> 2:20\n"));
+        assert!(rendered.contains("\n  |                    ^^^^^^^^^\n"));
+        assert!(rendered.contains("\n  |               - in expression starting here\n"));
+
+        // runtime errors only know their line, and are followed by a backtrace
+        let src = "a =: 1\nb =: a + nil";
+        let errors = crate::interpret(src).unwrap_err();
+        assert_eq!(
+            errors[0].render(src),
+            "at line 2 - [E0402] Incorrect type: expected integer or double, got integer + nil for op Add\n \
+             --> 2\n  \
+               |\n\
+             2 | b =: a + nil\n    \
+                 in <script> at line 2\n"
+        );
+    }
+
+    #[test]
+    fn backtrace() {
+        use crate::StackFrame;
+
+        let src = "fn inner(x) do\n  retn x + nil\nend\n\
+                   fn outer() do\n  y =: 1\n  retn inner(y)\nend\n\
+                   outer()\n";
+        let error = crate::interpret(src).unwrap_err().remove(0);
+        let frame = |function: &str, line| StackFrame {
+            function: String::from(function),
+            file: None,
+            line,
+        };
+        assert_eq!(
+            error.backtrace(),
+            &[frame("inner", 2), frame("outer", 6), frame("<script>", 8)]
+        );
+        assert!(error.to_string().ends_with(
+            "\n    in inner at line 2\n    in outer at line 6\n    in <script> at line 8"
+        ));
+
+        let error = error.file(String::from("test.pc"));
+        assert_eq!(error.backtrace()[1].file.as_deref(), Some("test.pc"));
+        assert!(error.to_string().contains("\n    in outer at test.pc:6\n"));
+
+        // the error and its innermost frame agree, even when the failing instruction
+        // is the last one on its line
+        let src = "fn mk() do\n  y =: 1\n  x =: y + nil\n  retn x\nend\nmk()\n";
+        let error = crate::interpret(src).unwrap_err().remove(0);
+        assert_eq!(error.get_line(), Some(3));
+        assert_eq!(error.backtrace(), &[frame("mk", 3), frame("<script>", 6)]);
+
+        // errors that don't happen at runtime don't have one
+        let error = crate::interpret("x =: (1").unwrap_err().remove(0);
+        assert!(error.backtrace().is_empty());
+    }
+
+    #[test]
+    fn error_kinds() {
+        use crate::{CompileError, ErrorKind, IoError, ParseError, RuntimeError, ScanError};
+
+        let kind = |src| crate::interpret(src).unwrap_err()[0].kind().clone();
+        assert!(matches!(
+            kind("x =: \"abc"),
+            ErrorKind::Scan(ScanError::UnterminatedString)
+        ));
+        assert!(matches!(
+            kind("x =: (1"),
+            ErrorKind::Parse(ParseError::UnexpectedToken { .. })
+        ));
+        assert!(matches!(
+            kind("x =: y"),
+            ErrorKind::Compile(CompileError::UndefinedVariable { .. })
+        ));
+        assert!(matches!(
+            kind("assert false"),
+            ErrorKind::Runtime(RuntimeError::AssertFailed)
+        ));
+        assert_eq!(kind("assert false").code(), "E0407");
+
+        // hosts can use ? with other error types
+        fn host() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let errors = crate::do_file(std::path::Path::new("does/not/exist.pc")).unwrap_err();
+            Err(errors.into_iter().next().unwrap())?;
+            Ok(())
+        }
+        let e = host().unwrap_err();
+        let e = e.downcast_ref::<PiccoloError>().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Io(IoError::FileNotFound)));
+        let source = std::error::Error::source(e).unwrap();
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
+
     #[test]
     fn encode_decode() {
         let bytes: u16 = 0xbead;
@@ -245,22 +550,22 @@ mod integration {
                 &Expr::Binary {
                     lhs: Box::new(Expr::Binary {
                         lhs: Box::new(Expr::Literal {
-                            literal: Token::new(TokenKind::Integer(1), "1", 1)
+                            literal: Token::new(TokenKind::Integer(1), "1", 1, Span::new(0, 1))
                         }),
-                        op: Token::new(TokenKind::Plus, "+", 1),
+                        op: Token::new(TokenKind::Plus, "+", 1, Span::new(1, 2)),
                         rhs: Box::new(Expr::Binary {
                             lhs: Box::new(Expr::Literal {
-                                literal: Token::new(TokenKind::Integer(2), "2", 1)
+                                literal: Token::new(TokenKind::Integer(2), "2", 1, Span::new(2, 3))
                             }),
-                            op: Token::new(TokenKind::Multiply, "*", 1),
+                            op: Token::new(TokenKind::Multiply, "*", 1, Span::new(3, 4)),
                             rhs: Box::new(Expr::Literal {
-                                literal: Token::new(TokenKind::Integer(3), "3", 1)
+                                literal: Token::new(TokenKind::Integer(3), "3", 1, Span::new(4, 5))
                             })
                         })
                     }),
-                    op: Token::new(TokenKind::Plus, "+", 1),
+                    op: Token::new(TokenKind::Plus, "+", 1, Span::new(5, 6)),
                     rhs: Box::new(Expr::Literal {
-                        literal: Token::new(TokenKind::Integer(4), "4", 1)
+                        literal: Token::new(TokenKind::Integer(4), "4", 1, Span::new(6, 7))
                     }),
                 }
             );
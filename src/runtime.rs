@@ -1,10 +1,15 @@
 //! Modules for the runtime representation and interpretation of Piccolo bytecode.
 
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod builtin;
+pub mod bytecode;
 pub mod chunk;
 pub mod memory;
 pub mod object;
 pub mod op;
 pub mod value;
+pub mod verify;
 pub mod vm;
 
 pub type ConstantIdx = u16;
@@ -0,0 +1,364 @@
+//! Arbitrary-precision integers, enabled by the `bigint` feature.
+//!
+//! Integers too large for an `i64` are [`BigInteger`] objects on the [`Heap`]. Integer
+//! arithmetic that overflows produces one, and any result that fits in an `i64` again
+//! becomes a plain integer, so a `BigInteger` never holds a value that would fit.
+//!
+//! [`BigInteger`]: ./struct.BigInteger.html
+//! [`Heap`]: ../memory/struct.Heap.html
+
+use crate::runtime::{
+    memory::Heap,
+    object::{Object, ObjectKind},
+    op::Opcode,
+    value::Value,
+};
+use crate::RuntimeError;
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+
+use core::cmp::Ordering;
+use core::fmt;
+
+/// How many bits a big integer can have. Arithmetic that would make a larger one is
+/// an [`OutOfMemory`] error, since shifting or squaring could otherwise use up memory
+/// in a handful of operations.
+///
+/// [`OutOfMemory`]: ../../error/enum.RuntimeError.html#variant.OutOfMemory
+pub const MAX_BITS: u64 = 1 << 20;
+
+/// An integer that doesn't fit in an `i64`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigInteger(BigInt);
+
+impl BigInteger {
+    pub fn value(&self) -> &BigInt {
+        &self.0
+    }
+}
+
+impl Object for BigInteger {
+    fn type_name(&self) -> &'static str {
+        "integer"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::BigInteger
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Object>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+impl fmt::Display for BigInteger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Store an integer as a plain integer if it fits in an `i64`, or on the heap if not.
+pub(crate) fn alloc(heap: &mut Heap, value: BigInt) -> Value {
+    match value.to_i64() {
+        Some(value) => Value::Integer(value),
+        None => heap.alloc(Box::new(BigInteger(value))),
+    }
+}
+
+/// Get the value of an integer, big or not.
+pub(crate) fn get(heap: &Heap, value: Value) -> Option<BigInt> {
+    match value {
+        Value::Integer(v) => Some(BigInt::from(v)),
+        Value::Object(_) => heap
+            .deref(value)
+            .downcast_ref::<BigInteger>()
+            .map(|v| v.0.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_big(heap: &Heap, value: Value) -> bool {
+    value.is_object() && heap.deref(value).is::<BigInteger>()
+}
+
+fn to_f64(heap: &Heap, value: Value) -> Option<f64> {
+    match value {
+        Value::Double(v) => Some(v),
+        _ => get(heap, value)?.to_f64(),
+    }
+}
+
+/// Apply `op` to two numbers when at least one is a big integer. Returns `None` if
+/// neither is, or if the other isn't a number, so the caller can report the error.
+/// `rhs` is ignored when negating.
+pub(crate) fn binary(
+    heap: &mut Heap,
+    op: Opcode,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Option<Value>, RuntimeError> {
+    if !is_big(heap, lhs) && !is_big(heap, rhs) {
+        return Ok(None);
+    }
+
+    if lhs.is_double() || rhs.is_double() {
+        let (lhs, rhs) = match (to_f64(heap, lhs), to_f64(heap, rhs)) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => return Ok(None),
+        };
+        return Ok(Some(Value::Double(match op {
+            Opcode::Add => lhs + rhs,
+            Opcode::Subtract => lhs - rhs,
+            Opcode::Multiply => lhs * rhs,
+            Opcode::Divide => lhs / rhs,
+            Opcode::Modulo => lhs % rhs,
+            _ => return Ok(None),
+        })));
+    }
+
+    match (get(heap, lhs), get(heap, rhs)) {
+        (Some(lhs), Some(rhs)) => integer(heap, op, lhs, rhs).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Apply `op` to two integers, which may be big.
+pub(crate) fn integer(
+    heap: &mut Heap,
+    op: Opcode,
+    lhs: BigInt,
+    rhs: BigInt,
+) -> Result<Value, RuntimeError> {
+    let too_big = || RuntimeError::OutOfMemory {
+        limit: format!("{} bits in an integer", MAX_BITS),
+    };
+    let value = match op {
+        Opcode::Add => lhs + rhs,
+        Opcode::Subtract => lhs - rhs,
+        // check the size before multiplying or shifting, which could take a while
+        Opcode::Multiply if lhs.bits() + rhs.bits() > MAX_BITS + 1 => return Err(too_big()),
+        Opcode::Multiply => lhs * rhs,
+        Opcode::Divide | Opcode::Modulo if rhs.is_zero() => {
+            return Err(RuntimeError::DivisionByZero { op });
+        }
+        Opcode::Divide => lhs / rhs,
+        Opcode::Modulo => lhs % rhs,
+        Opcode::Negate => -lhs,
+        Opcode::BitAnd => lhs & rhs,
+        Opcode::BitOr => lhs | rhs,
+        Opcode::BitXor => lhs ^ rhs,
+        Opcode::ShiftLeft | Opcode::ShiftRight => {
+            let amount = rhs.to_u32().ok_or_else(|| RuntimeError::InvalidShift {
+                amount: rhs.to_i64().unwrap_or(match rhs.sign() {
+                    Sign::Minus => i64::MIN,
+                    _ => i64::MAX,
+                }),
+            })?;
+            if op == Opcode::ShiftLeft {
+                if !lhs.is_zero() && lhs.bits() + u64::from(amount) > MAX_BITS {
+                    return Err(too_big());
+                }
+                lhs << amount
+            } else {
+                lhs >> amount
+            }
+        }
+        _ => unreachable!("big integer op {:?}", op),
+    };
+    if value.bits() > MAX_BITS {
+        return Err(too_big());
+    }
+
+    Ok(alloc(heap, value))
+}
+
+/// Compare two numbers when one is a big integer and the other is a number. Returns
+/// `None` otherwise, or `Some(None)` when comparing with NaN.
+pub(crate) fn compare(heap: &Heap, lhs: Value, rhs: Value) -> Option<Option<Ordering>> {
+    if !is_big(heap, lhs) && !is_big(heap, rhs) {
+        return None;
+    }
+
+    match (get(heap, lhs), get(heap, rhs)) {
+        (Some(lhs), Some(rhs)) => Some(Some(lhs.cmp(&rhs))),
+        (Some(lhs), None) if rhs.is_double() => Some(compare_double(&lhs, rhs.into())),
+        (None, Some(rhs)) if lhs.is_double() => {
+            Some(compare_double(&rhs, lhs.into()).map(Ordering::reverse))
+        }
+        _ => None,
+    }
+}
+
+fn compare_double(lhs: &BigInt, rhs: f64) -> Option<Ordering> {
+    if rhs.is_nan() {
+        None
+    } else if rhs.is_infinite() {
+        Some(if rhs > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        })
+    } else {
+        // a double large enough to equal a big integer has no fractional part, and
+        // one with a fractional part is too small to be anywhere near one
+        BigInt::from_f64(rhs.trunc()).map(|rhs| lhs.cmp(&rhs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Arithmetic, Constant, Session};
+
+    use num_bigint::BigInt;
+
+    fn big(s: &str) -> Constant {
+        Constant::BigInt(s.parse::<BigInt>().unwrap())
+    }
+
+    #[test]
+    fn promote_and_demote() {
+        let mut session = Session::new();
+        assert_eq!(session.machine().arithmetic(), Arithmetic::BigInt);
+
+        session.eval("max =: 9223372036854775807").unwrap();
+        assert_eq!(
+            session.eval("retn max + 1").unwrap(),
+            big("9223372036854775808")
+        );
+        assert_eq!(
+            session.eval("retn max * max").unwrap(),
+            big("85070591730234615847396907784232501249")
+        );
+        assert_eq!(
+            session.eval("retn (max + 1) - 1").unwrap(),
+            Constant::Integer(i64::MAX)
+        );
+        assert_eq!(
+            session.eval("retn -(max + 1)").unwrap(),
+            Constant::Integer(i64::MIN)
+        );
+        assert_eq!(
+            session.eval("retn (max + 1) / 2.0").unwrap(),
+            Constant::Double(2f64.powi(62))
+        );
+        assert!(session.eval("retn (max + 1) % 0").is_err());
+    }
+
+    #[test]
+    fn shifts() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.eval("retn 3 << 63").unwrap(),
+            big("27670116110564327424")
+        );
+        assert_eq!(
+            session.eval("retn 1 << 100").unwrap(),
+            big("1267650600228229401496703205376")
+        );
+        assert_eq!(
+            session.eval("retn (1 << 100) >> 99").unwrap(),
+            Constant::Integer(2)
+        );
+        assert_eq!(
+            session.eval("retn -1 >> 100").unwrap(),
+            Constant::Integer(-1)
+        );
+        assert_eq!(
+            session.eval("retn 1 << 62").unwrap(),
+            Constant::Integer(1 << 62)
+        );
+        assert!(session.eval("retn (1 << 100) << -1").is_err());
+        assert!(session.eval("retn 1 << -1").is_err());
+    }
+
+    #[test]
+    fn max_bits() {
+        use crate::{ErrorKind, RuntimeError};
+
+        let out_of_memory = |src: &str| {
+            let errors = Session::new().eval(src).unwrap_err();
+            matches!(
+                errors[0].kind(),
+                ErrorKind::Runtime(RuntimeError::OutOfMemory { .. })
+            )
+        };
+
+        let mut session = Session::new();
+        assert_eq!(
+            session
+                .eval("retn ((1 << 1048575) >> 1048574) + 1")
+                .unwrap(),
+            Constant::Integer(3)
+        );
+        assert!(out_of_memory("retn 1 << 1048576"));
+        assert!(out_of_memory("retn 3 << 1048575"));
+        assert!(out_of_memory("retn (1 << 4294967295) >> 4294967295"));
+        assert!(out_of_memory("retn -(1 << 1048575) - (1 << 1048575)"));
+
+        // squaring doubles the bits every time
+        assert!(out_of_memory(
+            "x =: 1 << 100\nwhile true do\n  x = x * x\nend"
+        ));
+        assert!(out_of_memory("x =: 3\nwhile true do\n  x = x * x\nend"));
+    }
+
+    #[test]
+    fn literals() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.eval("retn 100000000000000000000").unwrap(),
+            big("100000000000000000000")
+        );
+        assert_eq!(
+            session.eval("retn -9223372036854775808").unwrap(),
+            Constant::Integer(i64::MIN)
+        );
+        assert_eq!(
+            session
+                .eval("retn [100000000000000000000 - 1]")
+                .unwrap()
+                .to_string(),
+            "[99999999999999999999]"
+        );
+    }
+
+    #[test]
+    fn compare() {
+        let mut session = Session::new();
+        session.eval("big =: 100000000000000000000").unwrap();
+        for (src, result) in [
+            ("big == 100000000000000000000", true),
+            ("big == 100000000000000000000.0", true),
+            ("big > 9223372036854775807", true),
+            ("big < 1.5", false),
+            ("-big < 1.5", true),
+            ("big == 0.0 / 0.0", false),
+            ("{big + 0: 1}[100000000000000000000.0] == 1", true),
+        ] {
+            assert_eq!(
+                session.eval(&format!("retn {}", src)).unwrap(),
+                Constant::Bool(result),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn host_values() {
+        let mut session = Session::new();
+        session
+            .machine()
+            .set_global("x", big("-100000000000000000000"));
+        session.eval("y =: x * -1").unwrap();
+        assert_eq!(
+            session.machine().get_global("y"),
+            Some(big("100000000000000000000"))
+        );
+
+        // small values are always plain integers
+        session.machine().set_global("x", big("2"));
+        assert_eq!(session.eval("retn x").unwrap(), Constant::Integer(2));
+    }
+}
@@ -0,0 +1,479 @@
+//! Functions built in to every Piccolo [`Machine`].
+//!
+//! [`Machine`]: ../vm/struct.Machine.html
+
+use crate::runtime::StringPtr;
+use crate::runtime::{
+    memory::Heap,
+    object::{List, Map, MapKey, Range},
+    op::Opcode,
+};
+use crate::{PiccoloError, RuntimeError, Value};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::convert::TryFrom;
+
+type Builtin = fn(&mut Heap, &[Value]) -> Result<Value, PiccoloError>;
+type Methods = &'static [(&'static str, usize, Builtin)];
+
+/// Names, arities, and implementations of the builtin functions.
+pub(crate) const BUILTINS: &[(&str, usize, Builtin)] = &[("print", 1, print)];
+
+/// Names of types, along with the names, arities, and implementations of their
+/// builtin methods. The value the method was called on is the first argument, and
+/// isn't counted in the arity.
+pub(crate) const METHODS: &[(&str, Methods)] = &[
+    (
+        "list",
+        &[
+            ("len", 0, list_len),
+            ("push", 1, list_push),
+            ("pop", 0, list_pop),
+            ("insert", 2, list_insert),
+            ("remove", 1, list_remove),
+            ("slice", 2, list_slice),
+        ],
+    ),
+    (
+        "map",
+        &[
+            ("len", 0, map_len),
+            ("has", 1, map_has),
+            ("remove", 1, map_remove),
+            ("keys", 0, map_keys),
+            ("values", 0, map_values),
+        ],
+    ),
+    (
+        "range",
+        &[
+            ("len", 0, range_len),
+            ("has", 1, range_has),
+            ("by", 1, range_by),
+            ("values", 0, range_values),
+        ],
+    ),
+    (
+        "string",
+        &[
+            ("len", 0, string_len),
+            ("egc_len", 0, string_egc_len),
+            ("chars", 0, string_chars),
+            ("bytes", 0, string_bytes),
+            ("split", 1, string_split),
+            ("trim", 0, string_trim),
+            ("find", 1, string_find),
+            ("replace", 2, string_replace),
+            ("starts_with", 1, string_starts_with),
+            ("ends_with", 1, string_ends_with),
+            ("upper", 0, string_upper),
+            ("lower", 0, string_lower),
+            ("repeat", 1, string_repeat),
+            ("slice", 1, string_slice),
+            ("egc_slice", 1, string_egc_slice),
+        ],
+    ),
+];
+
+fn print(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    println!("{}", heap.fmt(&args[0]));
+    Ok(Value::Nil)
+}
+
+fn incorrect_type(heap: &Heap, exp: &str, got: &Value) -> PiccoloError {
+    PiccoloError::new(RuntimeError::IncorrectType {
+        exp: exp.into(),
+        got: heap.type_name(got).to_owned(),
+        op: Opcode::Call,
+    })
+}
+
+fn integer(heap: &Heap, value: &Value) -> Result<i64, PiccoloError> {
+    match value {
+        Value::Integer(v) => Ok(*v),
+        _ => Err(incorrect_type(heap, "integer", value)),
+    }
+}
+
+fn list<'a>(heap: &'a mut Heap, value: &Value) -> Result<&'a mut List, PiccoloError> {
+    if value.is_object() && heap.deref(*value).downcast_ref::<List>().is_some() {
+        Ok(heap.deref_mut(*value).downcast_mut::<List>().unwrap())
+    } else {
+        Err(incorrect_type(heap, "list", value))
+    }
+}
+
+fn map<'a>(heap: &'a mut Heap, value: &Value) -> Result<&'a mut Map, PiccoloError> {
+    if value.is_object() && heap.deref(*value).downcast_ref::<Map>().is_some() {
+        Ok(heap.deref_mut(*value).downcast_mut::<Map>().unwrap())
+    } else {
+        Err(incorrect_type(heap, "map", value))
+    }
+}
+
+fn range(heap: &Heap, value: &Value) -> Result<Range, PiccoloError> {
+    if value.is_object() {
+        if let Some(range) = heap.deref(*value).downcast_ref::<Range>() {
+            return Ok(*range);
+        }
+    }
+    Err(incorrect_type(heap, "range", value))
+}
+
+fn string(heap: &Heap, value: &Value) -> Result<StringPtr, PiccoloError> {
+    match value {
+        Value::String(ptr) => Ok(*ptr),
+        _ => Err(incorrect_type(heap, "string", value)),
+    }
+}
+
+fn str<'a>(heap: &'a Heap, value: &Value) -> Result<&'a str, PiccoloError> {
+    Ok(heap.interner().lookup(string(heap, value)?))
+}
+
+fn list_len(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    Ok(Value::Integer(list(heap, &args[0])?.values().len() as i64))
+}
+
+fn list_push(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    list(heap, &args[0])?.values_mut().push(args[1]);
+    heap.add_items(1);
+    Ok(Value::Nil)
+}
+
+fn list_pop(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let list = list(heap, &args[0])?;
+    let last = list.index(-1)?;
+    Ok(list.values_mut().remove(last))
+}
+
+fn list_insert(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let index = integer(heap, &args[1])?;
+    let list = list(heap, &args[0])?;
+    let index = list.boundary(index)?;
+    list.values_mut().insert(index, args[2]);
+    heap.add_items(1);
+    Ok(Value::Nil)
+}
+
+fn list_remove(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let index = integer(heap, &args[1])?;
+    let list = list(heap, &args[0])?;
+    let index = list.index(index)?;
+    Ok(list.values_mut().remove(index))
+}
+
+fn list_slice(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let start = integer(heap, &args[1])?;
+    let end = integer(heap, &args[2])?;
+    let list = list(heap, &args[0])?;
+    let start = list.boundary(start)?;
+    let end = list.boundary(end)?.max(start);
+    let values = list.values()[start..end].to_vec();
+    Ok(heap.alloc(Box::new(List::new(values))))
+}
+
+fn map_len(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    Ok(Value::Integer(map(heap, &args[0])?.entries().len() as i64))
+}
+
+fn map_has(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let key = MapKey::new(heap, args[1], Opcode::Call)?;
+    Ok(Value::Bool(map(heap, &args[0])?.get(key).is_some()))
+}
+
+fn map_remove(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let key = MapKey::new(heap, args[1], Opcode::Call)?;
+    match map(heap, &args[0])?.remove(key) {
+        Some(value) => Ok(value),
+        None => Err(PiccoloError::new(RuntimeError::KeyNotFound {
+            key: heap.repr(&args[1]),
+        })),
+    }
+}
+
+fn map_keys(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let keys = map(heap, &args[0])?
+        .entries()
+        .iter()
+        .map(|(key, _)| *key)
+        .collect();
+    Ok(heap.alloc(Box::new(List::new(keys))))
+}
+
+fn map_values(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let values = map(heap, &args[0])?
+        .entries()
+        .iter()
+        .map(|(_, value)| *value)
+        .collect();
+    Ok(heap.alloc(Box::new(List::new(values))))
+}
+
+fn range_len(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    // a range over all of i64 has one more value than i64::MAX
+    let len = range(heap, &args[0])?.len();
+    Ok(Value::Integer(i64::try_from(len).unwrap_or(i64::MAX)))
+}
+
+fn range_has(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let range = range(heap, &args[0])?;
+    Ok(Value::Bool(match args[1] {
+        Value::Integer(v) => range.contains(v),
+        // only doubles with an integer value can be in a range
+        Value::Double(v) => v as i64 as f64 == v && range.contains(v as i64),
+        _ => false,
+    }))
+}
+
+fn range_by(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let step = integer(heap, &args[1])?;
+    let range = range(heap, &args[0])?.by(step)?;
+    Ok(heap.alloc(Box::new(range)))
+}
+
+fn range_values(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let range = range(heap, &args[0])?;
+    heap.check_items(range.len())?;
+    let values = (0..range.len())
+        .map_while(|n| range.nth(n))
+        .map(Value::Integer)
+        .collect();
+    Ok(heap.alloc(Box::new(List::new(values))))
+}
+
+// the byte positions of a range that counts up by one
+fn string_bounds(
+    heap: &Heap,
+    value: &Value,
+    len: usize,
+) -> Result<core::ops::Range<usize>, PiccoloError> {
+    let range = range(heap, value)?;
+    if range.step() != 1 {
+        return Err(PiccoloError::new(RuntimeError::InvalidStep {
+            step: range.step(),
+        }));
+    }
+    range.bounds(len)
+}
+
+fn substrings(heap: &mut Heap, string: StringPtr, ranges: Vec<core::ops::Range<usize>>) -> Value {
+    let values = ranges
+        .into_iter()
+        .map(|range| heap.alloc_substring(string, range))
+        .collect();
+    heap.alloc(Box::new(List::new(values)))
+}
+
+fn string_len(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    Ok(Value::Integer(str(heap, &args[0])?.len() as i64))
+}
+
+fn string_egc_len(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    Ok(Value::Integer(
+        str(heap, &args[0])?.graphemes(true).count() as i64
+    ))
+}
+
+fn string_chars(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let string = string(heap, &args[0])?;
+    let ranges = heap
+        .interner()
+        .lookup(string)
+        .char_indices()
+        .map(|(i, c)| i..i + c.len_utf8())
+        .collect();
+    Ok(substrings(heap, string, ranges))
+}
+
+fn string_bytes(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let bytes = str(heap, &args[0])?
+        .bytes()
+        .map(|b| Value::Integer(b as i64))
+        .collect();
+    Ok(heap.alloc(Box::new(List::new(bytes))))
+}
+
+fn string_split(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let string = string(heap, &args[0])?;
+    let separator = str(heap, &args[1])?;
+    if separator.is_empty() {
+        return Err(incorrect_type(heap, "non-empty string", &args[1]));
+    }
+
+    let s = heap.interner().lookup(string);
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (i, _) in s.match_indices(separator) {
+        ranges.push(start..i);
+        start = i + separator.len();
+    }
+    ranges.push(start..s.len());
+    Ok(substrings(heap, string, ranges))
+}
+
+fn string_trim(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let string = string(heap, &args[0])?;
+    let s = heap.interner().lookup(string);
+    let start = s.len() - s.trim_start().len();
+    let end = s.trim_end().len().max(start);
+    Ok(heap.alloc_substring(string, start..end))
+}
+
+fn string_find(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let needle = str(heap, &args[1])?;
+    Ok(match str(heap, &args[0])?.find(needle) {
+        Some(i) => Value::Integer(i as i64),
+        None => Value::Nil,
+    })
+}
+
+fn string_replace(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let from = str(heap, &args[1])?;
+    let to = str(heap, &args[2])?;
+    let s = str(heap, &args[0])?;
+    // replacing every character with a long string could be huge
+    let matches = if from.is_empty() {
+        s.chars().count() + 1
+    } else {
+        s.matches(from).count()
+    };
+    heap.check_string_len(s.len().saturating_add(matches.saturating_mul(to.len())))?;
+    let replaced = s.replace(from, to);
+    Ok(heap.alloc_string(&replaced))
+}
+
+fn string_starts_with(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let prefix = str(heap, &args[1])?;
+    Ok(Value::Bool(str(heap, &args[0])?.starts_with(prefix)))
+}
+
+fn string_ends_with(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let suffix = str(heap, &args[1])?;
+    Ok(Value::Bool(str(heap, &args[0])?.ends_with(suffix)))
+}
+
+fn string_upper(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let upper = str(heap, &args[0])?.to_uppercase();
+    Ok(heap.alloc_string(&upper))
+}
+
+fn string_lower(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let lower = str(heap, &args[0])?.to_lowercase();
+    Ok(heap.alloc_string(&lower))
+}
+
+fn string_repeat(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let times = integer(heap, &args[1])?;
+    if times < 0 {
+        return Err(incorrect_type(heap, "non-negative integer", &args[1]));
+    }
+    let s = str(heap, &args[0])?;
+    // strings can't be longer than isize::MAX bytes, even without a limit
+    let len = s
+        .len()
+        .checked_mul(times as usize)
+        .filter(|&len| len <= isize::MAX as usize)
+        .ok_or_else(|| {
+            PiccoloError::new(RuntimeError::OutOfMemory {
+                limit: format!("{} bytes in a string", isize::MAX),
+            })
+        })?;
+    heap.check_string_len(len)?;
+    let repeated = s.repeat(times as usize);
+    Ok(heap.alloc_string(&repeated))
+}
+
+/// Slice a string by bytes, with a range like `0..3`. Also used for indexing strings.
+pub(crate) fn string_slice(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let string = string(heap, &args[0])?;
+    let s = heap.interner().lookup(string);
+    let bounds = string_bounds(heap, &args[1], s.len())?;
+    for index in [bounds.start, bounds.end] {
+        if !s.is_char_boundary(index) {
+            return Err(PiccoloError::new(RuntimeError::NotCharBoundary { index }));
+        }
+    }
+    Ok(heap.alloc_substring(string, bounds))
+}
+
+fn string_egc_slice(heap: &mut Heap, args: &[Value]) -> Result<Value, PiccoloError> {
+    let string = string(heap, &args[0])?;
+    let s = heap.interner().lookup(string);
+    // the byte offset of every grapheme, and of the end of the string
+    let offsets: Vec<usize> = s
+        .grapheme_indices(true)
+        .map(|(i, _)| i)
+        .chain(Some(s.len()))
+        .collect();
+    let bounds = string_bounds(heap, &args[1], offsets.len() - 1)?;
+    Ok(heap.alloc_substring(string, offsets[bounds.start]..offsets[bounds.end]))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ErrorKind, Machine, MachineConfig, PiccoloError, RuntimeError, Session};
+
+    #[test]
+    fn limits() {
+        let run = |config: &MachineConfig, src: &str| -> Vec<PiccoloError> {
+            Session::with_machine(Machine::with_config(config.clone()))
+                .eval(src)
+                .unwrap_err()
+        };
+        let out_of_memory = |errors: Vec<PiccoloError>| {
+            matches!(
+                errors[0].kind(),
+                ErrorKind::Runtime(RuntimeError::OutOfMemory { .. })
+            )
+        };
+
+        let objects = MachineConfig {
+            max_objects: Some(1000),
+            ..MachineConfig::default()
+        };
+        for src in [
+            "l =: []\nwhile true do\n  l.push([])\nend",
+            "l =: []\nwhile true do\n  l.push(1)\nend",
+            "l =: [0]\nwhile true do\n  l.insert(0, 1)\nend",
+            "(0..1000000000000).values()",
+            "(-9223372036854775807-1...9223372036854775807).values()",
+        ] {
+            assert!(out_of_memory(run(&objects, src)), "{}", src);
+        }
+
+        // garbage and removed items don't count against the limit
+        let mut session = Session::with_machine(Machine::with_config(objects));
+        for _ in 0..2 {
+            session
+                .eval("l =: (0..500).values()\nwhile l.len() > 0 do\n  l.pop()\nend")
+                .unwrap();
+        }
+
+        let strings = MachineConfig {
+            max_string_bytes: Some(1 << 16),
+            ..MachineConfig::default()
+        };
+        assert!(out_of_memory(run(&strings, "\"a\".repeat(1 << 40)")));
+        assert!(out_of_memory(run(
+            &strings,
+            "\"aaaa\".replace(\"a\", \"b\".repeat(1 << 15))"
+        )));
+        let mut session = Session::with_machine(Machine::with_config(strings));
+        session
+            .eval("i =: 0\nwhile i < 100 do\n  s =: \"a\".repeat((1 << 15) - i)\n  i = i + 1\nend")
+            .unwrap();
+
+        // too long for any string, even without a limit
+        let default = MachineConfig::default();
+        assert!(out_of_memory(run(
+            &default,
+            "\"ab\".repeat(4611686018427387904)"
+        )));
+        assert!(out_of_memory(run(
+            &default,
+            "\"ab\".repeat(9223372036854775807)"
+        )));
+    }
+}
@@ -0,0 +1,457 @@
+//! Reading and writing compiled Piccolo bytecode files.
+//!
+//! All integers are little-endian. A file consists of a header followed by the
+//! top-level chunk:
+//!
+//! | Item             | Size                                      |
+//! |------------------|-------------------------------------------|
+//! | Magic            | 4 bytes, `pcl\0`                          |
+//! | Format version   | u16, [`FORMAT_VERSION`]                   |
+//! | Opcode version   | u16, [`OPCODE_VERSION`]                   |
+//! | Chunk            |                                           |
+//!
+//! A chunk is its bytecode, its run-length line table, and its constants:
+//!
+//! | Item             | Size                                      |
+//! |------------------|-------------------------------------------|
+//! | Bytecode         | u32 length, then that many bytes          |
+//! | Lines            | u32 length, then that many u32 op counts  |
+//! | Constants        | u32 length, then that many constants      |
+//!
+//! Each constant is a one-byte tag followed by its contents:
+//!
+//! | Tag    | Constant   | Contents                                        |
+//! |--------|------------|-------------------------------------------------|
+//! | `0x00` | `Nil`      |                                                 |
+//! | `0x01` | `Bool`     | u8, 0 or 1                                      |
+//! | `0x02` | `Integer`  | i64                                             |
+//! | `0x03` | `Double`   | f64                                             |
+//! | `0x04` | `String`   | u32 length, then that many bytes of UTF-8       |
+//! | `0x05` | `Function` | name as a string, u32 arity, u16 number of      |
+//! |        |            | captures, each a u8 is-local flag and u16 index |
+//! |        |            | then the function's chunk                       |
+//! | `0x06` | `List`     | u32 length, then that many constants            |
+//! | `0x07` | `Map`      | u32 length, then that many pairs of constants,  |
+//! |        |            | each a key followed by its value                |
+//! | `0x08` | `BigInt`   | u32 length, then that many bytes of a two's     |
+//! |        |            | complement integer, with the `bigint` feature   |
+//!
+//! [`FORMAT_VERSION`]: ./constant.FORMAT_VERSION.html
+//! [`OPCODE_VERSION`]: ../op/constant.OPCODE_VERSION.html
+
+use crate::runtime::{
+    chunk::Chunk,
+    object::{Capture, Function},
+    op::OPCODE_VERSION,
+    verify::verify,
+};
+use crate::{BytecodeError, Constant, PiccoloError};
+
+/// Bytes at the start of every Piccolo bytecode file.
+pub const MAGIC: &[u8; 4] = b"pcl\0";
+
+/// Version of the bytecode file layout.
+pub const FORMAT_VERSION: u16 = 1;
+
+// function and list constants nested deeper than this are rejected when loading, rather than
+// overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+const TAG_NIL: u8 = 0x00;
+const TAG_BOOL: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_DOUBLE: u8 = 0x03;
+const TAG_STRING: u8 = 0x04;
+const TAG_FUNCTION: u8 = 0x05;
+const TAG_LIST: u8 = 0x06;
+const TAG_MAP: u8 = 0x07;
+#[cfg(feature = "bigint")]
+const TAG_BIGINT: u8 = 0x08;
+
+/// Serialize a chunk into the bytecode file format.
+pub fn serialize(chunk: &Chunk) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&OPCODE_VERSION.to_le_bytes());
+    write_chunk(&mut bytes, chunk);
+    bytes
+}
+
+/// Deserialize a chunk from the bytecode file format.
+///
+/// Returns an error if the file was made for a different version of Piccolo, if it
+/// is truncated, or if the chunk it contains doesn't pass [`verify`].
+///
+/// [`verify`]: ../verify/fn.verify.html
+pub fn deserialize(bytes: &[u8]) -> Result<Chunk, PiccoloError> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a Piccolo bytecode file"));
+    }
+
+    let format_version = reader.u16()?;
+    if format_version != FORMAT_VERSION {
+        return Err(invalid(&format!(
+            "file format version {}, expected {}",
+            format_version, FORMAT_VERSION
+        )));
+    }
+
+    let opcode_version = reader.u16()?;
+    if opcode_version != OPCODE_VERSION {
+        return Err(invalid(&format!(
+            "opcode version {}, expected {}",
+            opcode_version, OPCODE_VERSION
+        )));
+    }
+
+    let chunk = read_chunk(&mut reader, 0)?;
+    if reader.offset != bytes.len() {
+        return Err(invalid("trailing bytes after chunk"));
+    }
+
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn invalid(msg: &str) -> PiccoloError {
+    PiccoloError::new(BytecodeError::InvalidBytecode).msg(msg)
+}
+
+fn write_u32(bytes: &mut Vec<u8>, v: usize) {
+    bytes.extend_from_slice(&(v as u32).to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(bytes, chunk.data.len());
+    bytes.extend_from_slice(&chunk.data);
+
+    write_u32(bytes, chunk.lines.len());
+    for line in chunk.lines.iter() {
+        write_u32(bytes, *line);
+    }
+
+    write_u32(bytes, chunk.constants.len());
+    for constant in chunk.constants.iter() {
+        write_constant(bytes, constant);
+    }
+}
+
+fn write_constant(bytes: &mut Vec<u8>, constant: &Constant) {
+    match constant {
+        Constant::Nil => bytes.push(TAG_NIL),
+        Constant::Bool(v) => {
+            bytes.push(TAG_BOOL);
+            bytes.push(*v as u8);
+        }
+        Constant::Integer(v) => {
+            bytes.push(TAG_INTEGER);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        Constant::Double(v) => {
+            bytes.push(TAG_DOUBLE);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        Constant::String(v) => {
+            bytes.push(TAG_STRING);
+            write_str(bytes, v);
+        }
+        Constant::Function(f) => {
+            bytes.push(TAG_FUNCTION);
+            write_str(bytes, f.name());
+            write_u32(bytes, f.arity());
+            bytes.extend_from_slice(&(f.captures().len() as u16).to_le_bytes());
+            for capture in f.captures() {
+                bytes.push(capture.is_local as u8);
+                bytes.extend_from_slice(&capture.index.to_le_bytes());
+            }
+            write_chunk(bytes, f.chunk());
+        }
+        Constant::List(values) => {
+            bytes.push(TAG_LIST);
+            write_u32(bytes, values.len());
+            for value in values {
+                write_constant(bytes, value);
+            }
+        }
+        Constant::Map(entries) => {
+            bytes.push(TAG_MAP);
+            write_u32(bytes, entries.len());
+            for (key, value) in entries {
+                write_constant(bytes, key);
+                write_constant(bytes, value);
+            }
+        }
+        #[cfg(feature = "bigint")]
+        Constant::BigInt(v) => {
+            bytes.push(TAG_BIGINT);
+            let v = v.to_signed_bytes_le();
+            write_u32(bytes, v.len());
+            bytes.extend_from_slice(&v);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PiccoloError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], PiccoloError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, PiccoloError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PiccoloError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, PiccoloError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, PiccoloError> {
+        let len = self.u32()?;
+        Ok(std::str::from_utf8(self.bytes(len)?)?.to_owned())
+    }
+}
+
+fn read_chunk(reader: &mut Reader, depth: usize) -> Result<Chunk, PiccoloError> {
+    if depth > MAX_DEPTH {
+        return Err(invalid("functions nested too deeply"));
+    }
+
+    let len = reader.u32()?;
+    let data = reader.bytes(len)?.to_vec();
+
+    // don't trust the length to preallocate, the file could be lying
+    let len = reader.u32()?;
+    let mut lines = Vec::new();
+    for _ in 0..len {
+        lines.push(reader.u32()?);
+    }
+
+    let len = reader.u32()?;
+    let mut constants = Vec::new();
+    for _ in 0..len {
+        constants.push(read_constant(reader, depth)?);
+    }
+    if constants.len() > u16::MAX as usize + 1 {
+        return Err(invalid("too many constants"));
+    }
+
+    Ok(Chunk {
+        data,
+        lines,
+        constants,
+    })
+}
+
+fn read_constant(reader: &mut Reader, depth: usize) -> Result<Constant, PiccoloError> {
+    Ok(match reader.u8()? {
+        TAG_NIL => Constant::Nil,
+        TAG_BOOL => match reader.u8()? {
+            0 => Constant::Bool(false),
+            1 => Constant::Bool(true),
+            _ => return Err(invalid("bool constant is not 0 or 1")),
+        },
+        TAG_INTEGER => Constant::Integer(i64::from_le_bytes(reader.array()?)),
+        TAG_DOUBLE => Constant::Double(f64::from_le_bytes(reader.array()?)),
+        TAG_STRING => Constant::String(reader.string()?),
+        TAG_FUNCTION => {
+            let name = reader.string()?;
+            let arity = reader.u32()?;
+            // arguments are passed by a single Call, which counts them in a u16
+            if arity > u16::MAX as usize {
+                return Err(invalid(
+                    "function takes more arguments than a call can pass",
+                ));
+            }
+
+            let len = reader.u16()?;
+            let mut captures = Vec::new();
+            for _ in 0..len {
+                let is_local = match reader.u8()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(invalid("capture flag is not 0 or 1")),
+                };
+                let index = reader.u16()?;
+                captures.push(Capture { is_local, index });
+            }
+
+            let chunk = read_chunk(reader, depth + 1)?;
+            Constant::Function(Function::new(name, arity, chunk, captures))
+        }
+        TAG_LIST => {
+            if depth >= MAX_DEPTH {
+                return Err(invalid("lists nested too deeply"));
+            }
+
+            let len = reader.u32()?;
+            let mut values = Vec::new();
+            for _ in 0..len {
+                values.push(read_constant(reader, depth + 1)?);
+            }
+            Constant::List(values)
+        }
+        TAG_MAP => {
+            if depth >= MAX_DEPTH {
+                return Err(invalid("maps nested too deeply"));
+            }
+
+            let len = reader.u32()?;
+            let mut entries = Vec::new();
+            for _ in 0..len {
+                let key = read_constant(reader, depth + 1)?;
+                let value = read_constant(reader, depth + 1)?;
+                entries.push((key, value));
+            }
+            Constant::Map(entries)
+        }
+        #[cfg(feature = "bigint")]
+        TAG_BIGINT => {
+            let len = reader.u32()?;
+            Constant::BigInt(num_bigint::BigInt::from_signed_bytes_le(reader.bytes(len)?))
+        }
+        tag => {
+            return Err(invalid(&format!("unknown constant tag {:#04x}", tag)));
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deserialize, serialize};
+    use crate::compiler::emitter::{compile_ast, Emitter};
+    use crate::{parse, Machine, Scanner};
+
+    fn compile(src: &str) -> crate::Chunk {
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let mut emitter = Emitter::new();
+        compile_ast(&mut emitter, &ast).unwrap();
+        emitter.into_chunk()
+    }
+
+    #[test]
+    fn round_trip() {
+        let chunk = compile(
+            "x =: 1\ny =: 2.5\nz =: \"héllo\"\nw =: true\nv =: nil\n\
+             fn outer(a) do\n  fn inner() do\n    retn a\n  end\n  retn inner\nend\n\
+             retn outer(x + 41)()",
+        );
+        let bytes = serialize(&chunk);
+        let loaded = deserialize(&bytes).unwrap();
+        assert_eq!(
+            chunk.disassemble("round trip"),
+            loaded.disassemble("round trip")
+        );
+        assert_eq!(
+            Machine::new().interpret(&loaded).unwrap(),
+            crate::Constant::Integer(42)
+        );
+    }
+
+    #[test]
+    fn list_constant() {
+        let mut chunk = compile("retn nil");
+        let list = crate::Constant::List(vec![
+            crate::Constant::Integer(1),
+            crate::Constant::List(vec![crate::Constant::String("a".into())]),
+        ]);
+        chunk.constants[0] = list.clone();
+        let loaded = deserialize(&serialize(&chunk)).unwrap();
+        assert_eq!(loaded.constants[0], list);
+    }
+
+    #[test]
+    fn map_constant() {
+        let mut chunk = compile("retn nil");
+        let map = crate::Constant::Map(vec![
+            (
+                crate::Constant::String("a".into()),
+                crate::Constant::Integer(1),
+            ),
+            (
+                crate::Constant::Integer(2),
+                crate::Constant::Map(vec![(crate::Constant::Bool(true), crate::Constant::Nil)]),
+            ),
+        ]);
+        chunk.constants[0] = map.clone();
+        let loaded = deserialize(&serialize(&chunk)).unwrap();
+        assert_eq!(loaded.constants[0], map);
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn bigint_constant() {
+        let chunk = compile("retn -100000000000000000000 + 1");
+        let loaded = deserialize(&serialize(&chunk)).unwrap();
+        assert_eq!(
+            Machine::new().interpret(&loaded).unwrap().to_string(),
+            "-99999999999999999999"
+        );
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let bytes = serialize(&compile("x =: 1 + 2\nprint(x)"));
+
+        // every truncation is an error, not a panic
+        for len in 0..bytes.len() {
+            assert!(deserialize(&bytes[..len]).is_err());
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(deserialize(&trailing).is_err());
+
+        let mut magic = bytes.clone();
+        magic[0] = b'x';
+        assert!(deserialize(&magic).is_err());
+
+        let mut version = bytes.clone();
+        version[6] = version[6].wrapping_add(1);
+        assert!(deserialize(&version).is_err());
+
+        // the first byte of code is just after the header and the code length
+        let mut opcode = bytes;
+        opcode[12] = 0xee;
+        assert!(deserialize(&opcode).is_err());
+
+        // the arity follows the function's name, and can be at most what a call can pass
+        let bytes = serialize(&compile("fn f(a) do\nend"));
+        let name = [1, 0, 0, 0, b'f', 1, 0, 0, 0];
+        let arity = bytes.windows(name.len()).position(|w| w == name).unwrap() + 5;
+        let mut huge = bytes.clone();
+        huge[arity..arity + 4].copy_from_slice(&0x3fff_ffffu32.to_le_bytes());
+        assert!(deserialize(&huge).is_err());
+        let mut most = bytes;
+        most[arity..arity + 4].copy_from_slice(&u32::from(u16::MAX).to_le_bytes());
+        deserialize(&most).unwrap();
+    }
+}
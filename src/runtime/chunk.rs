@@ -6,7 +6,7 @@ use super::op::Opcode;
 use super::{ChunkOffset, Line};
 
 /// Stores a piece of compiled Piccolo bytecode.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, PartialEq)]
 pub struct Chunk {
     pub(crate) data: Vec<u8>,
     pub(crate) lines: Vec<Line>,
@@ -81,7 +81,8 @@ impl Chunk {
         crate::encode_bytes(low, high)
     }
 
-    // get a line number from a byte offset using run-length encoding
+    // get a line number from a byte offset using run-length encoding. errors
+    // from the last instruction may ask for the line just past the end.
     pub(crate) fn get_line_from_index(&self, idx: ChunkOffset) -> Line {
         let mut total_ops = 0;
         for (offset_line, num_ops) in self.lines.iter().enumerate() {
@@ -90,7 +91,7 @@ impl Chunk {
                 return offset_line + 1;
             }
         }
-        panic!("no line for idx {} {:?} {:?}", idx, self.lines, self.data);
+        self.lines.len()
     }
 
     // add one opcode to a line
@@ -118,6 +119,12 @@ impl Chunk {
             offset += super::op::op_len(self.data[offset].into());
         }
 
+        for constant in self.constants.iter() {
+            if let Constant::Function(f) = constant {
+                s.push_str(&f.chunk().disassemble(f.name()));
+            }
+        }
+
         s
     }
 
@@ -144,15 +151,29 @@ impl Chunk {
         let op_str = format!("{:15}", format!("{:?}", op));
 
         let arg = match op {
-            Opcode::Constant => {
+            Opcode::Constant | Opcode::Closure => {
                 let idx = self.read_short(offset + 1);
-                format!("@{:04x} ({:?})", idx, self.constants[idx as usize])
+                match &self.constants[idx as usize] {
+                    Constant::Function(f) => format!("@{:04x} ({})", idx, f),
+                    constant => format!("@{:04x} ({:?})", idx, constant),
+                }
             }
             Opcode::GetLocal | Opcode::SetLocal => {
                 let idx = self.read_short(offset + 1);
                 format!("${}", idx)
             }
-            Opcode::GetGlobal | Opcode::SetGlobal | Opcode::DeclareGlobal => {
+            Opcode::GetUpvalue | Opcode::SetUpvalue => {
+                let idx = self.read_short(offset + 1);
+                format!("^{}", idx)
+            }
+            Opcode::GetGlobal
+            | Opcode::SetGlobal
+            | Opcode::DeclareGlobal
+            | Opcode::Data
+            | Opcode::Field
+            | Opcode::Method
+            | Opcode::GetProperty
+            | Opcode::SetProperty => {
                 let idx = self.read_short(offset + 1);
                 format!("g{:04x} ({:?})", idx, self.constants[idx as usize])
             }
@@ -164,6 +185,22 @@ impl Chunk {
                 let idx = self.read_short(offset + 1);
                 format!("-{:04x}", idx)
             }
+            Opcode::Call => {
+                let arity = self.read_short(offset + 1);
+                format!("({} args)", arity)
+            }
+            Opcode::New => {
+                let fields = self.read_short(offset + 1);
+                format!("({} fields)", fields)
+            }
+            Opcode::List => {
+                let items = self.read_short(offset + 1);
+                format!("({} items)", items)
+            }
+            Opcode::Map => {
+                let entries = self.read_short(offset + 1);
+                format!("({} entries)", entries)
+            }
             _ => String::new(),
         };
 
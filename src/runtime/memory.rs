@@ -1,36 +1,47 @@
 //! Contains items for the manipulation of memory at runtime.
 
-use crate::fnv::FnvHashMap;
-use crate::runtime::StringPtr;
-use crate::{Constant, ErrorKind, Object, PiccoloError, Value};
+use crate::fnv::{FnvHashMap, FnvHashSet};
+use crate::runtime::{HeapPtr, StringPtr};
+use crate::{Constant, Object, PiccoloError, RuntimeError, Value};
 
-use super::object::ObjectPtr;
+use super::object::{Closure, Function, List, Map, MapKey, ObjectPtr};
+use super::op::Opcode;
 
-use std::mem;
+#[cfg(feature = "bigint")]
+use core::cmp::Ordering;
+use core::ops::Range;
+
+// how deeply lists and maps can be nested inside each other before formatting them
+// or turning them into constants gives up, rather than overflowing the stack
+const MAX_NESTING: usize = 256;
 
 /// Simple string interner.
 ///
 /// Discussed on [matklad's blog post.] Further discussion on reddit [here.]
 /// Uses FNV hashing provided by [`FnvHashMap`].
 ///
+/// Unlike the interner from the blog post, each string is allocated separately so
+/// that strings which are no longer referenced can be freed by the garbage collector.
+/// Freed [`StringPtr`]s are reused for new strings.
+///
 /// [matklad's blog post.]: https://matklad.github.io/2020/03/22/fast-simple-rust-interner.html
 /// [here.]: https://www.reddit.com/r/rust/comments/fn1jxf/blog_post_fast_and_simple_rust_interner/
 /// [`FnvHashMap`]: https://doc.servo.org/fnv/
+/// [`StringPtr`]: ../type.StringPtr.html
 pub struct Interner {
     map: FnvHashMap<&'static str, StringPtr>,
-    strings: Vec<&'static str>,
-    current_buffer: String,
-    previous_buffers: Vec<String>,
+    strings: Vec<Option<Box<str>>>,
+    free: Vec<StringPtr>,
+    bytes: usize,
 }
 
 impl Interner {
     pub fn with_capacity(cap: usize) -> Self {
-        let cap = cap.next_power_of_two();
         Self {
             map: FnvHashMap::default(),
-            strings: Vec::new(),
-            current_buffer: String::with_capacity(cap),
-            previous_buffers: Vec::new(),
+            strings: Vec::with_capacity(cap),
+            free: Vec::new(),
+            bytes: 0,
         }
     }
 
@@ -40,10 +51,20 @@ impl Interner {
             return id;
         }
 
-        let s = unsafe { self.alloc(s) };
-        let id = self.map.len();
+        let boxed: Box<str> = s.into();
+        let s = unsafe { Interner::extend(&boxed) };
+        self.bytes += s.len();
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.strings[id] = Some(boxed);
+                id
+            }
+            None => {
+                self.strings.push(Some(boxed));
+                self.strings.len() - 1
+            }
+        };
         self.map.insert(s, id);
-        self.strings.push(s);
 
         trace!("str does not exist {:x}", id);
         debug_assert!(self.lookup(id) == s);
@@ -55,44 +76,54 @@ impl Interner {
     pub fn lookup(&self, id: StringPtr) -> &str {
         self.strings
             .get(id)
+            .and_then(Option::as_deref)
             .unwrap_or_else(|| panic!("str does not exist: {:x}", id))
     }
 
+    /// Intern part of a string that is already interned, without copying it first.
+    /// `range` is in bytes, and panics like slicing a `str` if it is invalid.
+    pub fn intern_substring(&mut self, id: StringPtr, range: Range<usize>) -> StringPtr {
+        // interning never moves or frees a string, see `extend`
+        let s = unsafe { Interner::extend(&self.lookup(id)[range]) };
+        self.intern(s)
+    }
+
+    /// The number of strings currently interned.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The total length in bytes of the strings currently interned.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // free every string that isn't marked
+    fn sweep(&mut self, marked: &[bool]) {
+        for (id, string) in self.strings.iter_mut().enumerate() {
+            if !marked[id] {
+                if let Some(string) = string.take() {
+                    debug!("free str {:x} = {:?}", id, string);
+                    self.map.remove(&*string);
+                    self.bytes -= string.len();
+                    self.free.push(id);
+                }
+            }
+        }
+    }
+
     // this is OK because:
     //  1. we are the only user of this function
-    //  2. we don't drop or deallocate the old buffers when we reallocate the current one,
-    //     so any &str that are floating around will always point to valid data
+    //  2. the contents of a Box<str> don't move when the box is moved, and we only drop
+    //     the box after removing the &str from the map
     //  3. the &str from Intern::lookup is bounded by the lifetime of self anyway, so we can't
-    //     hold on to a &str at all if we want to add new ones
-    unsafe fn alloc(&mut self, s: &str) -> &'static str {
-        let current_capacity = self.current_buffer.capacity();
-
-        // if adding a new string will cause reallocation of the buffer
-        if current_capacity < self.current_buffer.len() + s.len() {
-            // create the next buffer
-            let new_capacity = (current_capacity.max(s.len()) + 1).next_power_of_two();
-            let next_buffer = String::with_capacity(new_capacity);
-            trace!(
-                "reallocate interner: {} -> {}",
-                self.current_buffer.len(),
-                new_capacity
-            );
-
-            // swap them out
-            let previous_buffer = mem::replace(&mut self.current_buffer, next_buffer);
-
-            // retain ownership of the old buffer so &str references don't become invalid
-            self.previous_buffers.push(previous_buffer);
-        }
-
-        // add s to the current buffer
-        let interned = {
-            let start = self.current_buffer.len();
-            self.current_buffer.push_str(s);
-            &self.current_buffer[start..]
-        };
-
-        &*(interned as *const str)
+    //     hold on to a &str at all if we want to add or free strings
+    unsafe fn extend(s: &str) -> &'static str {
+        &*(s as *const str)
     }
 }
 
@@ -107,11 +138,24 @@ impl Interner {
 /// are two levels of indirection, the negative impact on performance comes with static memory
 /// safety guarantees.
 ///
+/// Objects and interned strings are freed by a mark-and-sweep collector. Any object or
+/// string not reachable from the roots passed to [`Heap::gc`], either directly or
+/// through the values reported by [`Object::trace`], is dropped.
+///
 /// [`Object`]: ../object/trait.Object.html
+/// [`Heap::gc`]: #method.gc
+/// [`Object::trace`]: ../object/trait.Object.html#method.trace
 pub struct Heap {
     memory: Vec<Option<Box<dyn Object>>>,
     interner: Interner,
     alloc_after: usize,
+    allocated: usize,
+    threshold: usize,
+    next_gc: usize,
+    // items held by lists and maps, which count toward max_objects
+    items: usize,
+    max_objects: Option<usize>,
+    max_string_bytes: Option<usize>,
 }
 
 impl Heap {
@@ -125,21 +169,150 @@ impl Heap {
             memory,
             interner: Interner::with_capacity(32),
             alloc_after: 0,
+            allocated: 0,
+            threshold: capacity,
+            next_gc: capacity,
+            items: 0,
+            max_objects: None,
+            max_string_bytes: None,
         }
     }
 
-    // needs to see the vm stack
-    pub fn gc(&mut self) {
-        debug!("start GC");
-        // for item in memory
-        // if inaccessible by the VM, Option::take() it
+    /// Limit the number of objects and the total length in bytes of the strings the
+    /// heap may hold, or remove a limit with `None`. Every item in a list and every
+    /// entry in a map counts as an object too. See [`Heap::check_limits`].
+    ///
+    /// [`Heap::check_limits`]: #method.check_limits
+    pub fn set_limits(&mut self, max_objects: Option<usize>, max_string_bytes: Option<usize>) {
+        self.max_objects = max_objects;
+        self.max_string_bytes = max_string_bytes;
+    }
+
+    /// Returns an error if the heap holds more objects or string bytes than its limits
+    /// allow. Allocating never fails, so the limits are only enforced by checking
+    /// this, which a [`Machine`] does after collecting garbage.
+    ///
+    /// [`Machine`]: ../vm/struct.Machine.html
+    pub fn check_limits(&self) -> Result<(), PiccoloError> {
+        if let Some(max) = self
+            .max_objects
+            .filter(|&max| self.allocated + self.items > max)
+        {
+            return Err(PiccoloError::new(RuntimeError::OutOfMemory {
+                limit: format!("{} objects", max),
+            }));
+        }
+        self.check_string_len(self.interner.bytes)
+    }
+
+    /// Returns an error if a list of `len` items would be over the limit on objects by
+    /// itself. Used before building a list that could be arbitrarily large.
+    pub fn check_items(&self, len: usize) -> Result<(), PiccoloError> {
+        match self.max_objects {
+            Some(max) if len > max => Err(PiccoloError::new(RuntimeError::OutOfMemory {
+                limit: format!("{} objects", max),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count `len` items added to a list or map that is already allocated. Items
+    /// that are removed are only uncounted when [`Heap::gc`] runs.
+    ///
+    /// [`Heap::gc`]: #method.gc
+    pub fn add_items(&mut self, len: usize) {
+        self.items += len;
+    }
+
+    /// Returns an error if a string of `len` bytes would be over the limit on string
+    /// bytes by itself. Used before building a string that could be arbitrarily large.
+    pub fn check_string_len(&self, len: usize) -> Result<(), PiccoloError> {
+        match self.max_string_bytes {
+            Some(max) if len > max => Err(PiccoloError::new(RuntimeError::OutOfMemory {
+                limit: format!("{} bytes of strings", max),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Free every object that is not reachable from `roots`.
+    pub fn gc<I: IntoIterator<Item = Value>>(&mut self, roots: I) {
+        debug!("start GC with {} objects", self.allocated);
+
+        // mark
+        let mut marked = vec![false; self.memory.len()];
+        let mut marked_strings = vec![false; self.interner.strings.len()];
+        let mut gray: Vec<Value> = roots.into_iter().collect();
+        while let Some(value) = gray.pop() {
+            match value {
+                Value::Object(ptr) if !marked[ptr.idx] => {
+                    trace!("mark {:x}", ptr.idx);
+                    marked[ptr.idx] = true;
+                    self.deref(value).trace(&mut gray);
+                }
+                Value::String(ptr) => marked_strings[ptr] = true,
+                _ => {}
+            }
+        }
+
+        // sweep, and recount the items of the lists and maps that are left, since
+        // removing items doesn't update the count
+        self.items = 0;
+        for (idx, (object, marked)) in self.memory.iter_mut().zip(marked).enumerate() {
+            if !marked && object.is_some() {
+                debug!("free {:x} = {:?}", idx, object);
+                *object = None;
+                self.allocated -= 1;
+            } else if let Some(object) = object {
+                self.items += items(object.as_ref());
+            }
+        }
+
+        self.interner.sweep(&marked_strings);
+
         self.alloc_after = 0;
+        self.next_gc = self
+            .threshold
+            .max((self.allocated + self.interner.len()) * 2);
+        debug!(
+            "end GC with {} objects and {} strings",
+            self.allocated,
+            self.interner.len()
+        );
+    }
+
+    /// Returns true if enough objects and strings have been allocated since the last
+    /// collection that [`Heap::gc`] should be run, or if the heap is over its limits.
+    ///
+    /// [`Heap::gc`]: #method.gc
+    pub fn should_gc(&self) -> bool {
+        self.allocated + self.interner.len() >= self.next_gc || self.check_limits().is_err()
+    }
+
+    /// Get the [`Interner`] holding the heap's strings.
+    ///
+    /// [`Interner`]: ./struct.Interner.html
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// The number of objects currently allocated.
+    pub fn allocated(&self) -> usize {
+        self.allocated
     }
 
     pub fn alloc_string(&mut self, s: &str) -> Value {
         Value::String(self.interner.intern(s))
     }
 
+    /// Allocate part of a string that is already allocated. See
+    /// [`Interner::intern_substring`].
+    ///
+    /// [`Interner::intern_substring`]: ./struct.Interner.html#method.intern_substring
+    pub fn alloc_substring(&mut self, string: StringPtr, range: Range<usize>) -> Value {
+        Value::String(self.interner.intern_substring(string, range))
+    }
+
     /// Allocate space for a new value, and return its pointer.
     pub fn alloc(&mut self, value: Box<dyn Object>) -> Value {
         while self.memory[self.alloc_after].is_some() {
@@ -154,7 +327,9 @@ impl Heap {
         debug!("insert {:x} = {:?}", self.alloc_after, value);
 
         let kind = value.kind();
+        self.items += items(value.as_ref());
         self.memory[self.alloc_after] = Some(value);
+        self.allocated += 1;
         Value::Object(ObjectPtr {
             idx: self.alloc_after,
             kind,
@@ -208,7 +383,10 @@ impl Heap {
         match ptr {
             Value::Object(ptr) => {
                 debug!("take {:x}", ptr.idx);
-                self.memory[ptr.idx].take().expect("free invalid ptr")
+                let object = self.memory[ptr.idx].take().expect("free invalid ptr");
+                self.allocated -= 1;
+                self.items -= items(object.as_ref()).min(self.items);
+                object
             }
             _ => panic!("take with non-ptr {:?}", ptr),
         }
@@ -219,7 +397,7 @@ impl Heap {
             Value::Object(ptr) => {
                 trace!("try copy {:x}", ptr.idx);
                 let cloned = self.deref(*value).try_clone().ok_or_else(|| {
-                    PiccoloError::new(ErrorKind::CannotClone {
+                    PiccoloError::new(RuntimeError::CannotClone {
                         ty: self.deref(*value).type_name().to_owned(),
                     })
                 })?;
@@ -233,15 +411,80 @@ impl Heap {
         })
     }
 
-    pub(crate) fn value_into_constant(&mut self, v: Value) -> Constant {
-        match v {
+    pub(crate) fn value_into_constant(&self, v: Value) -> Constant {
+        self.to_constant(v, false, &mut Vec::new())
+            .unwrap_or(Constant::Nil)
+    }
+
+    // like value_into_constant, but only if the constant can be turned back into an
+    // equivalent value. lists and maps can't contain themselves, and map keys must
+    // be constants too. in either case closures that have captured variables can't
+    // be constants, since they would lose their upvalues.
+    pub(crate) fn try_value_into_constant(&self, v: Value) -> Option<Constant> {
+        self.to_constant(v, true, &mut Vec::new())
+    }
+
+    // `seen` holds the lists and maps being converted, to catch ones that contain
+    // themselves or are nested too deeply
+    fn to_constant(&self, v: Value, strict: bool, seen: &mut Vec<HeapPtr>) -> Option<Constant> {
+        Some(match v {
             Value::String(ptr) => Constant::String(self.interner.lookup(ptr).to_owned()),
             Value::Bool(v) => Constant::Bool(v),
             Value::Integer(v) => Constant::Integer(v),
             Value::Double(v) => Constant::Double(v),
             Value::Nil => Constant::Nil,
-            _ => panic!("cannot make constant from object"),
-        }
+            Value::Object(ptr) => {
+                let object = self.deref(v);
+                #[cfg(feature = "bigint")]
+                if let Some(v) = object.downcast_ref::<super::bigint::BigInteger>() {
+                    return Some(Constant::BigInt(v.value().clone()));
+                }
+                if let Some(f) = object.downcast_ref::<Function>() {
+                    Constant::Function(f.clone())
+                } else if let Some(c) = object.downcast_ref::<Closure>() {
+                    // a function that captures variables can only be called as a closure
+                    if !c.upvalues().is_empty() {
+                        return if strict { None } else { Some(Constant::Nil) };
+                    }
+                    Constant::Function(c.function().clone())
+                } else if let Some(list) = object.downcast_ref::<List>() {
+                    if seen.contains(&ptr.idx) || seen.len() >= MAX_NESTING {
+                        return if strict { None } else { Some(Constant::Nil) };
+                    }
+                    seen.push(ptr.idx);
+                    let values = list
+                        .values()
+                        .iter()
+                        .map(|value| self.to_constant(*value, strict, seen))
+                        .collect::<Option<_>>()?;
+                    seen.pop();
+                    Constant::List(values)
+                } else if let Some(map) = object.downcast_ref::<Map>() {
+                    if seen.contains(&ptr.idx) || seen.len() >= MAX_NESTING {
+                        return if strict { None } else { Some(Constant::Nil) };
+                    }
+                    seen.push(ptr.idx);
+                    let entries = map
+                        .entries()
+                        .iter()
+                        .map(|(key, value)| {
+                            Some((
+                                self.to_constant(*key, strict, seen)?,
+                                self.to_constant(*value, strict, seen)?,
+                            ))
+                        })
+                        .collect::<Option<_>>()?;
+                    seen.pop();
+                    Constant::Map(entries)
+                } else if strict {
+                    return None;
+                } else {
+                    // other objects only make sense inside the machine
+                    debug!("cannot make constant from {}", object.type_name());
+                    Constant::Nil
+                }
+            }
+        })
     }
 
     pub(crate) fn constant_into_value(&mut self, constant: &Constant) -> Value {
@@ -255,6 +498,26 @@ impl Heap {
             Constant::Integer(v) => Value::Integer(*v),
             Constant::Bool(v) => Value::Bool(*v),
             Constant::Double(v) => Value::Double(*v),
+            Constant::Function(v) => self.alloc(Box::new(v.clone())),
+            Constant::List(v) => {
+                let values = v.iter().map(|v| self.constant_into_value(v)).collect();
+                self.alloc(Box::new(List::new(values)))
+            }
+            Constant::Map(v) => {
+                let mut map = Map::new();
+                for (key, value) in v {
+                    let key = self.constant_into_value(key);
+                    let value = self.constant_into_value(value);
+                    // constants that can't be keys are functions, lists, and maps
+                    match MapKey::new(self, key, Opcode::Constant) {
+                        Ok(hash) => map.insert(hash, key, value),
+                        Err(_) => debug!("cannot use {} as a map key", self.type_name(&key)),
+                    }
+                }
+                self.alloc(Box::new(map))
+            }
+            #[cfg(feature = "bigint")]
+            Constant::BigInt(v) => super::bigint::alloc(self, v.clone()),
             Constant::Nil => Value::Nil,
         }
     }
@@ -273,16 +536,69 @@ impl Heap {
 
     /// Formats the value.
     pub fn fmt(&self, v: &Value) -> String {
+        self.fmt_seen(v, &mut Vec::new())
+    }
+
+    /// Formats the value like it would appear inside a list, with strings in quotes.
+    pub fn repr(&self, v: &Value) -> String {
+        self.fmt_item(v, &mut Vec::new())
+    }
+
+    // lists and maps format their strings with quotes, and a list or map inside
+    // itself or nested too deeply as [...] or {...}
+    fn fmt_seen(&self, v: &Value, seen: &mut Vec<HeapPtr>) -> String {
         match v {
             Value::Bool(v) => format!("{}", v),
             Value::Integer(v) => format!("{}", v),
             Value::Double(v) => format!("{}", v),
-            Value::Object(_) => format!("{}", self.deref(*v)),
+            Value::Object(ptr) => {
+                let object = self.deref(*v);
+                if let Some(list) = object.downcast_ref::<List>() {
+                    if seen.contains(&ptr.idx) || seen.len() >= MAX_NESTING {
+                        return "[...]".into();
+                    }
+                    seen.push(ptr.idx);
+                    let items = list
+                        .values()
+                        .iter()
+                        .map(|item| self.fmt_item(item, seen))
+                        .collect::<Vec<_>>();
+                    seen.pop();
+                    format!("[{}]", items.join(", "))
+                } else if let Some(map) = object.downcast_ref::<Map>() {
+                    if seen.contains(&ptr.idx) || seen.len() >= MAX_NESTING {
+                        return "{...}".into();
+                    }
+                    seen.push(ptr.idx);
+                    let entries = map
+                        .entries()
+                        .iter()
+                        .map(|(key, value)| {
+                            format!(
+                                "{}: {}",
+                                self.fmt_item(key, seen),
+                                self.fmt_item(value, seen)
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    seen.pop();
+                    format!("{{{}}}", entries.join(", "))
+                } else {
+                    format!("{}", object)
+                }
+            }
             Value::String(v) => self.interner.lookup(*v).to_string(),
             Value::Nil => "nil".into(),
         }
     }
 
+    fn fmt_item(&self, v: &Value, seen: &mut Vec<HeapPtr>) -> String {
+        match v {
+            Value::String(s) => format!("{:?}", self.interner.lookup(*s)),
+            v => self.fmt_seen(v, seen),
+        }
+    }
+
     /// Formats the value.
     pub fn dbg(&self, v: &Value) -> String {
         match v {
@@ -297,6 +613,36 @@ impl Heap {
 
     /// Tests a value for equality. Returns `None` if incomparable.
     pub fn eq(&self, lhs: &Value, rhs: &Value) -> Option<bool> {
+        // the items of lists and maps are compared from a work list rather than by
+        // recursing, so deeply nested ones can't overflow the stack
+        let mut items = Vec::new();
+        let mut seen = FnvHashSet::default();
+        if !self.eq_items(lhs, rhs, &mut items, &mut seen)? {
+            return Some(false);
+        }
+        while let Some((lhs, rhs)) = items.pop() {
+            if self.eq_items(&lhs, &rhs, &mut items, &mut seen) != Some(true) {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    // compare two values, but only push the pairs of items of lists and maps that
+    // still need comparing. `seen` holds the pairs of lists and maps compared
+    // already, so ones that contain themselves are only compared once.
+    fn eq_items(
+        &self,
+        lhs: &Value,
+        rhs: &Value,
+        items: &mut Vec<(Value, Value)>,
+        seen: &mut FnvHashSet<(HeapPtr, HeapPtr)>,
+    ) -> Option<bool> {
+        #[cfg(feature = "bigint")]
+        if let Some(ordering) = super::bigint::compare(self, *lhs, *rhs) {
+            return Some(ordering == Some(Ordering::Equal));
+        }
+
         match lhs {
             Value::Bool(l) => match rhs {
                 Value::Bool(r) => Some(l == r),
@@ -314,12 +660,47 @@ impl Heap {
             },
             Value::Object(l) => match rhs {
                 Value::Object(r) => {
-                    if *l == *r {
+                    let pair = (l.idx, r.idx);
+                    if *l == *r || seen.contains(&pair) {
                         Some(true)
                     } else {
                         let lhs = self.deref(*lhs);
                         let rhs = self.deref(*rhs);
-                        lhs.eq(rhs)
+                        if let (Some(l), Some(r)) =
+                            (lhs.downcast_ref::<List>(), rhs.downcast_ref::<List>())
+                        {
+                            if l.values().len() != r.values().len() {
+                                return Some(false);
+                            }
+                            seen.insert(pair);
+                            items
+                                .extend(l.values().iter().copied().zip(r.values().iter().copied()));
+                            Some(true)
+                        } else if let (Some(l), Some(r)) =
+                            (lhs.downcast_ref::<Map>(), rhs.downcast_ref::<Map>())
+                        {
+                            // maps are equal if they have the same keys and values,
+                            // whatever order they were inserted in
+                            if l.entries().len() != r.entries().len() {
+                                return Some(false);
+                            }
+                            seen.insert(pair);
+                            for (key, value) in l.entries().iter() {
+                                let other = MapKey::new(self, *key, Opcode::Equal)
+                                    .ok()
+                                    .and_then(|key| r.get(key));
+                                match other {
+                                    Some(other) => items.push((*value, other)),
+                                    None => return Some(false),
+                                }
+                            }
+                            Some(true)
+                        } else if lhs.as_any().type_id() != rhs.as_any().type_id() {
+                            // objects of different types are never equal
+                            Some(false)
+                        } else {
+                            lhs.eq(rhs)
+                        }
                     }
                 }
                 _ => None,
@@ -336,6 +717,11 @@ impl Heap {
     }
 
     pub fn lt(&self, lhs: &Value, rhs: &Value) -> Option<bool> {
+        #[cfg(feature = "bigint")]
+        if let Some(ordering) = super::bigint::compare(self, *lhs, *rhs) {
+            return Some(ordering == Some(Ordering::Less));
+        }
+
         match lhs {
             Value::Integer(l) => match rhs {
                 Value::Integer(r) => Some(l < r),
@@ -368,6 +754,11 @@ impl Heap {
     }
 
     pub fn gt(&self, lhs: &Value, rhs: &Value) -> Option<bool> {
+        #[cfg(feature = "bigint")]
+        if let Some(ordering) = super::bigint::compare(self, *lhs, *rhs) {
+            return Some(ordering == Some(Ordering::Greater));
+        }
+
         match lhs {
             Value::Integer(l) => match rhs {
                 Value::Integer(r) => Some(l > r),
@@ -400,6 +791,17 @@ impl Heap {
     }
 }
 
+// the number of items an object holds that count toward the limit on objects
+fn items(object: &dyn Object) -> usize {
+    if let Some(list) = object.downcast_ref::<List>() {
+        list.values().len()
+    } else if let Some(map) = object.downcast_ref::<Map>() {
+        map.entries().len()
+    } else {
+        0
+    }
+}
+
 pub(crate) fn dbg_list(l: &[Value], heap: &Heap) -> String {
     trace!("dbg_list");
     if l.is_empty() {
@@ -480,6 +882,106 @@ mod test {
         assert_eq!(heap.deref(ptr).downcast_ref::<S>().unwrap(), &S(78));
     }
 
+    #[derive(Debug)]
+    struct Pair(Value, Value);
+    impl Object for Pair {
+        fn trace(&self, values: &mut Vec<Value>) {
+            values.push(self.0);
+            values.push(self.1);
+        }
+    }
+    impl core::fmt::Display for Pair {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    #[test]
+    fn gc() {
+        let mut heap = Heap::new(8);
+        let a = heap.alloc(Box::new(S(1)));
+        let b = heap.alloc(Box::new(S(2)));
+        let garbage = heap.alloc(Box::new(S(3)));
+        let pair = heap.alloc(Box::new(Pair(a, b)));
+        let cycle = heap.alloc(Box::new(Pair(Value::Nil, Value::Nil)));
+        *heap.deref_mut(cycle).downcast_mut::<Pair>().unwrap() = Pair(cycle, cycle);
+        assert_eq!(heap.allocated(), 5);
+
+        heap.gc(vec![pair]);
+        assert_eq!(heap.allocated(), 3);
+        assert!(heap.deref(a).eq(&S(1)).unwrap());
+        assert!(heap.deref(b).eq(&S(2)).unwrap());
+        assert!(heap.memory[match garbage {
+            Value::Object(ptr) => ptr.idx,
+            _ => unreachable!(),
+        }]
+        .is_none());
+
+        heap.gc(vec![]);
+        assert_eq!(heap.allocated(), 0);
+    }
+
+    #[test]
+    fn gc_strings() {
+        let mut heap = Heap::new(8);
+        let hello = heap.alloc_string("hello");
+        let world = heap.alloc_string("world");
+        let pair = heap.alloc(Box::new(Pair(world, Value::Nil)));
+        let _garbage = heap.alloc_string("garbage");
+        assert_eq!(heap.interner.len(), 3);
+
+        heap.gc(vec![hello, pair]);
+        assert_eq!(heap.interner.len(), 2);
+        assert_eq!(heap.fmt(&hello), "hello");
+        assert_eq!(heap.fmt(&world), "world");
+
+        // freed pointers are reused, and equal strings still intern to the same pointer
+        let again = heap.alloc_string("garbage again");
+        assert_eq!(heap.interner.len(), 3);
+        assert_eq!(heap.interner.strings.len(), 3);
+        assert_eq!(heap.alloc_string("garbage again"), again);
+        assert_eq!(heap.alloc_string("hello"), hello);
+    }
+
+    #[test]
+    fn substrings() {
+        let mut heap = Heap::new(8);
+        let hello = heap.alloc_string("hello world");
+        let ptr = match hello {
+            Value::String(ptr) => ptr,
+            _ => unreachable!(),
+        };
+        let world = heap.alloc_substring(ptr, 6..11);
+        assert_eq!(heap.fmt(&world), "world");
+        assert_eq!(heap.alloc_string("world"), world);
+        assert_eq!(heap.alloc_substring(ptr, 0..11), hello);
+
+        // a substring of a string whose slot was freed and reused
+        heap.gc(vec![world]);
+        let again = heap.alloc_string("again");
+        let ptr = match again {
+            Value::String(ptr) => ptr,
+            _ => unreachable!(),
+        };
+        let ga = heap.alloc_substring(ptr, 1..3);
+        assert_eq!(heap.fmt(&ga), "ga");
+    }
+
+    #[test]
+    fn gc_threshold() {
+        let mut heap = Heap::new(4);
+        for i in 0..3 {
+            heap.alloc(Box::new(S(i)));
+        }
+        assert!(!heap.should_gc());
+        let keep = heap.alloc(Box::new(S(3)));
+        assert!(heap.should_gc());
+
+        heap.gc(vec![keep]);
+        assert!(!heap.should_gc());
+        assert_eq!(heap.allocated(), 1);
+    }
+
     #[test]
     fn bunches() {
         let mut pointers = vec![];
@@ -488,8 +990,8 @@ mod test {
         for i in 0..10 {
             pointers.push(h.alloc(Box::new(S(i))));
         }
-        for i in 0..10 {
-            assert!(h.deref(pointers[i]).eq(&S(i as i64)).unwrap());
+        for (i, ptr) in pointers.iter().enumerate() {
+            assert!(h.deref(*ptr).eq(&S(i as i64)).unwrap());
         }
         for ptr in pointers {
             h.take(ptr);
@@ -501,32 +1003,32 @@ mod test {
         let mut pointers = vec![];
         let mut heap = Heap::new(32);
 
-        heap.gc();
+        heap.gc(pointers.clone());
         for i in 0..16 {
             pointers.push(heap.alloc(Box::new(S(i))));
         }
 
-        heap.gc();
+        heap.gc(pointers.clone());
         for _ in 4..12 {
             let _ = heap.take(pointers.remove(4));
         }
 
-        heap.gc();
+        heap.gc(pointers.clone());
         for i in 0..16 {
             pointers.push(heap.alloc(Box::new(S(i))));
         }
 
-        heap.gc();
+        heap.gc(pointers.clone());
 
         for i in 50..58 {
             pointers.push(heap.alloc(Box::new(S(i))));
         }
-        heap.gc();
+        heap.gc(pointers.clone());
 
         for _ in 4..12 {
             let _ = heap.take(pointers.remove(16));
         }
-        heap.gc();
+        heap.gc(pointers.clone());
 
         for _ in 0..32 {
             pointers.push(heap.alloc(Box::new(S(777))));
@@ -551,7 +1053,7 @@ mod test {
         }
 
         // just to set the allocated size back to zero
-        heap.gc();
+        heap.gc(pointers.clone());
         assert_eq!(heap.memory.len(), 49);
         assert_eq!(pointers.len(), 24);
 
@@ -567,10 +1069,7 @@ mod test {
             .into_iter()
             .map(|item: Option<Box<dyn Object>>| {
                 item.and_then(|item: Box<dyn Object>| {
-                    item.downcast::<S>()
-                        .ok()
-                        .take()
-                        .and_then(|item: Box<S>| Some(*item))
+                    item.downcast::<S>().ok().map(|item: Box<S>| *item)
                 })
             })
             .collect();
@@ -630,4 +1129,203 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn constant_lists() {
+        use crate::runtime::object::List;
+        use crate::Constant;
+
+        let list = |values: &[i64]| {
+            Constant::List(values.iter().copied().map(Constant::Integer).collect())
+        };
+        let push = |heap: &mut Heap, list: Value, value: Value| {
+            let list = heap.deref_mut(list).downcast_mut::<List>().unwrap();
+            list.values_mut().push(value);
+        };
+
+        let mut heap = Heap::new(8);
+        let xs = heap.constant_into_value(&list(&[1, 2]));
+        push(&mut heap, xs, Value::Integer(3));
+        assert_eq!(heap.try_value_into_constant(xs), Some(list(&[1, 2, 3])));
+
+        let a = heap.alloc_string("a");
+        let ys = heap.alloc(Box::new(List::new(vec![xs, a])));
+        let constant = heap.try_value_into_constant(ys).unwrap();
+        assert_eq!(
+            constant,
+            Constant::List(vec![list(&[1, 2, 3]), Constant::String("a".into())])
+        );
+        assert_eq!(constant.to_string(), "[[1, 2, 3], \"a\"]");
+
+        // a list that contains itself can't be a constant
+        push(&mut heap, xs, xs);
+        assert_eq!(heap.try_value_into_constant(xs), None);
+        assert_eq!(
+            heap.value_into_constant(xs),
+            Constant::List(vec![
+                Constant::Integer(1),
+                Constant::Integer(2),
+                Constant::Integer(3),
+                Constant::Nil
+            ])
+        );
+    }
+
+    #[test]
+    fn nested_lists() {
+        use crate::runtime::object::List;
+
+        let cycle = |heap: &mut Heap, value: i64| {
+            let list = heap.alloc(Box::new(List::new(vec![Value::Integer(value)])));
+            let values = heap.deref_mut(list).downcast_mut::<List>().unwrap();
+            values.values_mut().push(list);
+            list
+        };
+
+        // lists that contain themselves are equal if their other items are
+        let mut heap = Heap::new(8);
+        let l = cycle(&mut heap, 1);
+        let m = cycle(&mut heap, 1);
+        let n = cycle(&mut heap, 2);
+        assert_eq!(heap.eq(&l, &m), Some(true));
+        assert_eq!(heap.eq(&l, &n), Some(false));
+        assert_eq!(heap.fmt(&l), "[1, [...]]");
+
+        // nesting deeper than the stack could recurse
+        let nest = |heap: &mut Heap| {
+            let mut list = heap.alloc(Box::new(List::new(vec![])));
+            for _ in 0..200_000 {
+                list = heap.alloc(Box::new(List::new(vec![list])));
+            }
+            list
+        };
+        let l = nest(&mut heap);
+        let m = nest(&mut heap);
+        assert_eq!(heap.eq(&l, &m), Some(true));
+        assert!(heap.fmt(&l).contains("[[...]]"));
+        assert_eq!(heap.try_value_into_constant(l), None);
+    }
+
+    #[test]
+    fn constant_maps() {
+        use crate::runtime::object::{List, Map, MapKey};
+        use crate::runtime::op::Opcode;
+        use crate::Constant;
+
+        let key = |k: &str| Constant::String(k.into());
+        let insert = |heap: &mut Heap, map: Value, key: Value, value: Value| {
+            let hash = MapKey::new(heap, key, Opcode::SetIndex).unwrap();
+            let map = heap.deref_mut(map).downcast_mut::<Map>().unwrap();
+            map.insert(hash, key, value);
+        };
+
+        let mut heap = Heap::new(8);
+        let config =
+            heap.constant_into_value(&Constant::Map(vec![(key("port"), Constant::Integer(80))]));
+        let host = heap.alloc_string("host");
+        let localhost = heap.alloc_string("localhost");
+        insert(&mut heap, config, host, localhost);
+        let list = heap.alloc(Box::new(List::new(vec![Value::Bool(true)])));
+        insert(&mut heap, config, Value::Double(1.0), list);
+        // equal keys are the same key, which stays the value it was first inserted as
+        insert(&mut heap, config, Value::Integer(1), list);
+
+        let constant = heap.try_value_into_constant(config).unwrap();
+        assert_eq!(
+            constant,
+            Constant::Map(vec![
+                (key("port"), Constant::Integer(80)),
+                (key("host"), key("localhost")),
+                (
+                    Constant::Double(1.0),
+                    Constant::List(vec![Constant::Bool(true)]),
+                ),
+            ])
+        );
+        assert_eq!(
+            constant.to_string(),
+            "{\"port\": 80, \"host\": \"localhost\", 1: [true]}"
+        );
+
+        // a map that contains itself can't be a constant
+        let this = heap.alloc_string("self");
+        insert(&mut heap, config, this, config);
+        assert_eq!(heap.try_value_into_constant(config), None);
+    }
+
+    #[test]
+    fn nested_maps() {
+        use crate::runtime::object::{Map, MapKey};
+        use crate::runtime::op::Opcode;
+
+        let insert = |heap: &mut Heap, map: Value, key: Value, value: Value| {
+            let hash = MapKey::new(heap, key, Opcode::SetIndex).unwrap();
+            let map = heap.deref_mut(map).downcast_mut::<Map>().unwrap();
+            map.insert(hash, key, value);
+        };
+        let cycle = |heap: &mut Heap, value: i64| {
+            let map = heap.alloc(Box::new(Map::new()));
+            let this = heap.alloc_string("self");
+            insert(heap, map, this, map);
+            insert(heap, map, Value::Integer(0), Value::Integer(value));
+            map
+        };
+
+        // maps that contain themselves are equal if their other entries are
+        let mut heap = Heap::new(8);
+        let l = cycle(&mut heap, 1);
+        let m = cycle(&mut heap, 1);
+        let n = cycle(&mut heap, 2);
+        assert_eq!(heap.eq(&l, &m), Some(true));
+        assert_eq!(heap.eq(&l, &n), Some(false));
+        assert_eq!(heap.fmt(&l), "{\"self\": {...}, 0: 1}");
+
+        // nesting deeper than the stack could recurse
+        let nest = |heap: &mut Heap| {
+            let mut map = heap.alloc(Box::new(Map::new()));
+            for _ in 0..200_000 {
+                let inner = map;
+                map = heap.alloc(Box::new(Map::new()));
+                insert(heap, map, Value::Integer(0), inner);
+            }
+            map
+        };
+        let l = nest(&mut heap);
+        let m = nest(&mut heap);
+        assert_eq!(heap.eq(&l, &m), Some(true));
+        assert!(heap.fmt(&l).contains("{0: {...}}"));
+        assert_eq!(heap.try_value_into_constant(l), None);
+    }
+
+    #[test]
+    fn limits() {
+        use crate::runtime::object::List;
+
+        let mut heap = Heap::new(8);
+        heap.set_limits(Some(4), Some(8));
+        let a = heap.alloc(Box::new(S(1)));
+        let list = heap.alloc(Box::new(List::new(vec![a, a])));
+        assert!(heap.check_limits().is_ok());
+        heap.add_items(1);
+        assert!(heap.check_limits().is_err());
+        assert!(heap.should_gc());
+
+        // removed items are only uncounted by the garbage collector
+        let values = heap.deref_mut(list).downcast_mut::<List>().unwrap();
+        values.values_mut().truncate(1);
+        assert!(heap.check_limits().is_err());
+        heap.gc(vec![list]);
+        assert!(heap.check_limits().is_ok());
+        assert!(heap.check_items(4).is_ok());
+        assert!(heap.check_items(5).is_err());
+
+        let s = heap.alloc_string("12345678");
+        assert!(heap.check_limits().is_ok());
+        let _garbage = heap.alloc_string("9");
+        assert!(heap.check_limits().is_err());
+        heap.gc(vec![s]);
+        assert!(heap.check_limits().is_ok());
+        assert!(heap.check_string_len(8).is_ok());
+        assert!(heap.check_string_len(9).is_err());
+    }
 }
@@ -1,14 +1,28 @@
 //! Objects defined in Rust that may exist at runtime.
 
-use crate::runtime::{value::Value, HeapPtr};
+use crate::runtime::{chunk::Chunk, memory::Heap, op::Opcode, value::Value, HeapPtr, StringPtr};
+use crate::{PiccoloError, RuntimeError};
 
 use downcast_rs::Downcast;
+use fnv::FnvHashMap;
 
 use core::fmt;
+use std::rc::Rc;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ObjectKind {
     Function,
+    NativeFunction,
+    Closure,
+    Upvalue,
+    Data,
+    Instance,
+    Method,
+    List,
+    Map,
+    Range,
+    #[cfg(feature = "bigint")]
+    BigInteger,
     Other,
 }
 
@@ -57,19 +71,60 @@ pub trait Object: Downcast + fmt::Debug + fmt::Display {
         None
     }
 
+    /// Whether the object may be used as a key in a [`Map`]. Keys are compared by
+    /// identity, so only objects that are never equal to a different object should
+    /// return `true`.
+    ///
+    /// [`Map`]: ./struct.Map.html
+    fn hashable(&self) -> bool {
+        false
+    }
+
+    /// Get the value at `position` of a `for` loop over the object, counting from zero.
+    /// Returns `Some(None)` once there are no values left, or `None` if the object
+    /// can't be iterated over.
+    fn iterate(&self, _position: usize) -> Option<Option<Value>> {
+        None
+    }
+
     /// Attempts to clone the object. Returns `None` if it is not possible.
     fn try_clone(&self) -> Option<Box<dyn Object>> {
         None
     }
+
+    /// Push every value the object holds a reference to onto `values`. Objects that
+    /// hold values must implement this, or those values may be freed by the garbage
+    /// collector while the object still refers to them.
+    fn trace(&self, _values: &mut Vec<Value>) {}
 }
 
 downcast_rs::impl_downcast!(Object);
 
-#[derive(Debug, Default)]
-pub(crate) struct Function {
+/// A compiled Piccolo function.
+///
+/// The chunk is reference-counted so that the function may be cheaply copied between the
+/// constant table, the [`Heap`], and the call frames of a [`Machine`].
+///
+/// [`Heap`]: ../memory/struct.Heap.html
+/// [`Machine`]: ../vm/struct.Machine.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Function {
     arity: usize,
     name: String,
-    chunk: super::chunk::Chunk,
+    chunk: Rc<Chunk>,
+    captures: Vec<Capture>,
+}
+
+/// Where a [`Closure`] finds a captured variable when it is created.
+///
+/// If `is_local` is true, `index` is a local slot in the frame of the enclosing
+/// function. Otherwise, it is an index into the upvalues of the enclosing closure.
+///
+/// [`Closure`]: ./struct.Closure.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capture {
+    pub(crate) is_local: bool,
+    pub(crate) index: u16,
 }
 
 impl Object for Function {
@@ -82,11 +137,9 @@ impl Object for Function {
     }
 
     fn eq(&self, other: &dyn Object) -> Option<bool> {
-        if let Some(other) = other.downcast_ref::<Function>() {
-            Some(other.name == self.name)
-        } else {
-            None
-        }
+        other
+            .downcast_ref::<Function>()
+            .map(|other| Rc::ptr_eq(&other.chunk, &self.chunk))
     }
 }
 
@@ -97,23 +150,795 @@ impl fmt::Display for Function {
 }
 
 impl Function {
-    pub(crate) fn arity(&self) -> &usize {
-        &self.arity
+    pub(crate) fn new(name: String, arity: usize, chunk: Chunk, captures: Vec<Capture>) -> Self {
+        Function {
+            arity,
+            name,
+            chunk: Rc::new(chunk),
+            captures,
+        }
+    }
+
+    pub(crate) fn arity(&self) -> usize {
+        self.arity
     }
 
     pub(crate) fn name(&self) -> &str {
         &self.name
     }
 
-    pub(crate) fn chunk(&self) -> &super::chunk::Chunk {
+    pub(crate) fn chunk(&self) -> &Rc<Chunk> {
         &self.chunk
     }
 
-    pub(crate) fn chunk_mut(&mut self) -> &mut super::chunk::Chunk {
-        &mut self.chunk
+    pub(crate) fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+}
+
+/// A variable captured by a [`Closure`].
+///
+/// An upvalue is open while the variable it refers to is still alive on the stack
+/// of the [`Machine`], and is closed by moving the variable into the upvalue when
+/// it goes out of scope.
+///
+/// [`Closure`]: ./struct.Closure.html
+/// [`Machine`]: ../vm/struct.Machine.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+impl Object for Upvalue {
+    fn type_name(&self) -> &'static str {
+        "upvalue"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Upvalue
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        if let Upvalue::Closed(value) = self {
+            values.push(*value);
+        }
+    }
+}
+
+impl fmt::Display for Upvalue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Upvalue::Open(slot) => write!(f, "<upvalue ${}>", slot),
+            Upvalue::Closed(value) => write!(f, "<upvalue {:?}>", value),
+        }
+    }
+}
+
+/// A [`Function`] along with the [`Upvalue`]s it has captured.
+///
+/// [`Function`]: ./struct.Function.html
+/// [`Upvalue`]: ./enum.Upvalue.html
+#[derive(Debug)]
+pub struct Closure {
+    function: Function,
+    upvalues: Vec<Value>,
+}
+
+impl Object for Closure {
+    fn type_name(&self) -> &'static str {
+        "function"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Closure
+    }
+
+    fn eq(&self, other: &dyn Object) -> Option<bool> {
+        other.downcast_ref::<Closure>().map(|other| {
+            Rc::ptr_eq(other.function.chunk(), self.function.chunk())
+                && other.upvalues == self.upvalues
+        })
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        values.extend_from_slice(&self.upvalues);
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl Closure {
+    pub(crate) fn new(function: Function, upvalues: Vec<Value>) -> Self {
+        Closure { function, upvalues }
+    }
+
+    pub(crate) fn function(&self) -> &Function {
+        &self.function
+    }
+
+    pub(crate) fn upvalues(&self) -> &[Value] {
+        &self.upvalues
+    }
+}
+
+/// A type declared with `data`, along with its field defaults and methods.
+///
+/// Calling a data type or using constructor syntax like `Foo(x=3, y=4)` creates
+/// an [`Instance`] of the type.
+///
+/// [`Instance`]: ./struct.Instance.html
+#[derive(Debug)]
+pub struct DataType {
+    name: String,
+    fields: Vec<(String, Value)>,
+    methods: FnvHashMap<String, Value>,
+}
+
+impl Object for DataType {
+    fn type_name(&self) -> &'static str {
+        "data"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Data
+    }
+
+    fn hashable(&self) -> bool {
+        true
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        values.extend(self.fields.iter().map(|(_, value)| *value));
+        values.extend(self.methods.values());
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<data {}>", self.name)
+    }
+}
+
+impl DataType {
+    pub(crate) fn new(name: String) -> Self {
+        DataType {
+            name,
+            fields: Vec::new(),
+            methods: FnvHashMap::default(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn add_field(&mut self, name: String, default: Value) {
+        self.fields.push((name, default));
+    }
+
+    pub(crate) fn method(&self, name: &str) -> Option<Value> {
+        self.methods.get(name).copied()
+    }
+
+    pub(crate) fn add_method(&mut self, name: String, method: Value) {
+        self.methods.insert(name, method);
+    }
+}
+
+/// An instance of a [`DataType`].
+///
+/// Instances have exactly the fields declared by their data type.
+///
+/// [`DataType`]: ./struct.DataType.html
+#[derive(Debug)]
+pub struct Instance {
+    data: Value,
+    name: String,
+    fields: FnvHashMap<String, Value>,
+}
+
+impl Object for Instance {
+    fn type_name(&self) -> &'static str {
+        "instance"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Instance
+    }
+
+    // instances are only equal to themselves, like they are as map keys
+    fn eq(&self, other: &dyn Object) -> Option<bool> {
+        other
+            .downcast_ref::<Instance>()
+            .map(|other| core::ptr::eq(self, other))
+    }
+
+    fn hashable(&self) -> bool {
+        true
+    }
+
+    fn get(&self, property: &str) -> Option<Value> {
+        self.fields.get(property).copied()
+    }
+
+    fn set(&mut self, property: &str, value: Value) -> Option<()> {
+        self.fields.get_mut(property).map(|field| *field = value)
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        values.push(self.data);
+        values.extend(self.fields.values());
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} instance>", self.name)
+    }
+}
+
+impl Instance {
+    /// Create an instance with the default field values of `data`, which must point
+    /// to a [`DataType`].
+    ///
+    /// [`DataType`]: ./struct.DataType.html
+    pub(crate) fn new(data: Value, data_type: &DataType) -> Self {
+        Instance {
+            data,
+            name: data_type.name.clone(),
+            fields: data_type.fields.iter().cloned().collect(),
+        }
+    }
+
+    pub(crate) fn data(&self) -> Value {
+        self.data
+    }
+}
+
+/// A method bound to the instance it was accessed from, which becomes `me` when
+/// the method is called.
+#[derive(Debug)]
+pub struct Method {
+    receiver: Value,
+    method: Value,
+}
+
+impl Object for Method {
+    fn type_name(&self) -> &'static str {
+        "function"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Method
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        values.push(self.receiver);
+        values.push(self.method);
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<method>")
+    }
+}
+
+impl Method {
+    pub(crate) fn new(receiver: Value, method: Value) -> Self {
+        Method { receiver, method }
+    }
+
+    pub(crate) fn receiver(&self) -> Value {
+        self.receiver
+    }
+
+    pub(crate) fn method(&self) -> Value {
+        self.method
+    }
+}
+
+/// A list of values, created with a list literal like `[1, 2, 3]`.
+///
+/// Lists are shared by reference, so changing a list through one variable changes it
+/// for every variable that refers to it.
+#[derive(Debug, Default)]
+pub struct List {
+    values: Vec<Value>,
+}
+
+impl Object for List {
+    fn type_name(&self) -> &'static str {
+        "list"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::List
+    }
+
+    fn iterate(&self, position: usize) -> Option<Option<Value>> {
+        Some(self.values.get(position).copied())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Object>> {
+        Some(Box::new(List::new(self.values.clone())))
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        values.extend_from_slice(&self.values);
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<list of {}>", self.values.len())
+    }
+}
+
+impl List {
+    pub(crate) fn new(values: Vec<Value>) -> Self {
+        List { values }
+    }
+
+    pub(crate) fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub(crate) fn values_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.values
+    }
+
+    /// Find the position of an item in the list. Negative indices count back from
+    /// the end of the list, so `-1` is the last item.
+    pub(crate) fn index(&self, index: i64) -> Result<usize, PiccoloError> {
+        position(index, self.values.len(), self.values.len())
+    }
+
+    /// Like [`index`], but `index` may also be the length of the list, for inserting
+    /// at the end or slicing up to it.
+    ///
+    /// [`index`]: #method.index
+    pub(crate) fn boundary(&self, index: i64) -> Result<usize, PiccoloError> {
+        position(index, self.values.len(), self.values.len() + 1)
+    }
+
+    /// The items at the positions in a range, like [`index`] except that the end of
+    /// the range may be the length of the list. Only ranges counting up are allowed.
+    ///
+    /// [`index`]: #method.index
+    pub(crate) fn slice(&self, range: &Range) -> Result<Vec<Value>, PiccoloError> {
+        if range.step <= 0 {
+            return Err(PiccoloError::new(RuntimeError::InvalidStep {
+                step: range.step,
+            }));
+        }
+
+        Ok(self.values[range.bounds(self.values.len())?]
+            .iter()
+            .step_by(range.step as usize)
+            .copied()
+            .collect())
+    }
+}
+
+// find the position `index` refers to in something of length `len`, counting back
+// from the end if it's negative. the position must be less than `limit`.
+fn position(index: i64, len: usize, limit: usize) -> Result<usize, PiccoloError> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    if 0 <= position && (position as usize) < limit {
+        Ok(position as usize)
+    } else {
+        Err(PiccoloError::new(RuntimeError::IndexOutOfBounds {
+            index,
+            len,
+        }))
+    }
+}
+
+/// A range of integers, created with `start..end`, or `start...end` to include the end.
+///
+/// Ranges count up by one unless given another step with `by`. A range that would
+/// have to count away from its end is empty, so `5..1` has no values, but
+/// `(5..1).by(-1)` has 5, 4, 3, and 2. The `start`, `stop`, and `step` of a range can
+/// be read like fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    start: i64,
+    end: i64,
+    step: i64,
+    inclusive: bool,
+}
+
+impl Object for Range {
+    fn type_name(&self) -> &'static str {
+        "range"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Range
+    }
+
+    fn eq(&self, other: &dyn Object) -> Option<bool> {
+        other.downcast_ref::<Range>().map(|other| other == self)
+    }
+
+    fn get(&self, property: &str) -> Option<Value> {
+        match property {
+            "start" => Some(Value::Integer(self.start)),
+            "stop" => Some(Value::Integer(self.end)),
+            "step" => Some(Value::Integer(self.step)),
+            _ => None,
+        }
+    }
+
+    fn iterate(&self, position: usize) -> Option<Option<Value>> {
+        Some(self.nth(position).map(Value::Integer))
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Object>> {
+        Some(Box::new(*self))
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dots = if self.inclusive { "..." } else { ".." };
+        write!(f, "{}{}{}", self.start, dots, self.end)?;
+        if self.step != 1 {
+            write!(f, " by {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+impl Range {
+    pub(crate) fn new(start: i64, end: i64, inclusive: bool) -> Self {
+        Range {
+            start,
+            end,
+            step: 1,
+            inclusive,
+        }
+    }
+
+    /// The same range, counting by `step` instead.
+    pub(crate) fn by(&self, step: i64) -> Result<Range, PiccoloError> {
+        if step == 0 {
+            return Err(PiccoloError::new(RuntimeError::InvalidStep { step }));
+        }
+        Ok(Range { step, ..*self })
+    }
+
+    /// The number of values in the range.
+    pub(crate) fn len(&self) -> usize {
+        // i128 so that counting across all of i64 can't overflow
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        let distance = if step > 0 { end - start } else { start - end };
+        let distance = if self.inclusive {
+            distance + 1
+        } else {
+            distance
+        };
+        if distance <= 0 {
+            0
+        } else {
+            ((distance - 1) / step.abs() + 1).min(usize::MAX as i128) as usize
+        }
+    }
+
+    /// The `n`th value of the range, counting from zero.
+    pub(crate) fn nth(&self, n: usize) -> Option<i64> {
+        if n < self.len() {
+            Some((self.start as i128 + n as i128 * self.step as i128) as i64)
+        } else {
+            None
+        }
+    }
+
+    /// The positions the range covers in something of length `len`, where a negative
+    /// start or end counts back from the end. The step is ignored.
+    pub(crate) fn bounds(&self, len: usize) -> Result<core::ops::Range<usize>, PiccoloError> {
+        let start = position(self.start, len, len + 1)?;
+        let end = if self.inclusive {
+            position(self.end, len, len)? + 1
+        } else {
+            position(self.end, len, len + 1)?
+        };
+        Ok(start..end.max(start))
+    }
+
+    pub(crate) fn step(&self) -> i64 {
+        self.step
+    }
+
+    pub(crate) fn contains(&self, value: i64) -> bool {
+        let offset = value as i128 - self.start as i128;
+        let step = self.step as i128;
+        offset % step == 0 && 0 <= offset / step && ((offset / step) as usize) < self.len()
+    }
+}
+
+/// A map from keys to values, created with a map literal like `{name: "x", 1: 2}`.
+///
+/// Keys may be strings, integers, doubles, booleans, nil, instances, or data types.
+/// Entries are kept in the order their keys were first inserted, so iterating over a
+/// map always visits its entries in the same order. Like lists, maps are shared by
+/// reference.
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    indices: FnvHashMap<MapKey, usize>,
+}
+
+impl Object for Map {
+    fn type_name(&self) -> &'static str {
+        "map"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Map
+    }
+
+    // loops go over the keys
+    fn iterate(&self, position: usize) -> Option<Option<Value>> {
+        Some(self.entries.get(position).map(|(key, _)| *key))
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Object>> {
+        Some(Box::new(Map {
+            entries: self.entries.clone(),
+            indices: self.indices.clone(),
+        }))
+    }
+
+    fn trace(&self, values: &mut Vec<Value>) {
+        for (key, value) in &self.entries {
+            values.push(*key);
+            values.push(*value);
+        }
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<map of {}>", self.entries.len())
+    }
+}
+
+impl Map {
+    pub(crate) fn new() -> Self {
+        Map::default()
     }
 
-    pub(crate) fn into_chunk(self) -> super::chunk::Chunk {
-        self.chunk
+    /// The keys and values of the map, in insertion order.
+    pub(crate) fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub(crate) fn get(&self, key: MapKey) -> Option<Value> {
+        self.indices.get(&key).map(|&i| self.entries[i].1)
+    }
+
+    pub(crate) fn contains(&self, key: &MapKey) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Set the value of a key. A key that is already in the map keeps its position,
+    /// and the value it was first inserted with.
+    pub(crate) fn insert(&mut self, key: MapKey, key_value: Value, value: Value) {
+        match self.indices.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.indices.insert(key, self.entries.len());
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: MapKey) -> Option<Value> {
+        let i = self.indices.remove(&key)?;
+        let (_, value) = self.entries.remove(i);
+        for index in self.indices.values_mut() {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+        Some(value)
+    }
+}
+
+/// A value that can be hashed as the key of a [`Map`].
+///
+/// Doubles with an integer value become integers, so that keys which are equal
+/// according to [`Heap::eq`] are the same key.
+///
+/// [`Map`]: ./struct.Map.html
+/// [`Heap::eq`]: ../memory/struct.Heap.html#method.eq
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(not(feature = "bigint"), derive(Copy))]
+pub(crate) enum MapKey {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Double(u64),
+    String(StringPtr),
+    Object(HeapPtr),
+    #[cfg(feature = "bigint")]
+    BigInteger(num_bigint::BigInt),
+}
+
+impl MapKey {
+    /// Get the key for a value, or an error if the value can't be hashed.
+    pub(crate) fn new(heap: &Heap, value: Value, op: Opcode) -> Result<MapKey, PiccoloError> {
+        let key = match value {
+            Value::Nil => Some(MapKey::Nil),
+            Value::Bool(v) => Some(MapKey::Bool(v)),
+            Value::Integer(v) => Some(MapKey::Integer(v)),
+            // NaN is never equal to anything, so it could never be found again
+            Value::Double(v) if v.is_nan() => None,
+            Value::Double(v) if v.fract() == 0.0 && v >= i64::MIN as f64 && v < i64::MAX as f64 => {
+                Some(MapKey::Integer(v as i64))
+            }
+            #[cfg(feature = "bigint")]
+            Value::Double(v) if v.fract() == 0.0 => {
+                num_traits::FromPrimitive::from_f64(v).map(MapKey::BigInteger)
+            }
+            Value::Double(v) => Some(MapKey::Double(v.to_bits())),
+            Value::String(ptr) => Some(MapKey::String(ptr)),
+            #[cfg(feature = "bigint")]
+            Value::Object(_) if super::bigint::is_big(heap, value) => {
+                super::bigint::get(heap, value).map(MapKey::BigInteger)
+            }
+            Value::Object(ptr) if heap.deref(value).hashable() => Some(MapKey::Object(ptr.idx)),
+            Value::Object(_) => None,
+        };
+
+        key.ok_or_else(|| {
+            PiccoloError::new(RuntimeError::IncorrectType {
+                exp: "hashable key".into(),
+                got: heap.type_name(&value).to_owned(),
+                op,
+            })
+        })
+    }
+}
+
+/// Signature of a function implemented in Rust.
+///
+/// Native functions get access to the [`Heap`] of the [`Machine`] calling them, and
+/// the arguments they were called with.
+///
+/// [`Heap`]: ../memory/struct.Heap.html
+/// [`Machine`]: ../vm/struct.Machine.html
+pub type NativeFn = dyn Fn(&mut Heap, &[Value]) -> Result<Value, PiccoloError>;
+
+/// A function implemented in Rust, callable from Piccolo.
+#[derive(Clone)]
+pub struct NativeFunction {
+    arity: usize,
+    name: String,
+    function: Rc<NativeFn>,
+}
+
+impl Object for NativeFunction {
+    fn type_name(&self) -> &'static str {
+        "native function"
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::NativeFunction
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({}, {})", self.name, self.arity)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl NativeFunction {
+    pub(crate) fn new(name: String, arity: usize, function: Rc<NativeFn>) -> Self {
+        NativeFunction {
+            arity,
+            name,
+            function,
+        }
+    }
+
+    pub(crate) fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn function(&self) -> Rc<NativeFn> {
+        Rc::clone(&self.function)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Function, List, Map, MapKey, Object, Range};
+    use crate::runtime::{memory::Heap, op::Opcode};
+    use crate::{Constant, Session, Value};
+
+    #[test]
+    fn iterate() {
+        let values = |object: &dyn Object| {
+            (0..)
+                .map_while(|position| object.iterate(position).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let list = List::new(vec![Value::Integer(1), Value::Nil]);
+        assert_eq!(values(&list), vec![Value::Integer(1), Value::Nil]);
+        let range = Range::new(5, 0, true).by(-2).unwrap();
+        assert_eq!(
+            values(&range),
+            vec![Value::Integer(5), Value::Integer(3), Value::Integer(1)]
+        );
+
+        // maps are iterated over by key, in insertion order
+        let heap = Heap::new(8);
+        let mut map = Map::new();
+        for (key, value) in [(2, 0), (1, 0), (2, 3)] {
+            let (key, value) = (Value::Integer(key), Value::Integer(value));
+            map.insert(MapKey::new(&heap, key, Opcode::Map).unwrap(), key, value);
+        }
+        assert_eq!(values(&map), vec![Value::Integer(2), Value::Integer(1)]);
+
+        assert_eq!(Function::default().iterate(0), None);
+    }
+
+    #[test]
+    fn host_iterable() {
+        // counts down from a number to one
+        #[derive(Debug)]
+        struct Countdown(i64);
+
+        impl Object for Countdown {
+            fn iterate(&self, position: usize) -> Option<Option<Value>> {
+                let value = self.0 - position as i64;
+                Some(if value > 0 {
+                    Some(Value::Integer(value))
+                } else {
+                    None
+                })
+            }
+        }
+
+        impl core::fmt::Display for Countdown {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "<countdown from {}>", self.0)
+            }
+        }
+
+        let mut session = Session::new();
+        session.machine().register_fn("countdown", 1, |heap, args| {
+            Ok(heap.alloc(Box::new(Countdown(args[0].into()))))
+        });
+        assert_eq!(
+            session
+                .eval("xs =: []\nfor x in countdown(3) do\n  xs.push(x)\nend\nretn xs")
+                .unwrap(),
+            Constant::List(vec![
+                Constant::Integer(3),
+                Constant::Integer(2),
+                Constant::Integer(1)
+            ])
+        );
     }
 }
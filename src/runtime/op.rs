@@ -1,6 +1,7 @@
 //! The compile-time representation of Piccolo code.
 //!
-//! Opcode bytes are currently unstable. Operands are little-endian.
+//! Opcode bytes are currently unstable, and [`OPCODE_VERSION`] changes whenever they
+//! do. Operands are little-endian.
 //!
 //! Index means the index in the chunk's constant table, and slot means the
 //! index from the bottom of the call frame on the [`Machine`] stack.
@@ -39,10 +40,37 @@
 //! | `JumpFalse`       | forward offset            | `0x18` |
 //! | `JumpTrue`        | forward offset            | `0x19` |
 //! | `Loop`            | backward offset           | `0x1A` |
+//! | <b>Functions</b>  |                           |        |
+//! | `Call`            | number of arguments       | `0x20` |
+//! | `Closure`         | index into constant table | `0x21` |
+//! | `GetUpvalue`      | index into upvalues       | `0x22` |
+//! | `SetUpvalue`      | index into upvalues       | `0x23` |
+//! | `CloseUpvalue`    |                           | `0x24` |
+//! | <b>Data</b>       |                           |        |
+//! | `Data`            | index into constant table | `0x25` |
+//! | `Field`           | index into constant table | `0x26` |
+//! | `Method`          | index into constant table | `0x27` |
+//! | `New`             | number of fields          | `0x28` |
+//! | `GetProperty`     | index into constant table | `0x29` |
+//! | `SetProperty`     | index into constant table | `0x2a` |
+//! | <b>Collections</b>|                           |        |
+//! | `List`            | number of items           | `0x2b` |
+//! | `GetIndex`        |                           | `0x2c` |
+//! | `SetIndex`        |                           | `0x2d` |
+//! | `Map`             | number of entries         | `0x2e` |
+//! | `ExclusiveRange`  |                           | `0x2f` |
+//! | `InclusiveRange`  |                           | `0x30` |
+//! | `Iterate`         |                           | `0x31` |
+//! | `IterItem`        |                           | `0x32` |
 //! | <b>Misc</b>       |                           |        |
 //! | `Assert`          |                           | `0xff` |
 //!
 //! [`Machine`]: ../vm/struct.Machine.html
+//! [`OPCODE_VERSION`]: ./constant.OPCODE_VERSION.html
+
+/// Version of the opcode table, stored in compiled bytecode files. Increment this
+/// whenever an opcode is added, removed, renumbered, or changes its operands.
+pub const OPCODE_VERSION: u16 = 5;
 
 macro_rules! opcodes {
     ($name:ident => $($op:ident = $num:expr,)*) => {
@@ -53,9 +81,9 @@ macro_rules! opcodes {
             $($op = $num,)*
         }
 
-        impl Into<u8> for $name {
-            fn into(self) -> u8 {
-                match self {
+        impl From<$name> for u8 {
+            fn from(op: $name) -> u8 {
+                match op {
                     $($name::$op => $num,)*
                 }
             }
@@ -69,6 +97,16 @@ macro_rules! opcodes {
                 }
             }
         }
+
+        impl $name {
+            /// Convert a byte to an opcode, returning `None` if the byte isn't an opcode.
+            pub fn from_byte(v: u8) -> Option<$name> {
+                match v {
+                    $($num => Some($name::$op),)*
+                    _ => None,
+                }
+            }
+        }
     };
 }
 
@@ -112,6 +150,28 @@ opcodes!(Opcode =>
     ShiftLeft       = 0x1e,
     ShiftRight      = 0x1f,
 
+    Call            = 0x20,
+    Closure         = 0x21,
+    GetUpvalue      = 0x22,
+    SetUpvalue      = 0x23,
+    CloseUpvalue    = 0x24,
+
+    Data            = 0x25,
+    Field           = 0x26,
+    Method          = 0x27,
+    New             = 0x28,
+    GetProperty     = 0x29,
+    SetProperty     = 0x2a,
+
+    List            = 0x2b,
+    GetIndex        = 0x2c,
+    SetIndex        = 0x2d,
+    Map             = 0x2e,
+    ExclusiveRange  = 0x2f,
+    InclusiveRange  = 0x30,
+    Iterate         = 0x31,
+    IterItem        = 0x32,
+
     Assert          = 0xff,
 );
 
@@ -126,7 +186,19 @@ pub(crate) fn op_len(op: Opcode) -> usize {
         | Opcode::JumpForward
         | Opcode::JumpFalse
         | Opcode::JumpTrue
-        | Opcode::JumpBack => 3,
+        | Opcode::JumpBack
+        | Opcode::Call
+        | Opcode::Closure
+        | Opcode::GetUpvalue
+        | Opcode::SetUpvalue
+        | Opcode::Data
+        | Opcode::Field
+        | Opcode::Method
+        | Opcode::New
+        | Opcode::GetProperty
+        | Opcode::SetProperty
+        | Opcode::List
+        | Opcode::Map => 3,
         _ => 1,
     }
 }
@@ -3,7 +3,7 @@
 use crate::runtime::StringPtr;
 use crate::{PiccoloError, Token, TokenKind};
 
-use super::object::ObjectPtr;
+use super::object::{Function, ObjectPtr};
 
 use core::fmt;
 
@@ -69,27 +69,27 @@ impl Value {
     }
 }
 
-impl Into<bool> for Value {
-    fn into(self) -> bool {
-        match self {
+impl From<Value> for bool {
+    fn from(v: Value) -> bool {
+        match v {
             Value::Bool(v) => v,
             _ => panic!("could not cast to bool"),
         }
     }
 }
 
-impl Into<i64> for Value {
-    fn into(self) -> i64 {
-        match self {
+impl From<Value> for i64 {
+    fn from(v: Value) -> i64 {
+        match v {
             Value::Integer(v) => v,
             _ => panic!("could not cast to i64"),
         }
     }
 }
 
-impl Into<f64> for Value {
-    fn into(self) -> f64 {
-        match self {
+impl From<Value> for f64 {
+    fn from(v: Value) -> f64 {
+        match v {
             Value::Double(v) => v,
             _ => panic!("could not cast to f64"),
         }
@@ -101,12 +101,17 @@ impl Into<f64> for Value {
 /// Similar to [`Value`]. `Constant` is also used to return from Piccolo execution.
 ///
 /// [`Value`]: ../value/enum.Value.html
-#[derive(Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Constant {
     String(String),
     Bool(bool),
     Integer(i64),
     Double(f64),
+    Function(Function),
+    List(Vec<Constant>),
+    Map(Vec<(Constant, Constant)>),
+    #[cfg(feature = "bigint")]
+    BigInt(num_bigint::BigInt),
     Nil,
 }
 
@@ -122,6 +127,9 @@ impl Constant {
         Ok(match token.kind {
             TokenKind::String => Constant::String(crate::compiler::escape_string(&token)?),
             TokenKind::Integer(v) => Constant::Integer(v),
+            // the scanner only makes these from a string of digits
+            #[cfg(feature = "bigint")]
+            TokenKind::BigInteger => Constant::BigInt(token.lexeme.parse().unwrap()),
             TokenKind::True => Constant::Bool(true),
             TokenKind::False => Constant::Bool(false),
             TokenKind::Double(v) => Constant::Double(v),
@@ -141,6 +149,14 @@ impl Constant {
     pub fn is_string(&self) -> bool {
         matches!(self, Constant::String(_))
     }
+
+    // items of lists and maps show strings with quotes
+    fn fmt_item(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::String(v) => write!(f, "{:?}", v),
+            v => write!(f, "{}", v),
+        }
+    }
 }
 
 impl fmt::Display for Constant {
@@ -150,32 +166,57 @@ impl fmt::Display for Constant {
             Constant::Bool(v) => write!(f, "{}", v),
             Constant::Integer(v) => write!(f, "{}", v),
             Constant::Double(v) => write!(f, "{}", v),
+            Constant::Function(v) => write!(f, "{}", v),
+            Constant::List(v) => {
+                write!(f, "[")?;
+                for (i, item) in v.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_item(f)?;
+                }
+                write!(f, "]")
+            }
+            Constant::Map(v) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in v.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_item(f)?;
+                    write!(f, ": ")?;
+                    value.fmt_item(f)?;
+                }
+                write!(f, "}}")
+            }
+            #[cfg(feature = "bigint")]
+            Constant::BigInt(v) => write!(f, "{}", v),
             Constant::Nil => write!(f, "nil"),
         }
     }
 }
 
-impl Into<bool> for Constant {
-    fn into(self) -> bool {
-        match self {
+impl From<Constant> for bool {
+    fn from(v: Constant) -> bool {
+        match v {
             Constant::Bool(v) => v,
             _ => panic!("could not cast to bool"),
         }
     }
 }
 
-impl Into<i64> for Constant {
-    fn into(self) -> i64 {
-        match self {
+impl From<Constant> for i64 {
+    fn from(v: Constant) -> i64 {
+        match v {
             Constant::Integer(v) => v,
             _ => panic!("could not cast to i64"),
         }
     }
 }
 
-impl Into<f64> for Constant {
-    fn into(self) -> f64 {
-        match self {
+impl From<Constant> for f64 {
+    fn from(v: Constant) -> f64 {
+        match v {
             Constant::Double(v) => v,
             _ => panic!("could not cast to f64"),
         }
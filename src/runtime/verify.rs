@@ -0,0 +1,767 @@
+//! Checking that a [`Chunk`] is safe to run.
+//!
+//! The [`Machine`] trusts the bytecode it runs, and will panic if it's asked to read
+//! a constant or local that doesn't exist, jump into the middle of an instruction,
+//! or pop from an empty stack. Chunks produced by the compiler never do these
+//! things, but chunks loaded from elsewhere need to be verified first.
+//!
+//! [`Chunk`]: ../chunk/struct.Chunk.html
+//! [`Machine`]: ../vm/struct.Machine.html
+
+use crate::runtime::{
+    chunk::Chunk,
+    object::Function,
+    op::{op_len, Opcode},
+    ChunkOffset,
+};
+use crate::{BytecodeError, Constant, PiccoloError};
+
+/// Verify a chunk and every function in its constant table.
+///
+/// A chunk passes verification if every instruction is a real opcode with all of
+/// its operands, every constant, local, and upvalue it refers to exists, every
+/// jump lands on an instruction, and the stack is the same height every time an
+/// instruction is reached.
+pub fn verify(chunk: &Chunk) -> Result<(), PiccoloError> {
+    verify_chunk(chunk, None)
+}
+
+// the deepest stack a chunk may need. far deeper than anything the compiler
+// produces, but it keeps a chunk from claiming an absurd one.
+const MAX_STACK: usize = 1 << 20;
+
+// the abstract state of the stack before an instruction. since captured locals
+// have to be closed rather than popped, we keep track of which slots may have
+// been captured by a closure. captures are rare, so only those slots are stored.
+#[derive(Clone, PartialEq)]
+struct State {
+    depth: usize,
+    // in ascending order
+    captured: Vec<usize>,
+}
+
+impl State {
+    fn push(&mut self) {
+        self.depth += 1;
+    }
+
+    // pop a value, returning whether it may have been captured
+    fn pop(&mut self) -> bool {
+        self.depth -= 1;
+        if self.captured.last() == Some(&self.depth) {
+            self.captured.pop();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn capture(&mut self, slot: usize) {
+        if let Err(idx) = self.captured.binary_search(&slot) {
+            self.captured.insert(idx, slot);
+        }
+    }
+
+    // a slot may be captured if it was captured along any path. returns whether
+    // any slot wasn't already.
+    fn merge(&mut self, other: &State) -> bool {
+        let mut changed = false;
+        for slot in other.captured.iter() {
+            if self.captured.binary_search(slot).is_err() {
+                self.capture(*slot);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn verify_chunk(chunk: &Chunk, function: Option<&Function>) -> Result<(), PiccoloError> {
+    let lines = chunk
+        .lines
+        .iter()
+        .try_fold(0usize, |total, ops| total.checked_add(*ops));
+    if lines != Some(chunk.data.len()) {
+        return Err(invalid("line table does not match bytecode"));
+    }
+
+    // find the start of every instruction, and every place a jump may land. the
+    // state of the stack is only kept where jumps land, since every other
+    // instruction can only be reached from the one before it.
+    let mut starts = vec![false; chunk.data.len() + 1];
+    let mut targets = vec![false; chunk.data.len() + 1];
+    targets[0] = true;
+    let mut offset = 0;
+    while offset < chunk.data.len() {
+        let op = Opcode::from_byte(chunk.data[offset]).ok_or_else(|| {
+            invalid_at(
+                chunk,
+                offset,
+                &format!("unknown opcode {:#04x}", chunk.data[offset]),
+            )
+        })?;
+        starts[offset] = true;
+
+        let next = offset + op_len(op);
+        if next <= chunk.data.len() {
+            let target = match op {
+                Opcode::JumpForward | Opcode::JumpFalse | Opcode::JumpTrue => {
+                    next.checked_add(chunk.read_short(offset + 1) as usize)
+                }
+                Opcode::JumpBack => next.checked_sub(chunk.read_short(offset + 1) as usize),
+                _ => None,
+            };
+            if let Some(target) = target.filter(|target| *target <= chunk.data.len()) {
+                targets[target] = true;
+            }
+        }
+        offset = next;
+    }
+    if offset != chunk.data.len() {
+        return Err(invalid("last instruction is missing operands"));
+    }
+    starts[offset] = true;
+
+    for constant in chunk.constants.iter() {
+        verify_constant(constant, false)?;
+    }
+
+    // the callee and its arguments are already on the stack when a function starts
+    let start = function.map(|f| f.arity() + 1).unwrap_or(0);
+    if start > MAX_STACK {
+        return Err(invalid("function takes too many arguments"));
+    }
+    let mut states: Vec<Option<State>> = vec![None; chunk.data.len() + 1];
+    states[0] = Some(State {
+        depth: start,
+        captured: Vec::new(),
+    });
+
+    let mut work = vec![0];
+    while let Some(offset) = work.pop() {
+        verify_from(
+            chunk,
+            function,
+            offset,
+            &starts,
+            &targets,
+            &mut states,
+            &mut work,
+        )?;
+    }
+
+    Ok(())
+}
+
+// follow instructions from a place a jump lands until the next one, merging the
+// state of the stack into every jump target reached
+fn verify_from(
+    chunk: &Chunk,
+    function: Option<&Function>,
+    mut offset: ChunkOffset,
+    starts: &[bool],
+    targets: &[bool],
+    states: &mut [Option<State>],
+    work: &mut Vec<ChunkOffset>,
+) -> Result<(), PiccoloError> {
+    let upvalues = function.map(|f| f.captures().len()).unwrap_or(0);
+    let mut state = states[offset].clone().unwrap();
+    loop {
+        if offset == chunk.data.len() {
+            if function.is_some() {
+                return Err(invalid("function ends without returning"));
+            }
+            return Ok(());
+        }
+
+        let op = Opcode::from_byte(chunk.data[offset]).unwrap();
+        let arg = if op_len(op) == 3 {
+            chunk.read_short(offset + 1) as usize
+        } else {
+            0
+        };
+        let next = offset + op_len(op);
+        let err = |msg: &str| invalid_at(chunk, offset, &format!("{:?}: {}", op, msg));
+
+        let need = |state: &State, n: usize| {
+            if state.depth < n {
+                Err(err(&format!(
+                    "needs {} values on the stack, but there are {}",
+                    n, state.depth
+                )))
+            } else {
+                Ok(())
+            }
+        };
+
+        // check operands, and pop and push values
+        let mut successors = vec![next];
+        match op {
+            Opcode::Pop => {
+                need(&state, 1)?;
+                if state.pop() {
+                    return Err(err("popped a captured local without closing it"));
+                }
+                // popping the last value of the script returns it
+                if next == chunk.data.len() && function.is_none() {
+                    successors.clear();
+                }
+            }
+            Opcode::Return => {
+                need(&state, 1)?;
+                if state.pop() {
+                    return Err(err("returned a captured local without closing it"));
+                }
+                successors.clear();
+            }
+
+            Opcode::Constant => {
+                match chunk.constants.get(arg) {
+                    None => return Err(err(&format!("no constant {}", arg))),
+                    // functions that capture variables must be created with Closure
+                    Some(Constant::Function(f)) if !f.captures().is_empty() => {
+                        return Err(err("function with captures used as a constant"));
+                    }
+                    _ => {}
+                }
+                state.push();
+            }
+            Opcode::Nil | Opcode::True | Opcode::False => state.push(),
+
+            Opcode::Negate | Opcode::Not => need(&state, 1)?,
+            Opcode::Add
+            | Opcode::Subtract
+            | Opcode::Multiply
+            | Opcode::Divide
+            | Opcode::Modulo
+            | Opcode::Equal
+            | Opcode::Greater
+            | Opcode::Less
+            | Opcode::GreaterEqual
+            | Opcode::LessEqual
+            | Opcode::BitAnd
+            | Opcode::BitOr
+            | Opcode::BitXor
+            | Opcode::ShiftLeft
+            | Opcode::ShiftRight
+            | Opcode::ExclusiveRange
+            | Opcode::InclusiveRange
+            | Opcode::Iterate
+            | Opcode::IterItem => {
+                need(&state, 2)?;
+                pop(&mut state, 2, &err)?;
+                state.push();
+            }
+
+            Opcode::GetLocal => {
+                if arg >= state.depth {
+                    return Err(err(&format!("no local in slot {}", arg)));
+                }
+                state.push();
+            }
+            Opcode::SetLocal => {
+                need(&state, 1)?;
+                pop(&mut state, 1, &err)?;
+                if arg >= state.depth {
+                    return Err(err(&format!("no local in slot {}", arg)));
+                }
+            }
+            Opcode::GetGlobal => {
+                string_constant(chunk, arg, &err)?;
+                state.push();
+            }
+            Opcode::SetGlobal | Opcode::DeclareGlobal => {
+                string_constant(chunk, arg, &err)?;
+                need(&state, 1)?;
+                pop(&mut state, 1, &err)?;
+            }
+
+            Opcode::JumpForward => {
+                successors = vec![jump_target(starts, next, arg, true, &err)?];
+            }
+            Opcode::JumpFalse | Opcode::JumpTrue => {
+                need(&state, 1)?;
+                successors.push(jump_target(starts, next, arg, true, &err)?);
+            }
+            Opcode::JumpBack => {
+                successors = vec![jump_target(starts, next, arg, false, &err)?];
+            }
+
+            Opcode::Call => {
+                need(&state, arg + 1)?;
+                pop(&mut state, arg + 1, &err)?;
+                state.push();
+            }
+            Opcode::Closure => {
+                let f = match chunk.constants.get(arg) {
+                    Some(Constant::Function(f)) => f,
+                    _ => return Err(err(&format!("constant {} is not a function", arg))),
+                };
+                // a local function can capture the slot the closure is about to fill,
+                // so that it can call itself
+                state.push();
+                for capture in f.captures() {
+                    let index = capture.index as usize;
+                    if capture.is_local {
+                        if index >= state.depth {
+                            return Err(err(&format!("no local in slot {} to capture", index)));
+                        }
+                        state.capture(index);
+                    } else if index >= upvalues {
+                        return Err(err(&format!("no upvalue {} to capture", index)));
+                    }
+                }
+            }
+            Opcode::GetUpvalue | Opcode::SetUpvalue => {
+                if arg >= upvalues {
+                    return Err(err(&format!("no upvalue {}", arg)));
+                }
+                if op == Opcode::GetUpvalue {
+                    state.push();
+                } else {
+                    need(&state, 1)?;
+                    pop(&mut state, 1, &err)?;
+                }
+            }
+            Opcode::CloseUpvalue => {
+                need(&state, 1)?;
+                state.pop();
+            }
+
+            Opcode::Data => {
+                string_constant(chunk, arg, &err)?;
+                state.push();
+            }
+            Opcode::Field | Opcode::Method => {
+                string_constant(chunk, arg, &err)?;
+                need(&state, 2)?;
+                pop(&mut state, 1, &err)?;
+            }
+            Opcode::New => {
+                need(&state, arg * 2 + 1)?;
+                pop(&mut state, arg * 2 + 1, &err)?;
+                state.push();
+            }
+            Opcode::GetProperty => {
+                string_constant(chunk, arg, &err)?;
+                need(&state, 1)?;
+                pop(&mut state, 1, &err)?;
+                state.push();
+            }
+            Opcode::SetProperty => {
+                string_constant(chunk, arg, &err)?;
+                need(&state, 2)?;
+                pop(&mut state, 2, &err)?;
+                state.push();
+            }
+
+            Opcode::List => {
+                need(&state, arg)?;
+                pop(&mut state, arg, &err)?;
+                state.push();
+            }
+            Opcode::GetIndex => {
+                need(&state, 2)?;
+                pop(&mut state, 2, &err)?;
+                state.push();
+            }
+            Opcode::SetIndex => {
+                need(&state, 3)?;
+                pop(&mut state, 3, &err)?;
+                state.push();
+            }
+            Opcode::Map => {
+                need(&state, arg * 2)?;
+                pop(&mut state, arg * 2, &err)?;
+                state.push();
+            }
+
+            Opcode::Assert => {
+                need(&state, 1)?;
+                pop(&mut state, 1, &err)?;
+            }
+        }
+        if state.depth > MAX_STACK {
+            return Err(err(&format!("stack is deeper than {}", MAX_STACK)));
+        }
+
+        // keep going with the next instruction unless a jump may land there too
+        let mut fallthrough = None;
+        for successor in successors {
+            if !targets[successor] {
+                fallthrough = Some(successor);
+                continue;
+            }
+
+            match &mut states[successor] {
+                Some(old) => {
+                    if old.depth != state.depth {
+                        return Err(invalid_at(
+                            chunk,
+                            successor.min(chunk.data.len() - 1),
+                            &format!(
+                                "stack height is {} or {} depending on the path taken",
+                                old.depth, state.depth
+                            ),
+                        ));
+                    }
+                    if old.merge(&state) {
+                        work.push(successor);
+                    }
+                }
+                None => {
+                    states[successor] = Some(state.clone());
+                    work.push(successor);
+                }
+            }
+        }
+
+        match fallthrough {
+            Some(next) => offset = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+// verify the functions in a constant, including any inside a list or map. functions
+// nested in other constants can only be loaded with Constant, never Closure, so
+// they can't capture anything.
+fn verify_constant(constant: &Constant, nested: bool) -> Result<(), PiccoloError> {
+    match constant {
+        Constant::Function(f) => {
+            if nested && !f.captures().is_empty() {
+                return Err(invalid("function with captures nested in a constant"));
+            }
+            verify_chunk(f.chunk(), Some(f))
+        }
+        Constant::List(items) => items
+            .iter()
+            .try_for_each(|item| verify_constant(item, true)),
+        Constant::Map(entries) => entries.iter().try_for_each(|(key, value)| {
+            verify_constant(key, true)?;
+            verify_constant(value, true)
+        }),
+        _ => Ok(()),
+    }
+}
+
+// pop values that aren't captured locals
+fn pop(
+    state: &mut State,
+    n: usize,
+    err: &dyn Fn(&str) -> PiccoloError,
+) -> Result<(), PiccoloError> {
+    for _ in 0..n {
+        if state.pop() {
+            return Err(err("popped a captured local without closing it"));
+        }
+    }
+    Ok(())
+}
+
+fn string_constant(
+    chunk: &Chunk,
+    idx: usize,
+    err: &dyn Fn(&str) -> PiccoloError,
+) -> Result<(), PiccoloError> {
+    match chunk.constants.get(idx) {
+        Some(Constant::String(_)) => Ok(()),
+        _ => Err(err(&format!("constant {} is not a string", idx))),
+    }
+}
+
+// jumps are relative to the instruction after the jump
+fn jump_target(
+    starts: &[bool],
+    next: ChunkOffset,
+    offset: usize,
+    forward: bool,
+    err: &dyn Fn(&str) -> PiccoloError,
+) -> Result<ChunkOffset, PiccoloError> {
+    let target = if forward {
+        next.checked_add(offset)
+    } else {
+        next.checked_sub(offset)
+    };
+
+    match target {
+        Some(target) if starts.get(target) == Some(&true) => Ok(target),
+        _ => Err(err("jump does not land on an instruction")),
+    }
+}
+
+fn invalid(msg: &str) -> PiccoloError {
+    PiccoloError::new(BytecodeError::InvalidBytecode).msg(msg)
+}
+
+fn invalid_at(chunk: &Chunk, offset: ChunkOffset, msg: &str) -> PiccoloError {
+    PiccoloError::new(BytecodeError::InvalidBytecode)
+        .line(chunk.get_line_from_index(offset))
+        .msg_string(format!("{} at {:#x}", msg, offset))
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify;
+    use crate::compiler::emitter::{compile_ast, Emitter};
+    use crate::runtime::{chunk::Chunk, op::Opcode};
+    use crate::{parse, Constant, Scanner};
+
+    fn compile(src: &str) -> Chunk {
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let mut emitter = Emitter::new();
+        compile_ast(&mut emitter, &ast).unwrap();
+        emitter.into_chunk()
+    }
+
+    fn chunk(data: &[u8], constants: Vec<Constant>) -> Chunk {
+        Chunk {
+            data: data.to_vec(),
+            lines: vec![data.len()],
+            constants,
+        }
+    }
+
+    #[test]
+    fn test_files() {
+        fn visit(dir: &std::path::Path) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    visit(&path);
+                    continue;
+                }
+
+                let src = std::fs::read_to_string(&path).unwrap();
+                let ast = match parse(&mut Scanner::new(&src)) {
+                    Ok(ast) => ast,
+                    Err(_) => continue,
+                };
+                let mut emitter = Emitter::new();
+                if compile_ast(&mut emitter, &ast).is_ok() {
+                    let chunk = emitter.into_chunk();
+                    if let Err(e) = verify(&chunk) {
+                        panic!("{}: {}\n{}", path.display(), e, chunk.disassemble(""));
+                    }
+                }
+            }
+        }
+
+        visit(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/test_files"));
+    }
+
+    #[test]
+    fn loops() {
+        let chunk = compile(
+            "i =: 0\n\
+             while i < 10 do\n  x =: i\n  i += 1\n  if x == 3 do\n    continue\n  end\n\
+             if x == 7 do\n    break\n  end\nend\n\
+             for j =: 0, j < 3, j += 1 do\n  y =: j\n  fn f() do\n    retn y\n  end\n\
+             if j == 1 do\n    break\n  end\nend\n\
+             retn i",
+        );
+        verify(&chunk).unwrap();
+        assert_eq!(
+            crate::Machine::new().interpret(&chunk).unwrap(),
+            Constant::Integer(8)
+        );
+    }
+
+    #[test]
+    fn operands() {
+        use Opcode::*;
+
+        // unknown opcode
+        assert!(verify(&chunk(&[0xee], vec![])).is_err());
+        // missing operand
+        assert!(verify(&chunk(&[Constant as u8, 0], vec![])).is_err());
+        // missing constant
+        assert!(verify(&chunk(&[Constant as u8, 1, 0, Return as u8], vec![])).is_err());
+        // global name isn't a string
+        let constants = vec![crate::Constant::Integer(1)];
+        assert!(verify(&chunk(&[GetGlobal as u8, 0, 0, Pop as u8], constants)).is_err());
+        // no local
+        assert!(verify(&chunk(&[GetLocal as u8, 0, 0, Pop as u8], vec![])).is_err());
+        // no upvalue at the top level
+        assert!(verify(&chunk(&[GetUpvalue as u8, 0, 0, Pop as u8], vec![])).is_err());
+
+        let ok = chunk(
+            &[Nil as u8, GetLocal as u8, 0, 0, Add as u8, Pop as u8],
+            vec![],
+        );
+        verify(&ok).unwrap();
+    }
+
+    #[test]
+    fn jumps() {
+        use Opcode::*;
+
+        // into the middle of an instruction
+        let data = [JumpForward as u8, 1, 0, Constant as u8, 0, 0, Pop as u8];
+        assert!(verify(&chunk(&data, vec![crate::Constant::Nil])).is_err());
+        // past the end
+        let data = [JumpForward as u8, 5, 0, Nil as u8];
+        assert!(verify(&chunk(&data, vec![])).is_err());
+        // before the start
+        let data = [Nil as u8, JumpBack as u8, 5, 0];
+        assert!(verify(&chunk(&data, vec![])).is_err());
+        // to the end
+        let data = [JumpForward as u8, 1, 0, Nil as u8];
+        verify(&chunk(&data, vec![])).unwrap();
+    }
+
+    #[test]
+    fn stack_depth() {
+        use Opcode::*;
+
+        assert!(verify(&chunk(&[Pop as u8], vec![])).is_err());
+        assert!(verify(&chunk(&[Nil as u8, Add as u8], vec![])).is_err());
+
+        // one branch pushes an extra value
+        let data = [
+            True as u8,
+            JumpFalse as u8,
+            1,
+            0,
+            Nil as u8,
+            Nil as u8,
+            Return as u8,
+        ];
+        assert!(verify(&chunk(&data, vec![])).is_err());
+
+        // a loop that grows the stack every time around
+        let data = [Nil as u8, JumpBack as u8, 4, 0];
+        assert!(verify(&chunk(&data, vec![])).is_err());
+
+        // a deep stack is fine, up to a point
+        let mut data = vec![Nil as u8; 60000];
+        data.extend_from_slice(&[List as u8, 0x60, 0xea, Return as u8]);
+        verify(&chunk(&data, vec![])).unwrap();
+        let mut data = vec![Nil as u8; super::MAX_STACK + 1];
+        data.push(Return as u8);
+        assert!(verify(&chunk(&data, vec![])).is_err());
+    }
+
+    #[test]
+    fn functions() {
+        use Opcode::*;
+
+        let function = |data: &[u8], arity, captures| {
+            crate::Constant::Function(crate::runtime::object::Function::new(
+                String::from("f"),
+                arity,
+                chunk(data, vec![]),
+                captures,
+            ))
+        };
+
+        // arguments are locals
+        let f = function(&[GetLocal as u8, 1, 0, Return as u8], 1, vec![]);
+        verify(&chunk(&[Closure as u8, 0, 0, Pop as u8], vec![f])).unwrap();
+
+        let f = function(&[GetLocal as u8, 2, 0, Return as u8], 1, vec![]);
+        assert!(verify(&chunk(&[Closure as u8, 0, 0, Pop as u8], vec![f])).is_err());
+
+        // falling off the end of a function
+        let f = function(&[Nil as u8, Pop as u8], 0, vec![]);
+        assert!(verify(&chunk(&[Closure as u8, 0, 0, Pop as u8], vec![f])).is_err());
+
+        // captured locals must be closed, not popped
+        let capture = crate::runtime::object::Capture {
+            is_local: true,
+            index: 0,
+        };
+        let f = function(&[GetUpvalue as u8, 0, 0, Return as u8], 0, vec![capture]);
+        let data = [
+            Nil as u8,
+            Closure as u8,
+            0,
+            0,
+            Pop as u8,
+            CloseUpvalue as u8,
+            Nil as u8,
+            Return as u8,
+        ];
+        verify(&chunk(&data, vec![f.clone()])).unwrap();
+        let data = [
+            Nil as u8,
+            Closure as u8,
+            0,
+            0,
+            Pop as u8,
+            Pop as u8,
+            Nil as u8,
+            Return as u8,
+        ];
+        assert!(verify(&chunk(&data, vec![f.clone()])).is_err());
+        let data = [Nil as u8, Closure as u8, 0, 0, Pop as u8, Return as u8];
+        assert!(verify(&chunk(&data, vec![f.clone()])).is_err());
+
+        // capturing functions can't be loaded as plain constants
+        let data = [Nil as u8, Constant as u8, 0, 0, Return as u8];
+        assert!(verify(&chunk(&data, vec![f])).is_err());
+    }
+
+    #[test]
+    fn nested_functions() {
+        use Opcode::*;
+
+        let function = |data: &[u8], captures| {
+            crate::Constant::Function(crate::runtime::object::Function::new(
+                String::from("f"),
+                0,
+                chunk(data, vec![]),
+                captures,
+            ))
+        };
+        let data = [
+            Constant as u8,
+            0,
+            0,
+            Constant as u8,
+            1,
+            0,
+            GetIndex as u8,
+            Call as u8,
+            0,
+            0,
+            Return as u8,
+        ];
+
+        let f = function(&[Nil as u8, Return as u8], vec![]);
+        let list = crate::Constant::List(vec![f]);
+        verify(&chunk(&data, vec![list, crate::Constant::Integer(0)])).unwrap();
+
+        // functions inside lists are verified too
+        let f = function(&[GetLocal as u8, 99, 0, Return as u8], vec![]);
+        let list = crate::Constant::List(vec![crate::Constant::List(vec![f])]);
+        assert!(verify(&chunk(&data, vec![list, crate::Constant::Integer(0)])).is_err());
+
+        // and can't capture anything
+        let capture = crate::runtime::object::Capture {
+            is_local: true,
+            index: 0,
+        };
+        let f = function(&[GetUpvalue as u8, 0, 0, Return as u8], vec![capture]);
+        let list = crate::Constant::List(vec![f.clone()]);
+        assert!(verify(&chunk(&data, vec![list, crate::Constant::Integer(0)])).is_err());
+
+        // the same goes for map keys and values
+        let map = |key, value| crate::Constant::Map(vec![(key, value)]);
+        let ok = function(&[Nil as u8, Return as u8], vec![]);
+        let bad = function(&[GetLocal as u8, 99, 0, Return as u8], vec![]);
+        let key = crate::Constant::Integer(0);
+        verify(&chunk(
+            &data,
+            vec![map(key.clone(), ok.clone()), key.clone()],
+        ))
+        .unwrap();
+        assert!(verify(&chunk(
+            &data,
+            vec![map(key.clone(), bad.clone()), key.clone()]
+        ))
+        .is_err());
+        assert!(verify(&chunk(&data, vec![map(bad, ok), key.clone()])).is_err());
+        assert!(verify(&chunk(&data, vec![map(key.clone(), f), key])).is_err());
+    }
+}
@@ -1,27 +1,256 @@
 //! Contains `Machine`, the Piccolo bytecode interpreter.
 
-use crate::runtime::{memory::Heap, ChunkOffset};
-use crate::{Chunk, Constant, ErrorKind, PiccoloError, Value};
+use crate::runtime::{
+    builtin::{string_slice, BUILTINS, METHODS},
+    memory::Heap,
+    object::{
+        Closure, DataType, Function, Instance, List, Map, MapKey, Method, NativeFunction, Object,
+        Range, Upvalue,
+    },
+    ChunkOffset,
+};
+use crate::{Chunk, Constant, PiccoloError, RuntimeError, StackFrame, Value};
 
 use super::op::Opcode;
 
 //use std::collections::HashMap;
 
 use fnv::FnvHashMap as HashMap;
+use fnv::FnvHashSet as HashSet;
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Instant;
+
+// A function invocation. Local variable slots are relative to `base`.
+struct Frame {
+    // None for the chunk passed to Machine::interpret
+    chunk: Option<Rc<Chunk>>,
+    // the closure being called, for looking up upvalues
+    closure: Value,
+    base: usize,
+    return_ip: ChunkOffset,
+}
+
+/// What a [`Machine`] does when integer arithmetic overflows.
+///
+/// Shifting left overflows when it loses bits, including the sign bit. Integer
+/// division or modulo by zero and shifting by a negative amount are runtime errors
+/// regardless of the policy, and so is shifting by 64 or more unless the policy is
+/// `BigInt`. Arithmetic on doubles follows IEEE 754 and is never affected.
+///
+/// [`Machine`]: struct.Machine.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    /// Overflow is a runtime error.
+    #[cfg_attr(not(feature = "bigint"), default)]
+    Checked,
+    /// Overflow wraps around in two's complement.
+    Wrapping,
+    /// Overflowing operations produce a double instead.
+    Promote,
+    /// Overflowing operations produce a big integer instead. This is the default with
+    /// the `bigint` feature.
+    #[cfg(feature = "bigint")]
+    #[default]
+    BigInt,
+}
+
+/// A thread-safe handle for stopping a [`Machine`] from another thread.
+///
+/// Cancelling stops the script the machine is running, or the next one it runs if
+/// it isn't running one, with [`RuntimeError::Cancelled`]. After that the machine
+/// runs scripts normally again.
+///
+/// [`Machine`]: struct.Machine.html
+/// [`RuntimeError::Cancelled`]: ../../error/enum.RuntimeError.html#variant.Cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Which of the language's builtins a script may use, and which globals it may not
+/// assign to. The default allows every builtin and has no read-only globals.
+///
+/// Builtin functions like `print` are allowed by name, and builtin methods are
+/// allowed by the type they're for, like `"string"` or `"list"`. Functions added with
+/// [`Machine::register_fn`] are chosen by the host, and are always available.
+///
+/// Assigning to or redeclaring a read-only global is a
+/// [`RuntimeError::ReadOnlyGlobal`]. The host can still set it, and a script can
+/// still change the value it holds, like pushing to a list.
+///
+/// ```rust
+/// # use piccolo::prelude::*;
+/// let policy = Policy::deny_all()
+///     .allow_methods("string")
+///     .read_only("limit");
+/// let mut machine = Machine::with_config(MachineConfig {
+///     policy,
+///     ..MachineConfig::default()
+/// });
+/// machine.set_global("limit", Constant::Integer(10));
+/// let mut session = Session::with_machine(machine);
+///
+/// assert!(session.eval("print(limit)").is_err());
+/// assert!(session.eval("limit = 11").is_err());
+/// assert!(session.eval("limit =: 11").is_err());
+/// assert_eq!(session.eval("retn \"abc\".len()").unwrap(), Constant::Integer(3));
+/// assert!(session.eval("retn [1, 2].len()").is_err());
+/// ```
+///
+/// [`Machine::register_fn`]: struct.Machine.html#method.register_fn
+/// [`RuntimeError::ReadOnlyGlobal`]: ../../error/enum.RuntimeError.html#variant.ReadOnlyGlobal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    // None allows all of them
+    natives: Option<HashSet<String>>,
+    methods: Option<HashSet<String>>,
+    read_only: HashSet<String>,
+}
+
+impl Policy {
+    /// A policy that allows every builtin. Same as `Policy::default()`.
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    /// A policy that allows no builtins, until they are allowed with
+    /// [`allow_native`] or [`allow_methods`].
+    ///
+    /// [`allow_native`]: #method.allow_native
+    /// [`allow_methods`]: #method.allow_methods
+    pub fn deny_all() -> Self {
+        Policy {
+            natives: Some(HashSet::default()),
+            methods: Some(HashSet::default()),
+            read_only: HashSet::default(),
+        }
+    }
+
+    /// Allow the builtin function `name`. Does nothing if every builtin function is
+    /// already allowed.
+    pub fn allow_native(mut self, name: &str) -> Self {
+        if let Some(natives) = &mut self.natives {
+            natives.insert(String::from(name));
+        }
+        self
+    }
+
+    /// Allow the builtin methods of the type `type_name`. Does nothing if every
+    /// builtin method is already allowed.
+    pub fn allow_methods(mut self, type_name: &str) -> Self {
+        if let Some(methods) = &mut self.methods {
+            methods.insert(String::from(type_name));
+        }
+        self
+    }
+
+    /// Make the global `name` read-only to scripts.
+    pub fn read_only(mut self, name: &str) -> Self {
+        self.read_only.insert(String::from(name));
+        self
+    }
+
+    /// Whether scripts may call the native function `name`.
+    // `Option::is_none_or` is newer than the Rust versions piccolo builds with
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn allows_native(&self, name: &str) -> bool {
+        self.natives
+            .as_ref()
+            .map_or(true, |natives| natives.contains(name))
+    }
+
+    /// Whether scripts may call the builtin methods of the type `type_name`.
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn allows_methods(&self, type_name: &str) -> bool {
+        self.methods
+            .as_ref()
+            .map_or(true, |methods| methods.contains(type_name))
+    }
+
+    /// Whether the global `name` is read-only to scripts.
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.read_only.contains(name)
+    }
+}
+
+/// Settings for running untrusted scripts on a [`Machine`]: limits on the memory it
+/// may use, and the [`Policy`] for what scripts may do. Each limit is off when
+/// `None`, which is the default.
+///
+/// Going over a heap limit is a [`RuntimeError::OutOfMemory`], and going over a
+/// stack limit is a [`RuntimeError::StackOverflow`].
+///
+/// ```rust
+/// # use piccolo::prelude::*;
+/// let config = MachineConfig {
+///     max_frames: Some(100),
+///     ..MachineConfig::default()
+/// };
+/// let mut session = Session::with_machine(Machine::with_config(config));
+/// assert!(session.eval("fn f() do retn f() end\nf()").is_err());
+/// ```
+///
+/// [`Machine`]: struct.Machine.html
+/// [`Policy`]: struct.Policy.html
+/// [`RuntimeError::OutOfMemory`]: ../../error/enum.RuntimeError.html#variant.OutOfMemory
+/// [`RuntimeError::StackOverflow`]: ../../error/enum.RuntimeError.html#variant.StackOverflow
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MachineConfig {
+    /// The most objects the heap may hold at once, including the machine's builtin
+    /// functions. Every item in a list and every entry in a map counts as an object.
+    pub max_objects: Option<usize>,
+    /// The most bytes the heap's strings may take up in total.
+    pub max_string_bytes: Option<usize>,
+    /// The most values the stack may hold, counting local variables and temporaries.
+    pub max_stack: Option<usize>,
+    /// The most function calls that may be in progress at once.
+    pub max_frames: Option<usize>,
+    /// Which builtins scripts may use, and which globals they may not assign to.
+    pub policy: Policy,
+}
 
 /// Interprets compiled Piccolo bytecode.
 ///
 /// Contains a [`Chunk`] from which it executes instructions, a global variable hash
-/// table, a stack for temporary values and local variables, and a [`Heap`] for long-lived
-/// objects that require heap allocation, like strings, class instances, and others.
+/// table, a stack for temporary values and local variables, a stack of call frames,
+/// and a [`Heap`] for long-lived objects that require heap allocation, like strings,
+/// class instances, and others.
 ///
 /// [`Chunk`]: ../chunk/struct.Chunk.html
 /// [`Heap`]: ../memory/struct.Heap.html
 pub struct Machine {
     ip: ChunkOffset,
+    // the start of the instruction being run, which errors and backtraces point at
+    inst: ChunkOffset,
     globals: HashMap<String, Value>,
     stack: Vec<Value>,
+    frames: Vec<Frame>,
+    open_upvalues: Vec<Value>,
+    // builtin methods, by the name of the type they're for and then their own name
+    methods: HashMap<&'static str, HashMap<&'static str, Value>>,
     heap: Heap,
+    arithmetic: Arithmetic,
+    // instructions left to run, if limited
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    cancel: CancelHandle,
+    config: MachineConfig,
 }
 
 impl Default for Machine {
@@ -33,12 +262,249 @@ impl Default for Machine {
 impl Machine {
     /// Creates a new machine from a chunk.
     pub fn new() -> Self {
-        Machine {
+        Machine::with_config(MachineConfig::default())
+    }
+
+    /// Creates a new machine with limits on its memory use and what scripts may do.
+    pub fn with_config(config: MachineConfig) -> Self {
+        let mut heap = Heap::new(1024);
+        heap.set_limits(config.max_objects, config.max_string_bytes);
+        let mut machine = Machine {
             ip: 0,
+            inst: 0,
             globals: HashMap::default(),
             stack: Vec::new(),
-            heap: Heap::new(1024),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            methods: HashMap::default(),
+            heap,
+            arithmetic: Arithmetic::default(),
+            fuel: None,
+            deadline: None,
+            cancel: CancelHandle::default(),
+            config,
+        };
+
+        for (name, arity, function) in BUILTINS {
+            if machine.config.policy.allows_native(name) {
+                machine.register_fn(name, *arity, *function);
+            }
+        }
+
+        for (type_name, methods) in METHODS {
+            if !machine.config.policy.allows_methods(type_name) {
+                continue;
+            }
+            for (name, arity, function) in methods.iter() {
+                let native = NativeFunction::new(String::from(*name), *arity, Rc::new(*function));
+                let ptr = machine.heap.alloc(Box::new(native));
+                machine
+                    .methods
+                    .entry(type_name)
+                    .or_default()
+                    .insert(name, ptr);
+            }
         }
+
+        machine
+    }
+
+    /// Register a function implemented in Rust as a global variable, so that it may
+    /// be called from Piccolo like any other function. Calling it with a number of
+    /// arguments other than `arity` is an error.
+    ///
+    /// The name must also be declared to the [`Emitter`] compiling scripts that use
+    /// the function, with [`Emitter::declare_global`].
+    ///
+    /// ```rust
+    /// # use piccolo::prelude::*;
+    /// let mut machine = Machine::new();
+    /// machine.register_fn("double", 1, |_heap, args| match args[0] {
+    ///     Value::Integer(v) => Ok(Value::Integer(v * 2)),
+    ///     _ => Ok(Value::Nil),
+    /// });
+    ///
+    /// let mut emitter = Emitter::new();
+    /// emitter.declare_global("double");
+    /// let ast = parse(&mut Scanner::new("retn double(21)")).unwrap();
+    /// piccolo::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+    ///
+    /// let result = machine.interpret(emitter.current_chunk()).unwrap();
+    /// assert_eq!(result, Constant::Integer(42));
+    /// ```
+    ///
+    /// [`Emitter`]: ../../compiler/emitter/struct.Emitter.html
+    /// [`Emitter::declare_global`]: ../../compiler/emitter/struct.Emitter.html#method.declare_global
+    pub fn register_fn<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut Heap, &[Value]) -> Result<Value, PiccoloError> + 'static,
+    {
+        let native = NativeFunction::new(String::from(name), arity, Rc::new(function));
+        let ptr = self.heap.alloc(Box::new(native));
+        self.globals.insert(String::from(name), ptr);
+    }
+
+    /// Free every object on the [`Heap`] that is no longer reachable from the stack,
+    /// the globals, the call frames, or an open upvalue. This happens automatically
+    /// while interpreting once enough objects have been allocated.
+    ///
+    /// [`Heap`]: ../memory/struct.Heap.html
+    pub fn gc(&mut self) {
+        let roots = self
+            .stack
+            .iter()
+            .chain(self.globals.values())
+            .chain(self.frames.iter().map(|frame| &frame.closure))
+            .chain(self.open_upvalues.iter())
+            .chain(self.methods.values().flat_map(HashMap::values))
+            .copied()
+            .collect::<Vec<_>>();
+        self.heap.gc(roots);
+    }
+
+    /// Set a global variable, defining it if it doesn't exist yet.
+    ///
+    /// Like [`register_fn`], the name must be declared with [`Emitter::declare_global`]
+    /// before compiling a script that uses the variable.
+    ///
+    /// ```rust
+    /// # use piccolo::prelude::*;
+    /// let mut machine = Machine::new();
+    /// machine.set_global("x", Constant::Integer(2));
+    ///
+    /// let mut emitter = Emitter::new();
+    /// emitter.declare_global("x");
+    /// let ast = parse(&mut Scanner::new("y =: x * 3")).unwrap();
+    /// piccolo::compiler::emitter::compile_ast(&mut emitter, &ast).unwrap();
+    /// machine.interpret(emitter.current_chunk()).unwrap();
+    ///
+    /// assert_eq!(machine.get_global("y"), Some(Constant::Integer(6)));
+    /// ```
+    ///
+    /// [`register_fn`]: #method.register_fn
+    /// [`Emitter::declare_global`]: ../../compiler/emitter/struct.Emitter.html#method.declare_global
+    pub fn set_global(&mut self, name: &str, value: Constant) {
+        let value = self.heap.constant_into_value(&value);
+        self.globals.insert(String::from(name), value);
+    }
+
+    /// Get the value of a global variable.
+    ///
+    /// Returns `None` if the global doesn't exist, or if its value can't be
+    /// represented as a [`Constant`], like a native function, a data instance, or a
+    /// closure that has captured variables.
+    ///
+    /// [`Constant`]: ../value/enum.Constant.html
+    pub fn get_global(&self, name: &str) -> Option<Constant> {
+        let value = self.globals.get(name)?;
+        self.heap.try_value_into_constant(*value)
+    }
+
+    /// Remove a global variable, returning its value if it had one that can be
+    /// represented as a [`Constant`]. See [`get_global`].
+    ///
+    /// [`Constant`]: ../value/enum.Constant.html
+    /// [`get_global`]: #method.get_global
+    pub fn remove_global(&mut self, name: &str) -> Option<Constant> {
+        let value = self.globals.remove(name)?;
+        self.heap.try_value_into_constant(value)
+    }
+
+    /// Iterate over the names and values of the global variables. Globals whose
+    /// values can't be represented as a [`Constant`] are skipped. See [`get_global`].
+    ///
+    /// [`Constant`]: ../value/enum.Constant.html
+    /// [`get_global`]: #method.get_global
+    pub fn globals(&self) -> impl Iterator<Item = (&str, Constant)> + '_ {
+        self.globals.iter().filter_map(move |(name, value)| {
+            self.heap
+                .try_value_into_constant(*value)
+                .map(|value| (name.as_str(), value))
+        })
+    }
+
+    /// Set what happens when integer arithmetic overflows. The default is
+    /// [`Arithmetic::Checked`], or `Arithmetic::BigInt` with the `bigint` feature.
+    ///
+    /// ```rust
+    /// # use piccolo::prelude::*;
+    /// let mut session = Session::new();
+    /// session.machine().set_arithmetic(Arithmetic::Checked);
+    /// assert!(session.eval("retn 9223372036854775807 + 1").is_err());
+    ///
+    /// session.machine().set_arithmetic(Arithmetic::Wrapping);
+    /// assert_eq!(
+    ///     session.eval("retn 9223372036854775807 + 1").unwrap(),
+    ///     Constant::Integer(i64::MIN)
+    /// );
+    /// ```
+    ///
+    /// [`Arithmetic::Checked`]: enum.Arithmetic.html#variant.Checked
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    /// The current integer overflow policy. See [`set_arithmetic`].
+    ///
+    /// [`set_arithmetic`]: #method.set_arithmetic
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    /// Limit the number of instructions the machine may run, or remove the limit
+    /// with `None`. Running out is a [`RuntimeError::OutOfFuel`].
+    ///
+    /// The fuel is shared by every script the machine runs until it is set again.
+    /// Like the other limits, it's only checked at backward jumps and calls, so a
+    /// script may run a few instructions past it before stopping.
+    ///
+    /// ```rust
+    /// # use piccolo::prelude::*;
+    /// let mut session = Session::new();
+    /// session.machine().set_fuel(Some(1000));
+    /// assert!(session.eval("i =: 0\nwhile true do i += 1 end").is_err());
+    /// assert_eq!(session.machine().fuel(), Some(0));
+    ///
+    /// // the globals are left as they were when the script stopped
+    /// let i: i64 = session.machine().get_global("i").unwrap().into();
+    /// assert!(i > 0);
+    /// ```
+    ///
+    /// [`RuntimeError::OutOfFuel`]: ../../error/enum.RuntimeError.html#variant.OutOfFuel
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// The number of instructions the machine may still run, if limited. See
+    /// [`set_fuel`].
+    ///
+    /// [`set_fuel`]: #method.set_fuel
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Stop running scripts once `deadline` has passed, or never with `None`.
+    /// Passing it is a [`RuntimeError::DeadlineExceeded`].
+    ///
+    /// [`RuntimeError::DeadlineExceeded`]: ../../error/enum.RuntimeError.html#variant.DeadlineExceeded
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// The limits on the machine's memory use and what scripts may do.
+    pub fn config(&self) -> &MachineConfig {
+        &self.config
+    }
+
+    /// Get a [`CancelHandle`] that can stop the machine from another thread.
+    ///
+    /// [`CancelHandle`]: struct.CancelHandle.html
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    pub(crate) fn global_names(&self) -> impl Iterator<Item = &str> {
+        self.globals.keys().map(String::as_str)
     }
 
     /// Get the [`Heap`] of a VM.
@@ -48,25 +514,97 @@ impl Machine {
         &mut self.heap
     }
 
+    // integer arithmetic for `op`, following the overflow policy. `rhs` is ignored
+    // when negating
+    fn integer_op(&mut self, op: Opcode, lhs: i64, rhs: i64) -> Result<Value, RuntimeError> {
+        let checked = match op {
+            Opcode::Add => lhs.checked_add(rhs),
+            Opcode::Subtract => lhs.checked_sub(rhs),
+            Opcode::Multiply => lhs.checked_mul(rhs),
+            Opcode::Divide | Opcode::Modulo if rhs == 0 => {
+                return Err(RuntimeError::DivisionByZero { op });
+            }
+            Opcode::Divide => lhs.checked_div(rhs),
+            // only i64::MIN % -1 overflows, and its remainder is still 0
+            Opcode::Modulo => Some(lhs.wrapping_rem(rhs)),
+            Opcode::Negate => lhs.checked_neg(),
+            Opcode::BitAnd => Some(lhs & rhs),
+            Opcode::BitOr => Some(lhs | rhs),
+            Opcode::BitXor => Some(lhs ^ rhs),
+            Opcode::ShiftLeft | Opcode::ShiftRight => {
+                let amount =
+                    u32::try_from(rhs).map_err(|_| RuntimeError::InvalidShift { amount: rhs })?;
+                if amount >= i64::BITS {
+                    #[cfg(feature = "bigint")]
+                    if self.arithmetic == Arithmetic::BigInt {
+                        return super::bigint::integer(&mut self.heap, op, lhs.into(), rhs.into());
+                    }
+                    return Err(RuntimeError::InvalidShift { amount: rhs });
+                }
+                if op == Opcode::ShiftLeft {
+                    // bits shifted out the top can't be shifted back in
+                    Some(lhs << amount).filter(|value| value >> amount == lhs)
+                } else {
+                    Some(lhs >> amount)
+                }
+            }
+            _ => unreachable!("integer_op with {:?}", op),
+        };
+
+        if let Some(value) = checked {
+            return Ok(Value::Integer(value));
+        }
+
+        match self.arithmetic {
+            Arithmetic::Checked => Err(RuntimeError::IntegerOverflow { op }),
+            Arithmetic::Wrapping => Ok(Value::Integer(match op {
+                Opcode::Add => lhs.wrapping_add(rhs),
+                Opcode::Subtract => lhs.wrapping_sub(rhs),
+                Opcode::Multiply => lhs.wrapping_mul(rhs),
+                Opcode::Divide => lhs.wrapping_div(rhs),
+                Opcode::Negate => lhs.wrapping_neg(),
+                Opcode::ShiftLeft => lhs << rhs,
+                _ => unreachable!("{:?} overflowed", op),
+            })),
+            Arithmetic::Promote => {
+                let (lhs, rhs) = (lhs as f64, rhs as f64);
+                Ok(Value::Double(match op {
+                    Opcode::Add => lhs + rhs,
+                    Opcode::Subtract => lhs - rhs,
+                    Opcode::Multiply => lhs * rhs,
+                    Opcode::Divide => lhs / rhs,
+                    Opcode::Negate => -lhs,
+                    Opcode::ShiftLeft => lhs * 2f64.powf(rhs),
+                    _ => unreachable!("{:?} overflowed", op),
+                }))
+            }
+            #[cfg(feature = "bigint")]
+            Arithmetic::BigInt => {
+                super::bigint::integer(&mut self.heap, op, lhs.into(), rhs.into())
+            }
+        }
+    }
+
     // TODO: determine if self.ip - 1 is necessary
     // this method is only ever called after self.ip is incremented
     // theoretically a program should never start with Opcode::Pop
     fn pop(&mut self, chunk: &Chunk) -> Result<Value, PiccoloError> {
         self.stack.pop().ok_or_else(|| {
-            PiccoloError::new(ErrorKind::StackUnderflow {
+            PiccoloError::new(RuntimeError::StackUnderflow {
                 op: chunk.data[self.ip - 1].into(),
             })
-            .line(chunk.get_line_from_index(self.ip))
+            .line(chunk.get_line_from_index(self.inst))
             .msg("file a bug report!")
         })
     }
 
     fn peek_back(&self, dist: usize, chunk: &Chunk) -> Result<&Value, PiccoloError> {
-        self.stack.get(self.stack.len() - dist - 1).ok_or_else(|| {
-            PiccoloError::new(ErrorKind::StackUnderflow {
+        let idx = self.stack.len().checked_sub(dist + 1);
+        idx.and_then(|idx| self.stack.get(idx)).ok_or_else(|| {
+            PiccoloError::new(RuntimeError::StackUnderflow {
                 op: chunk.data[self.ip - 1].into(),
             })
-            .line(chunk.get_line_from_index(self.ip))
+            .line(chunk.get_line_from_index(self.inst))
             .msg_string(format!("peek_back({})", dist))
         })
     }
@@ -89,6 +627,349 @@ impl Machine {
         short
     }
 
+    fn check_writable(&self, name: &str, chunk: &Chunk) -> Result<(), PiccoloError> {
+        if self.config.policy.is_read_only(name) {
+            return Err(PiccoloError::new(RuntimeError::ReadOnlyGlobal {
+                name: String::from(name),
+            })
+            .line(chunk.get_line_from_index(self.inst)));
+        }
+        Ok(())
+    }
+
+    // an infinite loop has to jump backward or call something, so those are the only
+    // places the limits are checked
+    fn check_limits(&self, chunk: &Chunk) -> Result<(), PiccoloError> {
+        let error = if self.fuel == Some(0) {
+            RuntimeError::OutOfFuel
+        } else if self.cancel.cancelled.swap(false, Ordering::Relaxed) {
+            RuntimeError::Cancelled
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            RuntimeError::DeadlineExceeded
+        } else {
+            return Ok(());
+        };
+
+        Err(PiccoloError::new(error).line(chunk.get_line_from_index(self.inst)))
+    }
+
+    // call the value below the arguments on the stack. returns true if
+    // a new frame was pushed, and false if the call has already completed.
+    fn call(&mut self, arity: usize, chunk: &Chunk) -> Result<bool, PiccoloError> {
+        self.check_limits(chunk)?;
+        let mut callee = *self.peek_back(arity, chunk)?;
+        let base = self.stack.len() - arity - 1;
+        let line = chunk.get_line_from_index(self.inst);
+        let incorrect_arity = |name: &str, exp: usize| {
+            PiccoloError::new(RuntimeError::IncorrectArity {
+                name: name.to_owned(),
+                exp,
+                got: arity,
+            })
+            .line(line)
+        };
+
+        let mut bound = false;
+        if callee.is_object() {
+            if let Some(method) = self.heap.deref(callee).downcast_ref::<Method>() {
+                // the instance takes the place of the method in the first slot of the frame
+                callee = method.method();
+                self.stack[base] = method.receiver();
+                bound = true;
+            }
+        }
+
+        if callee.is_object() {
+            if let Some(data) = self.heap.deref(callee).downcast_ref::<DataType>() {
+                if arity != 0 {
+                    return Err(incorrect_arity(data.name(), 0)
+                        .msg("use named fields to construct an instance"));
+                }
+
+                let instance = Instance::new(callee, data);
+                let instance = self.heap.alloc(Box::new(instance));
+                self.stack.truncate(base);
+                self.stack.push(instance);
+                return Ok(false);
+            }
+
+            let object = self.heap.deref(callee);
+            let function = object
+                .downcast_ref::<Closure>()
+                .map(Closure::function)
+                .or_else(|| object.downcast_ref::<Function>());
+            if let Some(function) = function {
+                if function.arity() != arity {
+                    return Err(incorrect_arity(function.name(), function.arity()));
+                }
+
+                if let Some(max) = self.config.max_frames {
+                    // the top-level script has a frame too
+                    if self.frames.len() > max {
+                        return Err(
+                            PiccoloError::new(RuntimeError::StackOverflow { depth: max })
+                                .line(line),
+                        );
+                    }
+                }
+
+                // the callee and its arguments become the bottom of the new frame
+                self.frames.push(Frame {
+                    chunk: Some(Rc::clone(function.chunk())),
+                    closure: callee,
+                    base,
+                    return_ip: self.ip,
+                });
+                self.ip = 0;
+                return Ok(true);
+            } else if let Some(native) = object.downcast_ref::<NativeFunction>() {
+                if native.arity() != arity {
+                    return Err(incorrect_arity(native.name(), native.arity()));
+                }
+
+                // builtin methods get the value they were called on as well
+                let function = native.function();
+                let args = if bound { base } else { base + 1 };
+                let result =
+                    function(&mut self.heap, &self.stack[args..]).map_err(|e| e.line(line))?;
+
+                // pop the arguments and the callee
+                self.stack.truncate(base);
+                self.stack.push(result);
+                return Ok(false);
+            }
+        }
+
+        Err(PiccoloError::new(RuntimeError::IncorrectType {
+            exp: "function".into(),
+            got: self.heap.type_name(&callee).to_owned(),
+            op: Opcode::Call,
+        })
+        .line(line))
+    }
+
+    // get the upvalue object for a captured local slot, creating it if
+    // no closure has captured the slot yet
+    fn capture_upvalue(&mut self, slot: usize) -> Value {
+        for upvalue in self.open_upvalues.iter() {
+            if let Some(Upvalue::Open(open)) = self.heap.deref(*upvalue).downcast_ref::<Upvalue>() {
+                if *open == slot {
+                    return *upvalue;
+                }
+            }
+        }
+
+        let upvalue = self.heap.alloc(Box::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // move the values of the locals at or above `slot` into their upvalues
+    fn close_upvalues(&mut self, slot: usize) {
+        let stack = &self.stack;
+        let heap = &mut self.heap;
+        self.open_upvalues.retain(|upvalue| {
+            let upvalue = heap.deref_mut(*upvalue).downcast_mut::<Upvalue>().unwrap();
+            match *upvalue {
+                Upvalue::Open(open) if open >= slot => {
+                    *upvalue = Upvalue::Closed(stack[open]);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    // get an upvalue of the closure running in the current frame
+    fn get_upvalue(&self, closure: Value, idx: u16) -> Value {
+        let closure = self.heap.deref(closure).downcast_ref::<Closure>();
+        closure.unwrap().upvalues()[idx as usize]
+    }
+
+    // get a field of an instance, or bind one of its methods or a builtin method
+    fn get_property(&mut self, object: Value, name: &str) -> Option<Value> {
+        if let Some(method) = self
+            .methods
+            .get(self.heap.type_name(&object))
+            .and_then(|methods| methods.get(name))
+        {
+            let method = *method;
+            return Some(self.heap.alloc(Box::new(Method::new(object, method))));
+        }
+
+        if !object.is_object() {
+            return None;
+        }
+
+        if let Some(value) = self.heap.deref(object).get(name) {
+            return Some(value);
+        }
+
+        let data = self.heap.deref(object).downcast_ref::<Instance>()?.data();
+        let method = self
+            .heap
+            .deref(data)
+            .downcast_ref::<DataType>()?
+            .method(name)?;
+        Some(self.heap.alloc(Box::new(Method::new(object, method))))
+    }
+
+    // get an item of a list, the value of a key in a map, or part of a string
+    fn get_index(&mut self, object: Value, idx: Value, op: Opcode) -> Result<Value, PiccoloError> {
+        if object.is_string() {
+            if self.range(idx).is_none() {
+                return Err(self.incorrect_type("range", &idx, op));
+            }
+            return string_slice(&mut self.heap, &[object, idx]);
+        } else if object.is_object() {
+            let target = self.heap.deref(object);
+            if let Some(list) = target.downcast_ref::<List>() {
+                if let Some(range) = self.range(idx) {
+                    let values = list.slice(range)?;
+                    return Ok(self.heap.alloc(Box::new(List::new(values))));
+                }
+                let item = self.list_item(list, idx, op)?;
+                return Ok(list.values()[item]);
+            } else if let Some(map) = target.downcast_ref::<Map>() {
+                let key = MapKey::new(&self.heap, idx, op)?;
+                return map.get(key).ok_or_else(|| {
+                    PiccoloError::new(RuntimeError::KeyNotFound {
+                        key: self.heap.repr(&idx),
+                    })
+                });
+            }
+        }
+        Err(self.incorrect_type("list, map, or string", &object, op))
+    }
+
+    // set an item of a list, or the value of a key in a map
+    fn set_index(
+        &mut self,
+        object: Value,
+        idx: Value,
+        value: Value,
+        op: Opcode,
+    ) -> Result<(), PiccoloError> {
+        if object.is_object() {
+            let target = self.heap.deref(object);
+            if let Some(list) = target.downcast_ref::<List>() {
+                let item = self.list_item(list, idx, op)?;
+                let list = self.heap.deref_mut(object).downcast_mut::<List>().unwrap();
+                list.values_mut()[item] = value;
+                return Ok(());
+            } else if let Some(map) = target.downcast_ref::<Map>() {
+                let key = MapKey::new(&self.heap, idx, op)?;
+                if !map.contains(&key) {
+                    self.heap.add_items(1);
+                }
+                let map = self.heap.deref_mut(object).downcast_mut::<Map>().unwrap();
+                map.insert(key, idx, value);
+                return Ok(());
+            }
+        }
+        Err(self.incorrect_type("list or map", &object, op))
+    }
+
+    // find the item of a list that an index refers to
+    fn list_item(&self, list: &List, idx: Value, op: Opcode) -> Result<usize, PiccoloError> {
+        match idx {
+            Value::Integer(idx) => list.index(idx),
+            idx => Err(self.incorrect_type("integer or range", &idx, op)),
+        }
+    }
+
+    // take a step of a for loop over a string or an object that implements
+    // `Object::iterate`. `Iterate` turns the state into the position of the next
+    // value, or nil if there are no more, and `IterItem` gets the value at a position.
+    // returns `None` for values that have to iterate with their own methods.
+    fn iterate(&mut self, iterable: Value, state: Value, op: Opcode) -> Option<Value> {
+        // the state is only ever nil or a position that was returned here
+        let position = match state {
+            Value::Integer(position) => Some(position as usize),
+            _ => None,
+        };
+
+        if let Value::String(string) = iterable {
+            let string = self.heap.interner().lookup(string);
+            // positions in strings are byte offsets of characters
+            let char_at = |position: usize| string.get(position..)?.chars().next();
+            return Some(match (op, position) {
+                (Opcode::Iterate, None) if !string.is_empty() => Value::Integer(0),
+                (Opcode::Iterate, Some(position)) => {
+                    let next = position + char_at(position).map_or(0, char::len_utf8);
+                    if next < string.len() {
+                        Value::Integer(next as i64)
+                    } else {
+                        Value::Nil
+                    }
+                }
+                (Opcode::IterItem, Some(position)) => match char_at(position) {
+                    Some(c) => self.heap.alloc_string(c.encode_utf8(&mut [0; 4])),
+                    None => Value::Nil,
+                },
+                _ => Value::Nil,
+            });
+        }
+
+        if !iterable.is_object() {
+            return None;
+        }
+        let object = self.heap.deref(iterable);
+        object.iterate(0)?;
+        Some(match op {
+            Opcode::Iterate => {
+                let next = position.map_or(0, |position| position + 1);
+                match object.iterate(next) {
+                    Some(Some(_)) => Value::Integer(next as i64),
+                    _ => Value::Nil,
+                }
+            }
+            _ => position
+                .and_then(|position| object.iterate(position).flatten())
+                .unwrap_or(Value::Nil),
+        })
+    }
+
+    fn range(&self, value: Value) -> Option<&Range> {
+        if value.is_object() {
+            self.heap.deref(value).downcast_ref::<Range>()
+        } else {
+            None
+        }
+    }
+
+    fn incorrect_type(&self, exp: &str, got: &Value, op: Opcode) -> PiccoloError {
+        PiccoloError::new(RuntimeError::IncorrectType {
+            exp: exp.into(),
+            got: self.heap.type_name(got).to_owned(),
+            op,
+        })
+    }
+
+    // get the data type being declared on top of the stack
+    fn data_type(&mut self, chunk: &Chunk) -> Result<&mut DataType, PiccoloError> {
+        let data = *self.peek_back(0, chunk)?;
+        let line = chunk.get_line_from_index(self.inst);
+        let got = self.heap.type_name(&data).to_owned();
+        let op = chunk.data[self.ip - 3].into();
+        let data_type = if data.is_object() {
+            self.heap.deref_mut(data).downcast_mut::<DataType>()
+        } else {
+            None
+        };
+        data_type.ok_or_else(|| {
+            PiccoloError::new(RuntimeError::IncorrectType {
+                exp: "data".into(),
+                got,
+                op,
+            })
+            .line(line)
+        })
+    }
+
     /// Interprets the machine's bytecode, returning a Constant.
     pub fn start_at(
         &mut self,
@@ -96,59 +977,145 @@ impl Machine {
         start: ChunkOffset,
     ) -> Result<Constant, PiccoloError> {
         self.ip = start;
-        self.interpret(chunk)
+        self.inst = start;
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.frames.push(Frame {
+            chunk: None,
+            closure: Value::Nil,
+            base: 0,
+            return_ip: 0,
+        });
+        self.run(chunk).map_err(|e| {
+            let e = e.with_backtrace(self.backtrace(chunk));
+            // closures that escaped into globals outlive a failed run too
+            self.close_upvalues(0);
+            e
+        })
+    }
+
+    // the function and line of every active frame, innermost first
+    fn backtrace(&self, top: &Chunk) -> Vec<StackFrame> {
+        let mut offset = self.inst;
+        let mut backtrace = Vec::new();
+        for frame in self.frames.iter().rev() {
+            let chunk = frame.chunk.as_deref().unwrap_or(top);
+            let function = if frame.closure.is_object() {
+                let object = self.heap.deref(frame.closure);
+                object
+                    .downcast_ref::<Closure>()
+                    .map(Closure::function)
+                    .or_else(|| object.downcast_ref::<Function>())
+                    .map_or_else(|| String::from("<fn>"), |f| String::from(f.name()))
+            } else {
+                String::from("<script>")
+            };
+
+            backtrace.push(StackFrame {
+                function,
+                file: None,
+                line: chunk.get_line_from_index(offset),
+            });
+            // the frame below is running the call that made this one, which ends
+            // just before where it returns to
+            offset = frame.return_ip.saturating_sub(1);
+        }
+        backtrace
+    }
+
+    /// Interprets the machine's bytecode, returning a Constant.
+    pub fn interpret(&mut self, chunk: &Chunk) -> Result<Constant, PiccoloError> {
+        self.start_at(chunk, 0)
     }
 
     // TODO: probably even move out the heap from the machine
     #[allow(clippy::cognitive_complexity)]
-    pub fn interpret(&mut self, chunk: &Chunk) -> Result<Constant, PiccoloError> {
-        while self.ip < chunk.data.len() {
-            // debug/macros {{{
-            debug!(
-                " ┌─{}{}",
-                if self.ip + 1 == chunk.data.len() {
-                    "─vm─exit─ "
-                } else {
-                    " "
-                },
-                super::memory::dbg_list(&self.stack, &self.heap),
-            );
-            debug!(
-                " └─{} {}",
-                if self.ip + 1 == chunk.data.len() {
-                    "───────── "
-                } else {
-                    " "
-                },
-                chunk.disassemble_instruction(self.ip)
-            );
+    fn run(&mut self, top: &Chunk) -> Result<Constant, PiccoloError> {
+        // every time a function is called or returns, start executing the new frame
+        'frames: loop {
+            let frame_chunk = self.frames.last().unwrap().chunk.clone();
+            let chunk = frame_chunk.as_deref().unwrap_or(top);
+            let base = self.frames.last().unwrap().base;
+            let closure = self.frames.last().unwrap().closure;
 
-            macro_rules! bit_op {
-                ($opcode:path, $op:tt) => {
-                    let rhs = self.pop(chunk)?;
-                    let lhs = self.pop(chunk)?;
-                    if lhs.is_integer() && rhs.is_integer() {
-                        let rhs = rhs.into::<i64>();
-                        let lhs = lhs.into::<i64>();
-                        self.stack.push(Value::Integer(lhs $op rhs));
-                    } else {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "integer".into(),
-                            got: format!(
-                                "{} {} {}",
-                                self.heap.type_name(&lhs),
-                                stringify!($op),
-                                self.heap.type_name(&rhs)
-                            ),
-                            op: $opcode,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
+            while self.ip < chunk.data.len() {
+                self.inst = self.ip;
+
+                // every live value is reachable from the machine between instructions
+                if self.heap.should_gc() {
+                    self.gc();
+                    self.heap
+                        .check_limits()
+                        .map_err(|e| e.line(chunk.get_line_from_index(self.inst)))?;
+                }
+                if let Some(max) = self.config.max_stack {
+                    if self.stack.len() > max {
+                        return Err(
+                            PiccoloError::new(RuntimeError::StackOverflow { depth: max })
+                                .line(chunk.get_line_from_index(self.inst)),
+                        );
                     }
-                };
-            }
+                }
+
+                // debug/macros {{{
+                debug!(
+                    " ┌─{}{}",
+                    if self.ip + 1 == chunk.data.len() {
+                        "─vm─exit─ "
+                    } else {
+                        " "
+                    },
+                    super::memory::dbg_list(&self.stack, &self.heap),
+                );
+                debug!(
+                    " └─{} {}",
+                    if self.ip + 1 == chunk.data.len() {
+                        "───────── "
+                    } else {
+                        " "
+                    },
+                    chunk.disassemble_instruction(self.ip)
+                );
+
+                macro_rules! bit_op {
+                    ($opcode:path, $op:tt) => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        #[cfg(feature = "bigint")]
+                        if let Some(value) =
+                            super::bigint::binary(&mut self.heap, $opcode, lhs, rhs).map_err(
+                                |e| PiccoloError::new(e).line(chunk.get_line_from_index(self.inst)),
+                            )?
+                        {
+                            self.stack.push(value);
+                            continue;
+                        }
+                        if lhs.is_integer() && rhs.is_integer() {
+                            let rhs = rhs.into::<i64>();
+                            let lhs = lhs.into::<i64>();
+                            let value = self.integer_op($opcode, lhs, rhs).map_err(|e| {
+                                PiccoloError::new(e).line(chunk.get_line_from_index(self.inst))
+                            })?;
+                            self.stack.push(value);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "integer".into(),
+                                got: format!(
+                                    "{} {} {}",
+                                    self.heap.type_name(&lhs),
+                                    stringify!($op),
+                                    self.heap.type_name(&rhs)
+                                ),
+                                op: $opcode,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                    };
+                }
 
-            // boolean argument to enable/disable string concatenation
-            macro_rules! bin_op {
+                // boolean argument to enable/disable string concatenation
+                macro_rules! bin_op {
                 ($opcode:path, $op:tt, nostring) => {
                     bin_op!($opcode, $op, false)
                 };
@@ -158,6 +1125,13 @@ impl Machine {
                 ($opcode:path, $op:tt, $allow_string:tt) => {
                     let rhs = self.pop(chunk)?;
                     let lhs = self.pop(chunk)?;
+                    #[cfg(feature = "bigint")]
+                    if let Some(value) = super::bigint::binary(&mut self.heap, $opcode, lhs, rhs)
+                        .map_err(|e| PiccoloError::new(e).line(chunk.get_line_from_index(self.inst)))?
+                    {
+                        self.stack.push(value);
+                        continue;
+                    }
                     if lhs.is_double() {
                         let lhs = lhs.into::<f64>();
                         if rhs.is_double() {
@@ -167,324 +1141,602 @@ impl Machine {
                             let rhs = rhs.into::<i64>();
                             self.stack.push(Value::Double(lhs $op rhs as f64));
                         } else {
-                            return Err(PiccoloError::new(ErrorKind::IncorrectType {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
                                 exp: "integer or double".into(),
                                 got: format!("double {} {}", stringify!($op), self.heap.type_name(&rhs)),
                                 op: $opcode,
                             })
-                            .line(chunk.get_line_from_index(self.ip)));
+                            .line(chunk.get_line_from_index(self.inst)));
                         }
                     } else if lhs.is_integer() {
                         let lhs = lhs.into::<i64>();
                         if rhs.is_integer() {
                             let rhs = rhs.into::<i64>();
-                            self.stack.push(Value::Integer(lhs $op rhs));
+                            let value = self.integer_op($opcode, lhs, rhs)
+                                .map_err(|e| PiccoloError::new(e).line(chunk.get_line_from_index(self.inst)))?;
+                            self.stack.push(value);
                         } else if rhs.is_double() {
                             let rhs = rhs.into::<f64>();
                             self.stack.push(Value::Double(lhs as f64 $op rhs));
                         } else {
-                            return Err(PiccoloError::new(ErrorKind::IncorrectType {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
                                 exp: "integer or double".into(),
                                 got: format!("integer {} {}", stringify!($op), self.heap.type_name(&rhs)),
                                 op: $opcode,
                             })
-                            .line(chunk.get_line_from_index(self.ip)));
+                            .line(chunk.get_line_from_index(self.inst)));
                         }
                     } else if $allow_string && lhs.is_string() {
                         let value = format!("{}{}", self.heap.fmt(&lhs), self.heap.fmt(&rhs));
                         let ptr = self.heap.alloc_string(&value);
                         self.stack.push(ptr);
                     } else {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
+                        return Err(PiccoloError::new(RuntimeError::IncorrectType {
                             exp: "integer or double".into(),
                             got: format!("{} {} {}", self.heap.type_name(&lhs), stringify!($op), self.heap.type_name(&rhs)),
                             op: $opcode,
                         })
-                        .line(chunk.get_line_from_index(self.ip)));
+                        .line(chunk.get_line_from_index(self.inst)));
                     }
                 };
             }
-            // }}}
+                // }}}
 
-            let inst = chunk.data[self.ip];
-            self.ip += 1;
+                let inst = chunk.data[self.ip];
+                self.ip += 1;
+                if let Some(fuel) = &mut self.fuel {
+                    *fuel = fuel.saturating_sub(1);
+                }
 
-            let op = inst.into();
-            match op {
-                Opcode::Pop => {
-                    if self.ip == chunk.data.len() {
-                        trace!("last instruction pop");
-                        let value = self.pop(chunk)?;
-                        return Ok(self.heap.value_into_constant(value));
+                let op = inst.into();
+                match op {
+                    Opcode::Pop => {
+                        if self.ip == chunk.data.len() {
+                            trace!("last instruction pop");
+                            let value = self.pop(chunk)?;
+                            return Ok(self.heap.value_into_constant(value));
+                        }
+                        self.pop(chunk)?;
                     }
-                    self.pop(chunk)?;
-                }
-                Opcode::Return => {
-                    let v = self.pop(chunk)?;
-                    println!("{}", self.heap.fmt(&v));
-                }
-                Opcode::Constant => {
-                    let c = self.peek_constant(chunk);
-                    let v = self.heap.constant_into_value(c);
-                    self.stack.push(v);
-                }
-                Opcode::Nil => self.stack.push(Value::Nil),
-                Opcode::True => self.stack.push(Value::Bool(true)),
-                Opcode::False => self.stack.push(Value::Bool(false)),
-
-                Opcode::Negate => {
-                    let v = self.pop(chunk)?;
-                    if v.is_double() {
-                        let v = v.into::<f64>();
-                        self.stack.push(Value::Double(-v));
-                    } else if v.is_integer() {
-                        let v = v.into::<i64>();
-                        self.stack.push(Value::Integer(-v));
-                    } else {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "integer or double".into(),
-                            got: self.heap.type_name(&v).to_owned(),
-                            op: Opcode::Negate,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
+                    Opcode::Return => {
+                        let v = self.pop(chunk)?;
+                        if self.frames.len() > 1 {
+                            let frame = self.frames.pop().unwrap();
+                            self.close_upvalues(frame.base);
+                            self.stack.truncate(frame.base);
+                            self.stack.push(v);
+                            self.ip = frame.return_ip;
+                            continue 'frames;
+                        }
+                        // closures that escaped into globals outlive the stack
+                        self.close_upvalues(0);
+                        return Ok(self.heap.value_into_constant(v));
                     }
-                }
-                Opcode::Not => {
-                    let v = self.pop(chunk)?;
-                    if v.is_truthy() {
-                        self.stack.push(Value::Bool(false));
-                    } else {
-                        self.stack.push(Value::Bool(true));
+                    Opcode::Constant => {
+                        let c = self.peek_constant(chunk);
+                        let v = self.heap.constant_into_value(c);
+                        self.stack.push(v);
                     }
-                }
-                Opcode::Add => {
-                    bin_op!(Opcode::Add, +, string);
-                }
-                Opcode::Subtract => {
-                    bin_op!(Opcode::Subtract, -, nostring);
-                }
-                Opcode::Multiply => {
-                    bin_op!(Opcode::Multiply, *, nostring);
-                }
-                Opcode::Divide => {
-                    bin_op!(Opcode::Divide, /, nostring);
-                }
-                Opcode::Modulo => {
-                    bin_op!(Opcode::Modulo, %, nostring);
-                }
+                    Opcode::Nil => self.stack.push(Value::Nil),
+                    Opcode::True => self.stack.push(Value::Bool(true)),
+                    Opcode::False => self.stack.push(Value::Bool(false)),
 
-                // comparison {{{
-                Opcode::Equal => {
-                    let a = self.pop(chunk)?;
-                    let b = self.pop(chunk)?;
-                    self.stack
-                        .push(Value::Bool(self.heap.eq(&a, &b).map_or_else(
-                            || {
-                                Err(PiccoloError::new(ErrorKind::IncorrectType {
-                                    exp: self.heap.type_name(&a).to_owned(),
-                                    got: self.heap.type_name(&b).to_owned(),
-                                    op,
-                                })
-                                .line(chunk.get_line_from_index(self.ip)))
-                            },
-                            Ok,
-                        )?));
-                }
-                Opcode::Greater => {
-                    let rhs = self.pop(chunk)?;
-                    let lhs = self.pop(chunk)?;
-                    if rhs.is_bool() || lhs.is_bool() {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "that isn't bool".into(),
-                            got: "bool".into(),
-                            op,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
-                    }
-                    self.stack
-                        .push(Value::Bool(self.heap.gt(&lhs, &rhs).map_or_else(
-                            || {
-                                Err(PiccoloError::new(ErrorKind::IncorrectType {
-                                    exp: self.heap.type_name(&lhs).to_owned(),
-                                    got: self.heap.type_name(&rhs).to_owned(),
-                                    op,
-                                })
-                                .line(chunk.get_line_from_index(self.ip)))
-                            },
-                            Ok,
-                        )?));
-                }
-                Opcode::Less => {
-                    let rhs = self.pop(chunk)?;
-                    let lhs = self.pop(chunk)?;
-                    if rhs.is_bool() || lhs.is_bool() {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "that isn't bool".into(),
-                            got: "bool".into(),
-                            op,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
-                    }
-                    self.stack
-                        .push(Value::Bool(self.heap.lt(&lhs, &rhs).map_or_else(
-                            || {
-                                Err(PiccoloError::new(ErrorKind::IncorrectType {
-                                    exp: self.heap.type_name(&lhs).to_owned(),
-                                    got: self.heap.type_name(&rhs).to_owned(),
-                                    op,
-                                })
-                                .line(chunk.get_line_from_index(self.ip)))
-                            },
-                            Ok,
-                        )?));
-                }
-                Opcode::GreaterEqual => {
-                    let rhs = self.pop(chunk)?;
-                    let lhs = self.pop(chunk)?;
-                    if rhs.is_bool() || lhs.is_bool() {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "that isn't bool".into(),
-                            got: "bool".into(),
-                            op,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
-                    }
-                    self.stack
-                        .push(Value::Bool(!self.heap.lt(&lhs, &rhs).map_or_else(
-                            || {
-                                Err(PiccoloError::new(ErrorKind::IncorrectType {
-                                    exp: self.heap.type_name(&lhs).to_owned(),
-                                    got: self.heap.type_name(&rhs).to_owned(),
-                                    op,
-                                })
-                                .line(chunk.get_line_from_index(self.ip)))
-                            },
-                            Ok,
-                        )?));
-                }
-                Opcode::LessEqual => {
-                    let rhs = self.pop(chunk)?;
-                    let lhs = self.pop(chunk)?;
-                    if rhs.is_bool() || lhs.is_bool() {
-                        return Err(PiccoloError::new(ErrorKind::IncorrectType {
-                            exp: "type that isn't bool".into(),
-                            got: "bool".into(),
-                            op,
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
-                    }
-                    self.stack
-                        .push(Value::Bool(!self.heap.gt(&lhs, &rhs).map_or_else(
-                            || {
-                                Err(PiccoloError::new(ErrorKind::IncorrectType {
-                                    exp: self.heap.type_name(&lhs).to_owned(),
-                                    got: self.heap.type_name(&rhs).to_owned(),
-                                    op,
+                    Opcode::Negate => {
+                        let v = self.pop(chunk)?;
+                        #[cfg(feature = "bigint")]
+                        if let Some(value) =
+                            super::bigint::binary(&mut self.heap, op, v, Value::Integer(0))
+                                .map_err(|e| {
+                                    PiccoloError::new(e).line(chunk.get_line_from_index(self.inst))
+                                })?
+                        {
+                            self.stack.push(value);
+                            continue;
+                        }
+                        if v.is_double() {
+                            let v = v.into::<f64>();
+                            self.stack.push(Value::Double(-v));
+                        } else if v.is_integer() {
+                            let v = v.into::<i64>();
+                            let value = self.integer_op(Opcode::Negate, v, 0).map_err(|e| {
+                                PiccoloError::new(e).line(chunk.get_line_from_index(self.inst))
+                            })?;
+                            self.stack.push(value);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "integer or double".into(),
+                                got: self.heap.type_name(&v).to_owned(),
+                                op: Opcode::Negate,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                    }
+                    Opcode::Not => {
+                        let v = self.pop(chunk)?;
+                        if v.is_truthy() {
+                            self.stack.push(Value::Bool(false));
+                        } else {
+                            self.stack.push(Value::Bool(true));
+                        }
+                    }
+                    Opcode::Add => {
+                        bin_op!(Opcode::Add, +, string);
+                    }
+                    Opcode::Subtract => {
+                        bin_op!(Opcode::Subtract, -, nostring);
+                    }
+                    Opcode::Multiply => {
+                        bin_op!(Opcode::Multiply, *, nostring);
+                    }
+                    Opcode::Divide => {
+                        bin_op!(Opcode::Divide, /, nostring);
+                    }
+                    Opcode::Modulo => {
+                        bin_op!(Opcode::Modulo, %, nostring);
+                    }
+
+                    // comparison {{{
+                    Opcode::Equal => {
+                        let a = self.pop(chunk)?;
+                        let b = self.pop(chunk)?;
+                        self.stack
+                            .push(Value::Bool(self.heap.eq(&a, &b).map_or_else(
+                                || {
+                                    Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                        exp: self.heap.type_name(&a).to_owned(),
+                                        got: self.heap.type_name(&b).to_owned(),
+                                        op,
+                                    })
+                                    .line(chunk.get_line_from_index(self.inst)))
+                                },
+                                Ok,
+                            )?));
+                    }
+                    Opcode::Greater => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        if rhs.is_bool() || lhs.is_bool() {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "that isn't bool".into(),
+                                got: "bool".into(),
+                                op,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                        self.stack
+                            .push(Value::Bool(self.heap.gt(&lhs, &rhs).map_or_else(
+                                || {
+                                    Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                        exp: self.heap.type_name(&lhs).to_owned(),
+                                        got: self.heap.type_name(&rhs).to_owned(),
+                                        op,
+                                    })
+                                    .line(chunk.get_line_from_index(self.inst)))
+                                },
+                                Ok,
+                            )?));
+                    }
+                    Opcode::Less => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        if rhs.is_bool() || lhs.is_bool() {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "that isn't bool".into(),
+                                got: "bool".into(),
+                                op,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                        self.stack
+                            .push(Value::Bool(self.heap.lt(&lhs, &rhs).map_or_else(
+                                || {
+                                    Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                        exp: self.heap.type_name(&lhs).to_owned(),
+                                        got: self.heap.type_name(&rhs).to_owned(),
+                                        op,
+                                    })
+                                    .line(chunk.get_line_from_index(self.inst)))
+                                },
+                                Ok,
+                            )?));
+                    }
+                    Opcode::GreaterEqual => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        if rhs.is_bool() || lhs.is_bool() {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "that isn't bool".into(),
+                                got: "bool".into(),
+                                op,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                        self.stack
+                            .push(Value::Bool(!self.heap.lt(&lhs, &rhs).map_or_else(
+                                || {
+                                    Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                        exp: self.heap.type_name(&lhs).to_owned(),
+                                        got: self.heap.type_name(&rhs).to_owned(),
+                                        op,
+                                    })
+                                    .line(chunk.get_line_from_index(self.inst)))
+                                },
+                                Ok,
+                            )?));
+                    }
+                    Opcode::LessEqual => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        if rhs.is_bool() || lhs.is_bool() {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "type that isn't bool".into(),
+                                got: "bool".into(),
+                                op,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                        self.stack
+                            .push(Value::Bool(!self.heap.gt(&lhs, &rhs).map_or_else(
+                                || {
+                                    Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                        exp: self.heap.type_name(&lhs).to_owned(),
+                                        got: self.heap.type_name(&rhs).to_owned(),
+                                        op,
+                                    })
+                                    .line(chunk.get_line_from_index(self.inst)))
+                                },
+                                Ok,
+                            )?));
+                    } // }}}
+
+                    Opcode::GetLocal => {
+                        let slot = self.read_short(chunk);
+                        self.stack.push(self.stack[base + slot as usize]);
+                    }
+                    Opcode::SetLocal => {
+                        let slot = self.read_short(chunk);
+                        self.stack[base + slot as usize] = self.pop(chunk)?;
+                    }
+                    Opcode::GetGlobal => {
+                        let name = self.peek_constant(chunk).ref_string();
+                        if let Some(var) = self.globals.get(name) {
+                            self.stack.push(*var);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::UndefinedVariable {
+                                name: name.to_owned(),
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                    }
+                    Opcode::SetGlobal => {
+                        if let Constant::String(name) = self.peek_constant(chunk) {
+                            let name = name.clone();
+                            self.check_writable(&name, chunk)?;
+                            let value = self.pop(chunk)?;
+                            if self.globals.insert(name.clone(), value).is_none() {
+                                return Err(PiccoloError::new(RuntimeError::UndefinedVariable {
+                                    name,
                                 })
-                                .line(chunk.get_line_from_index(self.ip)))
-                            },
-                            Ok,
-                        )?));
-                } // }}}
-
-                Opcode::GetLocal => {
-                    let slot = self.read_short(chunk);
-                    self.stack.push(self.stack[slot as usize]);
-                }
-                Opcode::SetLocal => {
-                    let slot = self.read_short(chunk);
-                    self.stack[slot as usize] = self.pop(chunk)?;
-                }
-                Opcode::GetGlobal => {
-                    let name = self.peek_constant(chunk).ref_string();
-                    if let Some(var) = self.globals.get(name) {
-                        self.stack.push(*var);
-                    } else {
-                        return Err(PiccoloError::new(ErrorKind::UndefinedVariable {
-                            name: name.to_owned(),
-                        })
-                        .line(chunk.get_line_from_index(self.ip)));
+                                .line(chunk.get_line_from_index(self.inst)));
+                            }
+                        }
                     }
-                }
-                Opcode::SetGlobal => {
-                    if let Constant::String(name) = self.peek_constant(chunk) {
-                        let name = name.clone();
+                    Opcode::DeclareGlobal => {
+                        if let Constant::String(name) = self.peek_constant(chunk) {
+                            let name = name.clone();
+                            self.check_writable(&name, chunk)?;
+                            let value = self.pop(chunk)?;
+                            self.globals.insert(name, value);
+                        } else {
+                            panic!("defined global with non-string name");
+                        }
+                    }
+
+                    Opcode::JumpForward => {
+                        let offset = self.read_short(chunk);
+                        debug!("jump ip {:x} -> {:x}", self.ip, self.ip + offset as usize);
+                        self.ip += offset as usize;
+                    }
+                    Opcode::JumpFalse => {
+                        let offset = self.read_short(chunk);
+                        if !self.peek_back(0, chunk)?.is_truthy() {
+                            debug!(
+                                "jump false ip {:x} -> {:x}",
+                                self.ip,
+                                self.ip + offset as usize
+                            );
+                            self.ip += offset as usize;
+                        }
+                    }
+                    Opcode::JumpTrue => {
+                        let offset = self.read_short(chunk);
+                        if self.peek_back(0, chunk)?.is_truthy() {
+                            debug!(
+                                "jump true ip {:x} -> {:x}",
+                                self.ip,
+                                self.ip + offset as usize
+                            );
+                            self.ip += offset as usize;
+                        }
+                    }
+                    Opcode::JumpBack => {
+                        let offset = self.read_short(chunk);
+                        debug!("loop ip {:x} -> {:x}", self.ip, self.ip - offset as usize);
+                        self.ip -= offset as usize;
+                        // a loop that runs out stops where it would have started again
+                        self.inst = self.ip;
+                        self.check_limits(chunk)?;
+                    }
+
+                    Opcode::BitAnd => {
+                        bit_op!(Opcode::BitAnd, &);
+                    }
+                    Opcode::BitOr => {
+                        bit_op!(Opcode::BitOr, |);
+                    }
+                    Opcode::BitXor => {
+                        bit_op!(Opcode::BitXor, ^);
+                    }
+                    Opcode::ShiftLeft => {
+                        bit_op!(Opcode::ShiftLeft, <<);
+                    }
+                    Opcode::ShiftRight => {
+                        bit_op!(Opcode::ShiftRight, >>);
+                    }
+
+                    Opcode::Call => {
+                        let arity = self.read_short(chunk) as usize;
+                        if self.call(arity, chunk)? {
+                            continue 'frames;
+                        }
+                    }
+
+                    Opcode::Closure => {
+                        let function = match self.peek_constant(chunk) {
+                            Constant::Function(function) => function.clone(),
+                            _ => panic!("closure from non-function constant"),
+                        };
+
+                        let upvalues = function
+                            .captures()
+                            .iter()
+                            .map(|capture| {
+                                if capture.is_local {
+                                    self.capture_upvalue(base + capture.index as usize)
+                                } else {
+                                    self.get_upvalue(closure, capture.index)
+                                }
+                            })
+                            .collect();
+
+                        let closure = self.heap.alloc(Box::new(Closure::new(function, upvalues)));
+                        self.stack.push(closure);
+                    }
+                    Opcode::GetUpvalue => {
+                        let idx = self.read_short(chunk);
+                        let upvalue = self.get_upvalue(closure, idx);
+                        let value = match self.heap.deref(upvalue).downcast_ref::<Upvalue>() {
+                            Some(Upvalue::Open(slot)) => self.stack[*slot],
+                            Some(Upvalue::Closed(value)) => *value,
+                            None => panic!("closure captured non-upvalue"),
+                        };
+                        self.stack.push(value);
+                    }
+                    Opcode::SetUpvalue => {
+                        let idx = self.read_short(chunk);
                         let value = self.pop(chunk)?;
-                        if self.globals.insert(name.clone(), value).is_none() {
-                            return Err(PiccoloError::new(ErrorKind::UndefinedVariable { name })
-                                .line(chunk.get_line_from_index(self.ip)));
+                        let upvalue = self.get_upvalue(closure, idx);
+                        match self.heap.deref_mut(upvalue).downcast_mut::<Upvalue>() {
+                            Some(Upvalue::Open(slot)) => self.stack[*slot] = value,
+                            Some(upvalue) => *upvalue = Upvalue::Closed(value),
+                            None => panic!("closure captured non-upvalue"),
                         }
                     }
-                }
-                Opcode::DeclareGlobal => {
-                    if let Constant::String(name) = self.peek_constant(chunk) {
-                        let name = name.clone();
+                    Opcode::CloseUpvalue => {
+                        self.close_upvalues(self.stack.len() - 1);
+                        self.pop(chunk)?;
+                    }
+
+                    Opcode::Data => {
+                        let name = self.peek_constant(chunk).ref_string().to_owned();
+                        let data = self.heap.alloc(Box::new(DataType::new(name)));
+                        self.stack.push(data);
+                    }
+                    Opcode::Field => {
+                        let name = self.peek_constant(chunk).ref_string().to_owned();
                         let value = self.pop(chunk)?;
-                        self.globals.insert(name, value);
-                    } else {
-                        panic!("defined global with non-string name");
+                        self.data_type(chunk)?.add_field(name, value);
                     }
-                }
+                    Opcode::Method => {
+                        let name = self.peek_constant(chunk).ref_string().to_owned();
+                        let method = self.pop(chunk)?;
+                        // the compiler only makes methods from functions, but bytecode
+                        // from elsewhere could try anything
+                        let function = method.is_object() && {
+                            let object = self.heap.deref(method);
+                            object.downcast_ref::<Closure>().is_some()
+                                || object.downcast_ref::<Function>().is_some()
+                        };
+                        if !function {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "function".into(),
+                                got: self.heap.type_name(&method).to_owned(),
+                                op: Opcode::Method,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                        self.data_type(chunk)?.add_method(name, method);
+                    }
+                    Opcode::New => {
+                        let fields = self.read_short(chunk) as usize;
+                        let line = chunk.get_line_from_index(self.inst);
+                        let data = *self.peek_back(fields * 2, chunk)?;
 
-                Opcode::JumpForward => {
-                    let offset = self.read_short(chunk);
-                    debug!("jump ip {:x} -> {:x}", self.ip, self.ip + offset as usize);
-                    self.ip += offset as usize;
-                }
-                Opcode::JumpFalse => {
-                    let offset = self.read_short(chunk);
-                    if !self.peek_back(0, chunk)?.is_truthy() {
-                        debug!(
-                            "jump false ip {:x} -> {:x}",
-                            self.ip,
-                            self.ip + offset as usize
-                        );
-                        self.ip += offset as usize;
+                        let data_type = if data.is_object() {
+                            self.heap.deref(data).downcast_ref::<DataType>()
+                        } else {
+                            None
+                        };
+                        let mut instance = match data_type {
+                            Some(data_type) => Instance::new(data, data_type),
+                            None => {
+                                return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                    exp: "data".into(),
+                                    got: self.heap.type_name(&data).to_owned(),
+                                    op: Opcode::New,
+                                })
+                                .line(line));
+                            }
+                        };
+
+                        let start = self.stack.len() - fields * 2;
+                        for field in self.stack[start..].chunks(2) {
+                            let name = self.heap.fmt(&field[0]);
+                            if instance.set(&name, field[1]).is_none() {
+                                return Err(PiccoloError::new(RuntimeError::UnknownField {
+                                    obj: self.heap.fmt(&data),
+                                    name,
+                                })
+                                .line(line));
+                            }
+                        }
+
+                        self.stack.truncate(start - 1);
+                        let instance = self.heap.alloc(Box::new(instance));
+                        self.stack.push(instance);
                     }
-                }
-                Opcode::JumpTrue => {
-                    let offset = self.read_short(chunk);
-                    if self.peek_back(0, chunk)?.is_truthy() {
-                        debug!(
-                            "jump true ip {:x} -> {:x}",
-                            self.ip,
-                            self.ip + offset as usize
-                        );
-                        self.ip += offset as usize;
+                    Opcode::GetProperty => {
+                        let name = self.peek_constant(chunk).ref_string();
+                        let object = self.pop(chunk)?;
+                        if let Some(value) = self.get_property(object, name) {
+                            self.stack.push(value);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::UnknownField {
+                                obj: self.heap.fmt(&object),
+                                name: name.to_owned(),
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                    }
+                    Opcode::SetProperty => {
+                        let name = self.peek_constant(chunk).ref_string();
+                        let value = self.pop(chunk)?;
+                        let object = self.pop(chunk)?;
+                        if object.is_object()
+                            && self.heap.deref_mut(object).set(name, value).is_some()
+                        {
+                            self.stack.push(value);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::UnknownField {
+                                obj: self.heap.fmt(&object),
+                                name: name.to_owned(),
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
                     }
-                }
-                Opcode::JumpBack => {
-                    let offset = self.read_short(chunk);
-                    debug!("loop ip {:x} -> {:x}", self.ip, self.ip - offset as usize);
-                    self.ip -= offset as usize;
-                }
 
-                Opcode::BitAnd => {
-                    bit_op!(Opcode::BitAnd, &);
-                }
-                Opcode::BitOr => {
-                    bit_op!(Opcode::BitOr, |);
-                }
-                Opcode::BitXor => {
-                    bit_op!(Opcode::BitXor, ^);
-                }
-                Opcode::ShiftLeft => {
-                    bit_op!(Opcode::ShiftLeft, <<);
-                }
-                Opcode::ShiftRight => {
-                    bit_op!(Opcode::ShiftRight, >>);
-                }
+                    Opcode::List => {
+                        let len = self.read_short(chunk) as usize;
+                        if len > 0 {
+                            self.peek_back(len - 1, chunk)?;
+                        }
+                        let values = self.stack.split_off(self.stack.len() - len);
+                        let list = self.heap.alloc(Box::new(List::new(values)));
+                        self.stack.push(list);
+                    }
+                    Opcode::GetIndex => {
+                        let idx = self.pop(chunk)?;
+                        let object = self.pop(chunk)?;
+                        let value = self
+                            .get_index(object, idx, Opcode::GetIndex)
+                            .map_err(|e| e.line(chunk.get_line_from_index(self.inst)))?;
+                        self.stack.push(value);
+                    }
+                    Opcode::SetIndex => {
+                        let value = self.pop(chunk)?;
+                        let idx = self.pop(chunk)?;
+                        let object = self.pop(chunk)?;
+                        self.set_index(object, idx, value, Opcode::SetIndex)
+                            .map_err(|e| e.line(chunk.get_line_from_index(self.inst)))?;
+                        self.stack.push(value);
+                    }
+                    Opcode::ExclusiveRange | Opcode::InclusiveRange => {
+                        let rhs = self.pop(chunk)?;
+                        let lhs = self.pop(chunk)?;
+                        if let (Value::Integer(start), Value::Integer(end)) = (lhs, rhs) {
+                            let inclusive = op == Opcode::InclusiveRange;
+                            let range = Range::new(start, end, inclusive);
+                            let range = self.heap.alloc(Box::new(range));
+                            self.stack.push(range);
+                        } else {
+                            return Err(PiccoloError::new(RuntimeError::IncorrectType {
+                                exp: "integer".into(),
+                                got: format!(
+                                    "{} and {}",
+                                    self.heap.type_name(&lhs),
+                                    self.heap.type_name(&rhs)
+                                ),
+                                op,
+                            })
+                            .line(chunk.get_line_from_index(self.inst)));
+                        }
+                    }
+                    Opcode::Iterate | Opcode::IterItem => {
+                        let state = self.pop(chunk)?;
+                        let iterable = self.pop(chunk)?;
+                        if let Some(value) = self.iterate(iterable, state, op) {
+                            self.stack.push(value);
+                        } else {
+                            // instances iterate by calling their own methods with the state
+                            let name = if op == Opcode::Iterate {
+                                "iterate"
+                            } else {
+                                "item"
+                            };
+                            let method = self.get_property(iterable, name).ok_or_else(|| {
+                                PiccoloError::new(RuntimeError::IncorrectType {
+                                    exp: "iterable".into(),
+                                    got: self.heap.type_name(&iterable).to_owned(),
+                                    op,
+                                })
+                                .line(chunk.get_line_from_index(self.inst))
+                            })?;
+                            self.stack.push(method);
+                            self.stack.push(state);
+                            if self.call(1, chunk)? {
+                                continue 'frames;
+                            }
+                        }
+                    }
+                    Opcode::Map => {
+                        let len = self.read_short(chunk) as usize;
+                        if len > 0 {
+                            self.peek_back(len * 2 - 1, chunk)?;
+                        }
+                        let entries = self.stack.split_off(self.stack.len() - len * 2);
+                        let mut map = Map::new();
+                        for pair in entries.chunks(2) {
+                            let key = MapKey::new(&self.heap, pair[0], Opcode::Map)
+                                .map_err(|e| e.line(chunk.get_line_from_index(self.inst)))?;
+                            map.insert(key, pair[0], pair[1]);
+                        }
+                        let map = self.heap.alloc(Box::new(map));
+                        self.stack.push(map);
+                    }
 
-                Opcode::Assert => {
-                    let v = self.pop(chunk)?;
-                    if !v.is_truthy() {
-                        return Err(PiccoloError::new(ErrorKind::AssertFailed)
-                            .line(chunk.get_line_from_index(self.ip - 1)));
+                    Opcode::Assert => {
+                        let v = self.pop(chunk)?;
+                        if !v.is_truthy() {
+                            return Err(PiccoloError::new(RuntimeError::AssertFailed)
+                                .line(chunk.get_line_from_index(self.inst)));
+                        }
                     }
                 }
+
+                trace!("next instruction");
             }
 
-            trace!("next instruction");
+            break;
         }
 
         Ok(self
@@ -492,3 +1744,270 @@ impl Machine {
             .value_into_constant(self.stack.pop().unwrap_or(Value::Nil)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{Constant, Session};
+
+    #[test]
+    fn arithmetic() {
+        use crate::{Arithmetic, ErrorKind, PiccoloError, RuntimeError};
+
+        let code = |e: Vec<PiccoloError>| e[0].kind().code();
+        let mut session = Session::new();
+        session.machine().set_arithmetic(Arithmetic::Checked);
+        session.eval("max =: 9223372036854775807").unwrap();
+        assert_eq!(code(session.eval("retn max + 1").unwrap_err()), "E0412");
+        assert_eq!(code(session.eval("retn max / 0").unwrap_err()), "E0413");
+        assert_eq!(code(session.eval("retn max << 64").unwrap_err()), "E0414");
+        assert_eq!(code(session.eval("retn 3 << 62").unwrap_err()), "E0412");
+
+        session.machine().set_arithmetic(Arithmetic::Wrapping);
+        assert_eq!(
+            session.eval("retn max + 1").unwrap(),
+            Constant::Integer(i64::MIN)
+        );
+        assert_eq!(
+            session.eval("retn -(max + 1)").unwrap(),
+            Constant::Integer(i64::MIN)
+        );
+        assert_eq!(
+            session.eval("retn 3 << 62").unwrap(),
+            Constant::Integer(3 << 62)
+        );
+
+        session.machine().set_arithmetic(Arithmetic::Promote);
+        assert_eq!(
+            session.eval("retn max * 2").unwrap(),
+            Constant::Double(i64::MAX as f64 * 2.0)
+        );
+        assert_eq!(
+            session.eval("retn max - 1").unwrap(),
+            Constant::Integer(i64::MAX - 1)
+        );
+        assert_eq!(
+            session.eval("retn 3 << 62").unwrap(),
+            Constant::Double(3.0 * 2f64.powi(62))
+        );
+
+        // division by zero is an error no matter the policy
+        let error = session.eval("retn 1 % 0").unwrap_err();
+        assert!(matches!(
+            error[0].kind(),
+            ErrorKind::Runtime(RuntimeError::DivisionByZero { .. })
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        use crate::{ErrorKind, RuntimeError};
+        use std::time::{Duration, Instant};
+
+        let forever = "i =: 0\nwhile true do\n  i = i + 1\nend";
+        let error = |session: &mut Session| {
+            let errors = session.eval(forever).unwrap_err();
+            assert_eq!(errors[0].get_line(), Some(2));
+            match errors[0].kind() {
+                ErrorKind::Runtime(error) => error.clone(),
+                _ => panic!("{:?}", errors),
+            }
+        };
+
+        let mut session = Session::new();
+        session.machine().set_fuel(Some(100));
+        assert!(matches!(error(&mut session), RuntimeError::OutOfFuel));
+        assert_eq!(session.machine().fuel(), Some(0));
+        // the loop got partway before stopping
+        let i: i64 = session.machine().get_global("i").unwrap().into();
+        assert!(0 < i && i < 100);
+
+        let mut session = Session::new();
+        session.machine().set_deadline(Some(Instant::now()));
+        assert!(matches!(
+            error(&mut session),
+            RuntimeError::DeadlineExceeded
+        ));
+
+        let mut session = Session::new();
+        let handle = session.machine().cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.cancel();
+        });
+        assert!(matches!(error(&mut session), RuntimeError::Cancelled));
+        canceller.join().unwrap();
+
+        // cancelling only stops one script
+        assert!(!session.machine().cancel_handle().is_cancelled());
+        assert_eq!(session.eval("retn 1").unwrap(), Constant::Integer(1));
+    }
+
+    #[test]
+    fn memory_limits() {
+        use crate::{ErrorKind, Machine, MachineConfig, PiccoloError, RuntimeError};
+
+        let run = |config: &MachineConfig, src: &str| -> Vec<PiccoloError> {
+            Session::with_machine(Machine::with_config(config.clone()))
+                .eval(src)
+                .unwrap_err()
+        };
+        let out_of_memory = |errors: Vec<PiccoloError>| {
+            matches!(
+                errors[0].kind(),
+                ErrorKind::Runtime(RuntimeError::OutOfMemory { .. })
+            )
+        };
+        let stack_overflow = |errors: Vec<PiccoloError>| {
+            matches!(
+                errors[0].kind(),
+                ErrorKind::Runtime(RuntimeError::StackOverflow { .. })
+            )
+        };
+
+        let objects = MachineConfig {
+            max_objects: Some(1000),
+            ..MachineConfig::default()
+        };
+        assert!(out_of_memory(run(
+            &objects,
+            "l =: []\nwhile true do\n  l = [l]\nend"
+        )));
+        assert!(out_of_memory(run(
+            &objects,
+            "m =: {}\ni =: 0\nwhile true do\n  m[i] = i\n  i = i + 1\nend"
+        )));
+        let mut session = Session::with_machine(Machine::with_config(objects));
+        session
+            .eval("m =: {}\ni =: 0\nwhile i < 100000 do\n  m[0] = i\n  i = i + 1\nend")
+            .unwrap();
+
+        let strings = MachineConfig {
+            max_string_bytes: Some(1 << 16),
+            ..MachineConfig::default()
+        };
+        assert!(out_of_memory(run(
+            &strings,
+            "s =: \"a\"\nwhile true do\n  s = s + s\nend"
+        )));
+
+        let stack = MachineConfig {
+            max_stack: Some(1000),
+            ..MachineConfig::default()
+        };
+        let recurse = "fn f(n) do\n  retn f(n + 1)\nend\nf(0)";
+        assert!(stack_overflow(run(&stack, recurse)));
+
+        let frames = MachineConfig {
+            max_frames: Some(10),
+            ..MachineConfig::default()
+        };
+        let mut session = Session::with_machine(Machine::with_config(frames));
+        session
+            .eval("fn f(n) do\n  if n == 0 do\n    retn 0\n  end\n  retn f(n - 1)\nend")
+            .unwrap();
+        assert_eq!(session.eval("retn f(9)").unwrap(), Constant::Integer(0));
+        let errors = session.eval("retn f(10)").unwrap_err();
+        assert_eq!(errors[0].get_line(), Some(5));
+        assert!(stack_overflow(errors));
+        assert_eq!(session.eval("retn f(1)").unwrap(), Constant::Integer(0));
+    }
+
+    #[test]
+    fn policy() {
+        use crate::{ErrorKind, Machine, MachineConfig, PiccoloError, Policy, RuntimeError};
+
+        let policy = Policy::deny_all()
+            .allow_native("print")
+            .allow_methods("list")
+            .read_only("rules")
+            .read_only("undeclared");
+        assert!(policy.allows_native("print"));
+        assert!(!policy.allows_native("input"));
+        assert!(policy.allows_methods("list"));
+        assert!(!policy.allows_methods("string"));
+        assert!(policy.is_read_only("rules"));
+        assert!(!Policy::default().is_read_only("rules"));
+        assert!(Policy::default().allows_methods("string"));
+
+        let mut machine = Machine::with_config(MachineConfig {
+            policy,
+            ..MachineConfig::default()
+        });
+        machine.set_global("rules", Constant::List(vec![]));
+        let mut session = Session::with_machine(machine);
+
+        let read_only = |errors: Vec<PiccoloError>| {
+            assert_eq!(errors.len(), 1);
+            matches!(
+                errors[0].kind(),
+                ErrorKind::Runtime(RuntimeError::ReadOnlyGlobal { .. })
+            )
+        };
+        assert!(read_only(session.eval("rules = []").unwrap_err()));
+        assert!(read_only(session.eval("rules =: []").unwrap_err()));
+        assert!(read_only(session.eval("undeclared =: 1").unwrap_err()));
+        session.eval("rules.push(1)\nprint(rules)").unwrap();
+        assert_eq!(
+            session.machine().get_global("rules"),
+            Some(Constant::List(vec![Constant::Integer(1)]))
+        );
+
+        // methods of types that aren't allowed don't exist
+        assert!(session.eval("retn \"abc\".len()").is_err());
+        assert_eq!(
+            session.eval("retn rules.len()").unwrap(),
+            Constant::Integer(1)
+        );
+
+        // the host still decides
+        session.machine().set_global("rules", Constant::Integer(2));
+        session
+            .machine()
+            .register_fn("double", 1, |_, args| match args[0] {
+                crate::Value::Integer(v) => Ok(crate::Value::Integer(v * 2)),
+                _ => Ok(crate::Value::Nil),
+            });
+        assert_eq!(
+            session.eval("retn double(rules)").unwrap(),
+            Constant::Integer(4)
+        );
+
+        let mut session = Session::with_machine(Machine::with_config(MachineConfig {
+            policy: Policy::deny_all(),
+            ..MachineConfig::default()
+        }));
+        assert!(session.eval("print(1)").is_err());
+    }
+
+    #[test]
+    fn foreign_methods() {
+        use crate::compiler::emitter::{compile_ast, Emitter};
+        use crate::runtime::{op::Opcode, verify::verify};
+        use crate::{parse, ErrorKind, Machine, RuntimeError, Scanner};
+
+        let src = "data P do\n  fn get() do\n    retn 1\n  end\nend\np =: P()\nretn p.get()";
+        let ast = parse(&mut Scanner::new(src)).unwrap();
+        let mut emitter = Emitter::new();
+        compile_ast(&mut emitter, &ast).unwrap();
+        let mut chunk = emitter.into_chunk();
+
+        // replace the function that becomes the method with a bool
+        let method = chunk
+            .data
+            .iter()
+            .position(|op| *op == Opcode::Method as u8)
+            .unwrap();
+        chunk.data[method - 3..method].copy_from_slice(&[
+            Opcode::True as u8,
+            Opcode::Nil as u8,
+            Opcode::Pop as u8,
+        ]);
+        verify(&chunk).unwrap();
+
+        let error = Machine::new().interpret(&chunk).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Runtime(RuntimeError::IncorrectType { .. })
+        ));
+    }
+}
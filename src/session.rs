@@ -0,0 +1,147 @@
+//! Contains `Session`, which runs many Piccolo sources against one [`Machine`].
+//!
+//! [`Machine`]: ../runtime/vm/struct.Machine.html
+
+use crate::compiler::{ast, emitter};
+use crate::{parse, Constant, Emitter, Machine, PiccoloError, Scanner};
+
+/// A [`Machine`] along with everything needed to compile Piccolo source for it.
+///
+/// Global variables defined by one call to [`eval`] are available to later calls,
+/// which makes a `Session` suitable for a REPL or for a host that runs several
+/// scripts that share state.
+///
+/// ```rust
+/// # use piccolo::prelude::*;
+/// # fn main() -> Result<(), Vec<PiccoloError>> {
+/// let mut session = Session::new();
+/// session.eval("x =: 1")?;
+/// assert_eq!(session.eval("retn x + 1")?, Constant::Integer(2));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Machine`]: ../runtime/vm/struct.Machine.html
+/// [`eval`]: #method.eval
+pub struct Session {
+    machine: Machine,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+impl Session {
+    /// Create a new session with a fresh [`Machine`].
+    ///
+    /// [`Machine`]: ../runtime/vm/struct.Machine.html
+    pub fn new() -> Self {
+        Session::with_machine(Machine::new())
+    }
+
+    /// Create a session that runs code on an existing [`Machine`], for example one
+    /// with native functions already registered.
+    ///
+    /// [`Machine`]: ../runtime/vm/struct.Machine.html
+    pub fn with_machine(machine: Machine) -> Self {
+        Session { machine }
+    }
+
+    /// Get the [`Machine`] of the session, for registering functions or accessing
+    /// globals. Any globals defined on the machine can be used by later calls to
+    /// [`eval`].
+    ///
+    /// [`Machine`]: ../runtime/vm/struct.Machine.html
+    /// [`eval`]: #method.eval
+    pub fn machine(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Compile and run a Piccolo source, returning its result.
+    pub fn eval(&mut self, src: &str) -> Result<Constant, Vec<PiccoloError>> {
+        let mut scanner = Scanner::new(src);
+        debug!("parse");
+        let ast = parse(&mut scanner)?;
+        debug!("ast\n{}", ast::print_ast(&ast));
+        debug!("compile");
+
+        // the compiler needs to know about every global defined so far
+        let mut emitter = Emitter::new();
+        for name in self.machine.global_names() {
+            emitter.declare_global(name);
+        }
+        emitter::compile_ast(&mut emitter, &ast)?;
+        let chunk = emitter.into_chunk();
+
+        debug!("chunk\n{}", chunk.disassemble(""));
+        debug!("interpret");
+        Ok(self.machine.interpret(&chunk)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Session;
+    use crate::Constant;
+
+    #[test]
+    fn persistent_globals() {
+        let mut session = Session::new();
+        session.eval("x =: 1").unwrap();
+        session.eval("fn inc() do\n x += 1\nend").unwrap();
+        session.eval("inc()\ninc()").unwrap();
+        assert_eq!(session.eval("retn x").unwrap(), Constant::Integer(3));
+        assert_eq!(
+            session.machine().get_global("x"),
+            Some(Constant::Integer(3))
+        );
+    }
+
+    #[test]
+    fn errors_keep_session() {
+        let mut session = Session::new();
+        session.eval("x =: 1").unwrap();
+        assert!(session.eval("y").is_err());
+        assert!(session.eval("x = x / nil").is_err());
+        assert_eq!(session.eval("retn x").unwrap(), Constant::Integer(1));
+    }
+
+    #[test]
+    fn errors_close_upvalues() {
+        let mut session = Session::new();
+        session
+            .eval(
+                "g =: nil\n\
+                 fn mk() do\n  x =: 1\n  fn f() do\n    retn x\n  end\n  g = f\n  \
+                   y =: x + nil\nend",
+            )
+            .unwrap();
+        assert!(session.eval("mk()").is_err());
+
+        // the closure that escaped keeps the variable the failed run captured
+        assert_eq!(session.eval("retn g()").unwrap(), Constant::Integer(1));
+        assert_eq!(
+            session.eval("y =: [0]\nretn g()").unwrap(),
+            Constant::Integer(1)
+        );
+    }
+
+    #[test]
+    fn host_globals() {
+        let mut session = Session::new();
+        session
+            .machine()
+            .set_global("name", Constant::String("world".into()));
+        session.machine().register_fn("twice", 1, |heap, args| {
+            let s = heap.fmt(&args[0]);
+            Ok(heap.alloc_string(&format!("{}{}", s, s)))
+        });
+        assert_eq!(
+            session.eval("retn twice(name)").unwrap(),
+            Constant::String("worldworld".into())
+        );
+        assert_eq!(session.eval("retn print").unwrap(), Constant::Nil);
+    }
+}